@@ -1,4 +1,5 @@
 use errors::AppError;
+use fairings::{Compression, Cors, Csrf, SecurityHeaders};
 use rocket::{get, http::CookieJar, routes};
 use routes::{
     delete, done, find_all, find_by_id, find_by_pattern, find_ongoing, insert, undo, update,
@@ -50,6 +51,10 @@ async fn main() {
 
     rocket::build()
         .manage(db_pool)
+        .attach(Cors)
+        .attach(SecurityHeaders)
+        .attach(Compression)
+        .attach(Csrf)
         .mount(
             "/",
             routes![
@@ -0,0 +1,326 @@
+use std::{
+    io::{self, Cursor, Write},
+    sync::Arc,
+};
+
+use flate2::write::GzEncoder;
+use log::error;
+use rocket::{
+    fairing::{self, Fairing, Info, Kind},
+    http::{ContentType, Status},
+    serde::Deserialize,
+    tokio, Build, Request, Response, Rocket,
+};
+
+/// Codings the fairing can compress with, named like in `Accept-Encoding`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Encoding {
+    Br,
+    Zstd,
+    Gzip,
+}
+
+/// How hard to compress, mapped to the levels of each encoding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Level {
+    Fastest,
+    #[default]
+    Default,
+    Best,
+}
+
+impl Encoding {
+    fn name(&self) -> &'static str {
+        match self {
+            Encoding::Br => "br",
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    fn compress(&self, level: Level, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Br => {
+                let quality = match level {
+                    Level::Fastest => 1,
+                    Level::Default => 5,
+                    Level::Best => 11,
+                };
+
+                let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, quality, 22);
+                writer.write_all(bytes)?;
+                Ok(writer.into_inner())
+            }
+            Encoding::Zstd => {
+                let level = match level {
+                    Level::Fastest => 1,
+                    Level::Default => 3,
+                    Level::Best => 19,
+                };
+
+                zstd::bulk::compress(bytes, level)
+            }
+            Encoding::Gzip => {
+                let level = match level {
+                    Level::Fastest => flate2::Compression::fast(),
+                    Level::Default => flate2::Compression::default(),
+                    Level::Best => flate2::Compression::best(),
+                };
+
+                let mut encoder = GzEncoder::new(Vec::new(), level);
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// The `[<profile>.compression]` section of `Rocket.toml`.
+#[derive(Debug, Clone, Deserialize)]
+struct CompressionConfig {
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+    /// Bodies smaller than this (bytes) aren't worth the trouble.
+    #[serde(default = "default_min_size")]
+    min_size: usize,
+    /// Bodies bigger than this (bytes) are left streaming, compressing holds the whole body in
+    /// memory (a big file would be read at once).
+    #[serde(default = "default_max_size")]
+    max_size: usize,
+    #[serde(default)]
+    level: Level,
+    /// Preferred first, when the client accepts several with the same `q`.
+    #[serde(default = "default_encodings")]
+    encodings: Vec<Encoding>,
+    /// Media types worth compressing, `text/*` matches any subtype and `application/*+json` any
+    /// subtype with that suffix.
+    #[serde(default = "default_content_types")]
+    content_types: Vec<String>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_min_size() -> usize {
+    1024
+}
+
+fn default_max_size() -> usize {
+    1024 * 1024
+}
+
+fn default_encodings() -> Vec<Encoding> {
+    vec![Encoding::Br, Encoding::Zstd, Encoding::Gzip]
+}
+
+fn default_content_types() -> Vec<String> {
+    [
+        "text/*",
+        "application/json",
+        "application/*+json",
+        "application/javascript",
+        "application/xml",
+        "application/*+xml",
+        "application/yaml",
+    ]
+    .iter()
+    .map(|content_type| content_type.to_string())
+    .collect()
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            min_size: default_min_size(),
+            max_size: default_max_size(),
+            level: Level::default(),
+            encodings: default_encodings(),
+            content_types: default_content_types(),
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// NOTE(alex): Server-sent events are never compressed, even when `text/*` is listed, they must
+    /// reach the client as soon as they're written.
+    fn is_compressible(&self, content_type: &ContentType) -> bool {
+        let top = content_type.top().as_str().to_ascii_lowercase();
+        let sub = content_type.sub().as_str().to_ascii_lowercase();
+
+        if top == "text" && sub == "event-stream" {
+            return false;
+        }
+
+        self.content_types.iter().any(|pattern| {
+            let (pattern_top, pattern_sub) = match pattern.split_once('/') {
+                Some(parts) => parts,
+                None => return false,
+            };
+
+            pattern_top.eq_ignore_ascii_case(&top)
+                && match pattern_sub.strip_prefix('*') {
+                    Some(suffix) => sub.ends_with(&suffix.to_ascii_lowercase()),
+                    None => pattern_sub.eq_ignore_ascii_case(&sub),
+                }
+        })
+    }
+
+    /// The encoding with the highest `q` in `accept_encoding` (`*` stands for any), ties go to the
+    /// one that comes first in `encodings`.
+    fn negotiate(&self, accept_encoding: &str) -> Option<Encoding> {
+        let codings = accept_encoding
+            .split(',')
+            .filter_map(|coding| {
+                let mut parts = coding.split(';').map(|part| part.trim());
+                let name = parts.next()?.to_ascii_lowercase();
+                let q = match parts.find_map(|param| param.strip_prefix("q=")) {
+                    Some(q) => q.parse::<f32>().ok()?,
+                    None => 1.0,
+                };
+
+                Some((name, q))
+            })
+            .collect::<Vec<_>>();
+
+        let q_of = |encoding: &Encoding| {
+            let named = codings.iter().find(|(name, _)| name == encoding.name());
+            let any = codings.iter().find(|(name, _)| name == "*");
+
+            named.or(any).map_or(0.0, |(_, q)| *q)
+        };
+
+        let mut best = None;
+        for encoding in &self.encodings {
+            let q = q_of(encoding);
+            if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((*encoding, q));
+            }
+        }
+
+        best.map(|(encoding, _)| encoding)
+    }
+}
+
+/// Compresses response bodies with the best `Accept-Encoding` of the client (`br`, `zstd` or
+/// `gzip`), reads its config from `[<profile>.compression]` (everything has a default).
+///
+/// Only sized bodies of `min_size` to `max_size` bytes, with one of the `content_types`, are
+/// compressed. Streams (like server-sent events), partial content, and responses that already
+/// have a `Content-Encoding` (precompressed assets) are sent untouched.
+///
+/// NOTE(alex): Attach it after the fairings that look at the response body (`Idempotency`), so
+/// they see the uncompressed one. `ETag`s are left alone, they're the version of a task, not a
+/// hash of the bytes.
+pub struct Compression;
+
+#[rocket::async_trait]
+impl Fairing for Compression {
+    fn info(&self) -> Info {
+        Info {
+            name: "Compression",
+            kind: Kind::Ignite | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        if rocket.figment().find_value("compression").is_err() {
+            return Ok(rocket.manage(CompressionConfig::default()));
+        }
+
+        match rocket
+            .figment()
+            .extract_inner::<CompressionConfig>("compression")
+        {
+            Ok(config) => Ok(rocket.manage(config)),
+            Err(fail) => {
+                error!("Invalid `compression` configuration: {}", fail);
+                Err(rocket)
+            }
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let config = match req.rocket().state::<CompressionConfig>() {
+            Some(config) if config.enabled => config,
+            _ => return,
+        };
+
+        let untouchable = res.status() == Status::PartialContent
+            || res.headers().contains("Content-Encoding")
+            || res
+                .headers()
+                .get("Cache-Control")
+                .any(|value| value.contains("no-transform"));
+
+        let compressible = res
+            .content_type()
+            .is_some_and(|content_type| config.is_compressible(&content_type));
+
+        if untouchable || !compressible {
+            return;
+        }
+
+        let varies = res
+            .headers()
+            .get("Vary")
+            .flat_map(|value| value.split(','))
+            .any(|name| name.trim().eq_ignore_ascii_case("Accept-Encoding"));
+        if !varies {
+            res.adjoin_raw_header("Vary", "Accept-Encoding");
+        }
+
+        let encoding = match req
+            .headers()
+            .get_one("Accept-Encoding")
+            .and_then(|accept_encoding| config.negotiate(accept_encoding))
+        {
+            Some(encoding) => encoding,
+            None => return,
+        };
+
+        // NOTE(alex): Streamed bodies have no size, and are left streaming.
+        match res.body_mut().size().await {
+            Some(size) if size >= config.min_size && size <= config.max_size => (),
+            _ => return,
+        }
+
+        let bytes = match res.body_mut().to_bytes().await {
+            Ok(bytes) => bytes,
+            Err(fail) => {
+                error!("Failed to read the body to compress: {}", fail);
+                return;
+            }
+        };
+
+        // NOTE(alex): The body is drained now, it goes back when the compression doesn't pay off,
+        // or fails.
+        let bytes = Arc::new(bytes);
+        let input = Arc::clone(&bytes);
+        let level = config.level;
+        let compressed =
+            tokio::task::spawn_blocking(move || encoding.compress(level, &input)).await;
+
+        match compressed {
+            Ok(Ok(compressed)) if compressed.len() < bytes.len() => {
+                res.set_raw_header("Content-Encoding", encoding.name());
+                res.set_sized_body(compressed.len(), Cursor::new(compressed));
+            }
+            result => {
+                match result {
+                    Ok(Err(fail)) => {
+                        error!("Failed to compress with `{}`: {}", encoding.name(), fail)
+                    }
+                    Err(fail) => error!("Compression task failed: {}", fail),
+                    Ok(Ok(_)) => (),
+                }
+
+                let bytes = Arc::try_unwrap(bytes).unwrap_or_else(|bytes| bytes.to_vec());
+                res.set_sized_body(bytes.len(), Cursor::new(bytes));
+            }
+        }
+    }
+}
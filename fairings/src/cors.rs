@@ -0,0 +1,193 @@
+use std::io::Cursor;
+
+use log::error;
+use rocket::{
+    fairing::{self, Fairing, Info, Kind},
+    http::{Header, Method, Status},
+    serde::Deserialize,
+    Build, Orbit, Request, Response, Rocket,
+};
+
+/// The `[global.cors]` section of `Rocket.toml`.
+#[derive(Debug, Clone, Deserialize)]
+struct CorsConfig {
+    /// Origins allowed to call the API, `"*"` allows any origin (but never with credentials).
+    #[serde(default)]
+    allowed_origins: AllowedOrigins,
+    #[serde(default = "default_allowed_methods")]
+    allowed_methods: Vec<String>,
+    #[serde(default = "default_allowed_headers")]
+    allowed_headers: Vec<String>,
+    #[serde(default)]
+    expose_headers: Vec<String>,
+    #[serde(default)]
+    allow_credentials: bool,
+    /// How long (seconds) browsers may cache the preflight response.
+    #[serde(default = "default_max_age")]
+    max_age: u64,
+}
+
+fn default_allowed_methods() -> Vec<String> {
+    ["GET", "POST", "PUT", "PATCH", "DELETE"]
+        .iter()
+        .map(|method| method.to_string())
+        .collect()
+}
+
+fn default_allowed_headers() -> Vec<String> {
+    ["Content-Type", "Authorization"]
+        .iter()
+        .map(|header| header.to_string())
+        .collect()
+}
+
+fn default_max_age() -> u64 {
+    3600
+}
+
+impl CorsConfig {
+    fn any_origin(&self) -> bool {
+        self.allowed_origins.0.iter().any(|allowed| allowed == "*")
+    }
+
+    fn allows_method(&self, method: &str) -> bool {
+        self.allowed_methods
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(method))
+    }
+
+    fn allows_headers(&self, headers: &str) -> bool {
+        headers
+            .split(',')
+            .map(|header| header.trim())
+            .filter(|header| !header.is_empty())
+            .all(|header| {
+                self.allowed_headers
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(header))
+            })
+    }
+}
+
+/// Cross-origin resource sharing for browser clients, reads its config from `[global.cors]`.
+///
+/// NOTE(alex): There are no `OPTIONS` routes, so a preflight request ends up as a `404`, the
+/// fairing turns that into a `204` with the `Access-Control-Allow-*` headers when the origin,
+/// method and headers are all allowed.
+pub struct Cors;
+
+/// The `allowed_origins` of `[global.cors]`, for servers running next to Rocket (a websocket
+/// listener checks the `Origin` of its handshakes against them).
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct AllowedOrigins(Vec<String>);
+
+impl AllowedOrigins {
+    pub fn allows(&self, origin: &str) -> bool {
+        self.0
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+    }
+}
+
+impl Cors {
+    /// No other origin is allowed without a `[global.cors]` section.
+    pub fn allowed_origins(rocket: &Rocket<Orbit>) -> AllowedOrigins {
+        match rocket.state::<CorsConfig>() {
+            Some(config) => config.allowed_origins.clone(),
+            None => AllowedOrigins::default(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info {
+            name: "CORS",
+            kind: Kind::Ignite | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        if rocket.figment().find_value("cors").is_err() {
+            return Ok(rocket);
+        }
+
+        match rocket.figment().extract_inner::<CorsConfig>("cors") {
+            // NOTE(alex): Reflecting any `Origin` with credentials would let every site make
+            // authenticated calls, so the wildcard only ever goes out as a literal `*`.
+            Ok(config) if config.any_origin() && config.allow_credentials => {
+                error!("CORS `allowed_origins = [\"*\"]` can't be used with `allow_credentials`!");
+                Err(rocket)
+            }
+            Ok(config) => Ok(rocket.manage(config)),
+            Err(fail) => {
+                error!("Invalid `cors` configuration: {}", fail);
+                Err(rocket)
+            }
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let config = match req.rocket().state::<CorsConfig>() {
+            Some(config) => config,
+            None => return,
+        };
+
+        res.adjoin_header(Header::new("Vary", "Origin"));
+
+        let origin = match req.headers().get_one("Origin") {
+            Some(origin) if config.allowed_origins.allows(origin) => origin,
+            _ => return,
+        };
+
+        let requested_method = req.headers().get_one("Access-Control-Request-Method");
+        if req.method() == Method::Options && requested_method.is_some() {
+            let requested_headers = req
+                .headers()
+                .get_one("Access-Control-Request-Headers")
+                .unwrap_or_default();
+
+            if !requested_method.is_some_and(|method| config.allows_method(method))
+                || !config.allows_headers(requested_headers)
+            {
+                return;
+            }
+
+            if res.status() == Status::NotFound {
+                res.set_status(Status::NoContent);
+                res.set_sized_body(0, Cursor::new(""));
+                res.remove_header("Content-Type");
+            }
+
+            res.set_header(Header::new(
+                "Access-Control-Allow-Methods",
+                config.allowed_methods.join(", "),
+            ));
+            res.set_header(Header::new(
+                "Access-Control-Allow-Headers",
+                config.allowed_headers.join(", "),
+            ));
+            res.set_header(Header::new(
+                "Access-Control-Max-Age",
+                config.max_age.to_string(),
+            ));
+        } else if !config.expose_headers.is_empty() {
+            res.set_header(Header::new(
+                "Access-Control-Expose-Headers",
+                config.expose_headers.join(", "),
+            ));
+        }
+
+        let allowed_origin = if config.any_origin() { "*" } else { origin };
+        res.set_header(Header::new(
+            "Access-Control-Allow-Origin",
+            allowed_origin.to_string(),
+        ));
+
+        if config.allow_credentials {
+            res.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
+        }
+    }
+}
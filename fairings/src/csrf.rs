@@ -0,0 +1,192 @@
+use rocket::{
+    fairing::{self, Fairing, Info, Kind},
+    http::{uri::Origin, ContentType, Cookie, Method, SameSite, Status},
+    request::{FromRequest, Outcome},
+    response::status::Custom,
+    route::{self, Handler, Route},
+    serde::json::serde_json,
+    Build, Data, Request, Rocket,
+};
+
+const COOKIE_NAME: &str = "csrf_token";
+const HEADER_NAME: &str = "X-CSRF-Token";
+const FORM_FIELD: &str = "_csrf";
+
+/// How much of a form body we look at when searching for the `_csrf` field, keep the field first.
+const FORM_PEEK_LIMIT: usize = 512;
+
+/// Where `on_request` sends the requests it rejects, followed by the `CsrfFailure`.
+const REJECTED_PATH: &str = "/csrf/rejected";
+
+#[derive(Debug, Clone, Copy)]
+enum CsrfFailure {
+    MissingCookie,
+    MissingToken,
+    Mismatch,
+}
+
+impl CsrfFailure {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CsrfFailure::MissingCookie => "missing-cookie",
+            CsrfFailure::MissingToken => "missing-token",
+            CsrfFailure::Mismatch => "mismatch",
+        }
+    }
+}
+
+/// Double-submit cookie CSRF protection.
+///
+/// Every response carries a `csrf_token` cookie (readable by scripts), and unsafe requests
+/// (POST, PUT, PATCH, DELETE) must echo it back in the `X-CSRF-Token` header, or in a `_csrf`
+/// form field (see [`CsrfToken`] for pages rendering forms). Requests with an `Authorization`
+/// header are exempt, a cross-site attacker can't make the browser attach one.
+///
+/// NOTE(alex): Fairings can't respond by themselves, so a rejected request is rewritten into a
+/// `GET` to `/csrf/rejected/<reason>` (mounted on ignite), the original handler never runs.
+pub struct Csrf;
+
+#[rocket::async_trait]
+impl Fairing for Csrf {
+    fn info(&self) -> Info {
+        Info {
+            name: "CSRF",
+            kind: Kind::Ignite | Kind::Request,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let path = format!("{}/<reason>", REJECTED_PATH);
+
+        Ok(rocket.mount("/", vec![Route::new(Method::Get, &path, Rejected)]))
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, data: &mut Data<'_>) {
+        let cookie_token = req
+            .cookies()
+            .get(COOKIE_NAME)
+            .map(|crumb| crumb.value().to_string());
+
+        if cookie_token.is_none() {
+            let mut cookie = Cookie::new(COOKIE_NAME, new_token());
+            cookie.set_path("/");
+            cookie.set_same_site(SameSite::Strict);
+            req.cookies().add(cookie);
+        }
+
+        let is_unsafe = matches!(
+            req.method(),
+            Method::Post | Method::Put | Method::Patch | Method::Delete
+        );
+
+        if !is_unsafe || req.headers().contains("Authorization") {
+            return;
+        }
+
+        let submitted_token = match req.headers().get_one(HEADER_NAME) {
+            Some(token) => Some(token.to_string()),
+            None if req.content_type() == Some(&ContentType::Form) => {
+                form_token(data.peek(FORM_PEEK_LIMIT).await)
+            }
+            None => None,
+        };
+
+        let failure = match (cookie_token, submitted_token) {
+            (None, _) => Some(CsrfFailure::MissingCookie),
+            (_, None) => Some(CsrfFailure::MissingToken),
+            (Some(expected), Some(submitted)) if !constant_time_eq(&expected, &submitted) => {
+                Some(CsrfFailure::Mismatch)
+            }
+            _ => None,
+        };
+
+        if let Some(failure) = failure {
+            let uri = format!("{}/{}", REJECTED_PATH, failure.as_str());
+            if let Ok(uri) = Origin::parse_owned(uri) {
+                req.set_method(Method::Get);
+                req.set_uri(uri);
+            }
+        }
+    }
+}
+
+/// The `csrf_token` of the request (the one `Csrf` just issued on a first visit), for the
+/// `_csrf` field of the forms a page renders.
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CsrfToken {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match req.cookies().get_pending(COOKIE_NAME) {
+            Some(cookie) => Outcome::Success(CsrfToken(cookie.value().to_string())),
+            None => Outcome::Failure((Status::InternalServerError, ())),
+        }
+    }
+}
+
+/// Answers the requests `on_request` rejected.
+#[derive(Clone)]
+struct Rejected;
+
+#[rocket::async_trait]
+impl Handler for Rejected {
+    async fn handle<'r>(&self, req: &'r Request<'_>, _data: Data<'r>) -> route::Outcome<'r> {
+        let detail = match req.uri().path().segments().last() {
+            Some("missing-cookie") => {
+                "The `csrf_token` cookie was not sent, fetch any page to receive one."
+            }
+            Some("missing-token") => {
+                "Unsafe requests must send the `csrf_token` cookie value back in the \
+                `X-CSRF-Token` header, or in a `_csrf` form field."
+            }
+            _ => "The submitted CSRF token does not match the `csrf_token` cookie.",
+        };
+
+        let problem = serde_json::json!({
+            "type": "about:blank",
+            "title": "CSRF token missing or invalid",
+            "status": Status::Forbidden.code,
+            "detail": detail,
+        });
+
+        route::Outcome::from(
+            req,
+            Custom(
+                Status::Forbidden,
+                (
+                    ContentType::new("application", "problem+json"),
+                    problem.to_string(),
+                ),
+            ),
+        )
+    }
+}
+
+fn new_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn form_token(body: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(body)
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(FORM_FIELD)?.strip_prefix('='))
+        .map(|token| token.to_string())
+}
+
+fn constant_time_eq(expected: &str, submitted: &str) -> bool {
+    expected.len() == submitted.len()
+        && expected
+            .bytes()
+            .zip(submitted.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
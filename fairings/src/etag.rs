@@ -0,0 +1,124 @@
+use std::time::SystemTime;
+
+use rocket::{
+    http::{Header, Status},
+    request::{FromRequest, Outcome},
+    response::{self, Responder},
+    Request, Response,
+};
+
+/// A resource served with a strong `ETag`, like a task and its `version`.
+pub trait ETagged {
+    fn etag(&self) -> String;
+}
+
+/// The `If-Match` precondition of a request didn't hold, a `412 Precondition Failed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreconditionFailed;
+
+/// Whether `etag` is in the list of a `If-Match`/`If-None-Match` header, weak tags only ever match
+/// when `weak` is set.
+fn list_contains(header: &str, etag: &str, weak: bool) -> bool {
+    header.split(',').map(|tag| tag.trim()).any(|tag| {
+        let tag = match (tag.strip_prefix("W/"), weak) {
+            (Some(tag), true) => tag,
+            (Some(_), false) => return false,
+            (None, _) => tag,
+        };
+
+        tag == "*" || tag == etag
+    })
+}
+
+/// The `If-Match`, `If-None-Match` and `If-Modified-Since` headers of a request.
+pub struct Preconditions {
+    if_match: Option<String>,
+    if_none_match: Option<String>,
+    if_modified_since: Option<SystemTime>,
+}
+
+impl Preconditions {
+    /// Checks `If-Match` against the `current` resource (`None` when there's none), `Ok(false)`
+    /// when the request has no `If-Match`, the write is unconditional.
+    pub fn check_match<T: ETagged>(&self, current: Option<&T>) -> Result<bool, PreconditionFailed> {
+        let if_match = match &self.if_match {
+            Some(if_match) => if_match,
+            None => return Ok(false),
+        };
+
+        match current {
+            Some(current) if list_contains(if_match, &current.etag(), false) => Ok(true),
+            _ => Err(PreconditionFailed),
+        }
+    }
+
+    pub fn is_not_modified<T: ETagged>(&self, current: &T) -> bool {
+        self.if_none_match
+            .as_deref()
+            .is_some_and(|if_none_match| list_contains(if_none_match, &current.etag(), true))
+    }
+
+    /// For responses that aren't a single resource (feeds), `If-Modified-Since` is only looked at
+    /// without an `If-None-Match` (RFC 7232, 6).
+    pub fn is_unchanged(&self, etag: &str, last_modified: Option<SystemTime>) -> bool {
+        match (&self.if_none_match, self.if_modified_since, last_modified) {
+            (Some(if_none_match), _, _) => list_contains(if_none_match, etag, true),
+            (None, Some(since), Some(last_modified)) => last_modified <= since,
+            _ => false,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Preconditions {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let header = |name| req.headers().get_one(name).map(|value| value.to_string());
+
+        Outcome::Success(Preconditions {
+            if_match: header("If-Match"),
+            if_none_match: header("If-None-Match"),
+            if_modified_since: header("If-Modified-Since")
+                .and_then(|date| httpdate::parse_http_date(&date).ok()),
+        })
+    }
+}
+
+/// Sets the `ETag` of a resource on the inner response, without an inner response it's a `304 Not
+/// Modified`.
+pub struct Tagged<R> {
+    etag: Option<String>,
+    response: Option<R>,
+}
+
+impl<R> Tagged<R> {
+    pub fn new(etag: Option<String>, response: R) -> Self {
+        Self {
+            etag,
+            response: Some(response),
+        }
+    }
+
+    pub fn not_modified<T: ETagged>(current: &T) -> Self {
+        Self {
+            etag: Some(current.etag()),
+            response: None,
+        }
+    }
+}
+
+impl<'r, R: Responder<'r, 'static>> Responder<'r, 'static> for Tagged<R> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = match self.response {
+            Some(response) => response.respond_to(req)?,
+            None => Response::build().status(Status::NotModified).finalize(),
+        };
+
+        if let Some(etag) = self.etag {
+            response.set_header(Header::new("ETag", etag));
+        }
+
+        Ok(response)
+    }
+}
@@ -0,0 +1,469 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    io::{self, Cursor},
+    ops::Deref,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use log::error;
+use rocket::{
+    data::{self, ByteUnit, FromData, ToByteUnit},
+    fairing::{self, Fairing, Info, Kind},
+    http::{ContentType, Status},
+    outcome::Outcome,
+    request::{self, FromRequest},
+    serde::{
+        json::{serde_json, Json},
+        Deserialize, DeserializeOwned,
+    },
+    tokio, Build, Data, Orbit, Request, Response, Rocket,
+};
+
+/// The `[global.idempotency]` section of `Rocket.toml`.
+#[derive(Debug, Clone, Deserialize)]
+struct IdempotencyConfig {
+    /// How long (seconds) a response is replayed for repeats of its key.
+    #[serde(default = "default_ttl")]
+    ttl: u64,
+    /// How often (seconds) expired keys are swept from memory.
+    #[serde(default = "default_eviction_interval")]
+    eviction_interval: u64,
+    /// Most keys kept in memory, the oldest one makes room for a new key.
+    #[serde(default = "default_max_keys")]
+    max_keys: usize,
+}
+
+fn default_ttl() -> u64 {
+    86400
+}
+
+fn default_eviction_interval() -> u64 {
+    60
+}
+
+fn default_max_keys() -> usize {
+    10_000
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self {
+            ttl: default_ttl(),
+            eviction_interval: default_eviction_interval(),
+            max_keys: default_max_keys(),
+        }
+    }
+}
+
+/// A response as it was first sent, replayed for repeats of its key.
+#[derive(Debug, Clone)]
+struct StoredResponse {
+    status: Status,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+#[derive(Debug)]
+enum Entry {
+    /// The first request with this key is still being handled.
+    InFlight { fingerprint: u64, created: Instant },
+    Completed {
+        fingerprint: u64,
+        created: Instant,
+        response: StoredResponse,
+    },
+}
+
+impl Entry {
+    fn fingerprint(&self) -> u64 {
+        match self {
+            Entry::InFlight { fingerprint, .. } | Entry::Completed { fingerprint, .. } => {
+                *fingerprint
+            }
+        }
+    }
+
+    fn created(&self) -> Instant {
+        match self {
+            Entry::InFlight { created, .. } | Entry::Completed { created, .. } => *created,
+        }
+    }
+
+    fn is_expired(&self, ttl: Duration) -> bool {
+        self.created().elapsed() >= ttl
+    }
+}
+
+/// Keys are scoped by client (API token, or IP address), so clients can't replay each other.
+type Slot = (String, String);
+type Entries = Arc<Mutex<HashMap<Slot, Entry>>>;
+
+/// The keys of the `Idempotency` fairing and its configuration, managed for the guards.
+struct Keys {
+    config: IdempotencyConfig,
+    entries: Entries,
+}
+
+/// What the guards decided, cached in the request for `on_response`.
+#[derive(Debug, Clone)]
+enum Decision {
+    Skip,
+    Fresh { slot: Slot, fingerprint: u64 },
+    Replay(StoredResponse),
+    Rejected(Status, &'static str),
+}
+
+/// Replays the stored response for requests that repeat an `Idempotency-Key` (within `ttl`), so
+/// retried `POST`s don't create duplicates. Reusing a key for a different request is a `422`, and
+/// repeating it while the first request is still running is a `409`.
+///
+/// Routes take part with the [`Idempotent`] data guard (or the [`IdempotencyKey`] request guard
+/// when they have no body), they compare requests by method, URI and the whole body. This fairing
+/// only stores the responses and sends them back for the requests the guards turned away.
+///
+/// NOTE(alex): Attach it before the fairings that add response headers (CORS, rate limits), so
+/// those aren't stored and replayed.
+#[derive(Default)]
+pub struct Idempotency {
+    entries: Entries,
+}
+
+fn client_scope(req: &Request<'_>) -> String {
+    match req.headers().get_one("Authorization") {
+        Some(authorization) => format!("auth:{}", authorization),
+        None => match req.client_ip() {
+            Some(ip) => format!("ip:{}", ip),
+            None => "unknown".to_string(),
+        },
+    }
+}
+
+/// Responses worth replaying: successes, and client errors a repeat can't change. Conflicts,
+/// failed preconditions and rate limits (like server errors) are worth retrying.
+fn is_final(status: Status) -> bool {
+    match status.code {
+        200..=299 => true,
+        408 | 409 | 412 | 423 | 425 | 428 | 429 => false,
+        400..=499 => true,
+        _ => false,
+    }
+}
+
+fn fingerprint(req: &Request<'_>, body: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    req.method().as_str().hash(&mut hasher);
+    req.uri().to_string().hash(&mut hasher);
+    body.hash(&mut hasher);
+
+    hasher.finish()
+}
+
+impl Keys {
+    /// Claims the key of `req` for it, unless a request with the same key came first.
+    fn decide(&self, req: &Request<'_>, key: &str, body: &[u8]) -> Decision {
+        let fingerprint = fingerprint(req, body);
+        let slot = (client_scope(req), key.to_string());
+        let ttl = Duration::from_secs(self.config.ttl);
+
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(&slot) {
+            Some(entry) if entry.is_expired(ttl) => (),
+            Some(entry) if entry.fingerprint() != fingerprint => {
+                return Decision::Rejected(
+                    Status::UnprocessableEntity,
+                    "`Idempotency-Key` was already used for a different request!",
+                )
+            }
+            Some(Entry::InFlight { .. }) => {
+                return Decision::Rejected(
+                    Status::Conflict,
+                    "A request with this `Idempotency-Key` is still being processed!",
+                )
+            }
+            Some(Entry::Completed { response, .. }) => return Decision::Replay(response.clone()),
+            None => (),
+        }
+
+        if entries.len() >= self.config.max_keys && !entries.contains_key(&slot) {
+            entries.retain(|_, entry| !entry.is_expired(ttl));
+
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.created())
+                .map(|(slot, _)| slot.clone());
+
+            if let (Some(oldest), true) = (oldest, entries.len() >= self.config.max_keys) {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(
+            slot.clone(),
+            Entry::InFlight {
+                fingerprint,
+                created: Instant::now(),
+            },
+        );
+
+        Decision::Fresh { slot, fingerprint }
+    }
+}
+
+/// Why a keyed request didn't reach its route.
+#[derive(Debug)]
+pub enum IdempotencyError<E> {
+    /// The body is over its limit.
+    TooLarge,
+    IO(io::Error),
+    /// Answered with the stored response of its key.
+    Replayed,
+    /// The key is in use by another request.
+    Rejected(&'static str),
+    /// The guard of the body failed.
+    Body(E),
+}
+
+/// Runs the key of `req` (if it has one) past the `Idempotency` fairing, a request it turned away
+/// fails with the status it's answered with.
+fn claim<E>(req: &Request<'_>, body: &[u8]) -> Result<(), (Status, IdempotencyError<E>)> {
+    let key = match req.headers().get_one("Idempotency-Key") {
+        Some(key) if !key.trim().is_empty() => key.trim(),
+        _ => return Ok(()),
+    };
+
+    let keys = match req.rocket().state::<Keys>() {
+        Some(keys) => keys,
+        None => return Ok(()),
+    };
+
+    let decision = keys.decide(req, key, body);
+    let failure = match &decision {
+        Decision::Skip | Decision::Fresh { .. } => Ok(()),
+        Decision::Replay(response) => Err((response.status, IdempotencyError::Replayed)),
+        Decision::Rejected(status, reason) => Err((*status, IdempotencyError::Rejected(reason))),
+    };
+    req.local_cache(|| decision);
+
+    failure
+}
+
+/// A data guard that can be built from the body `Idempotent` already read (and fingerprinted).
+pub trait FromBody<'r>: Sized {
+    type Error: Send + std::fmt::Debug;
+
+    /// The most of the body that's read, from the `limits` of `Rocket.toml`.
+    fn limit(req: &'r Request<'_>) -> ByteUnit;
+
+    fn from_body(req: &'r Request<'_>, body: Vec<u8>) -> Result<Self, (Status, Self::Error)>;
+}
+
+impl<'r, T: DeserializeOwned> FromBody<'r> for Json<T> {
+    type Error = serde_json::Error;
+
+    fn limit(req: &'r Request<'_>) -> ByteUnit {
+        req.limits().get("json").unwrap_or(1.mebibytes())
+    }
+
+    fn from_body(_req: &'r Request<'_>, body: Vec<u8>) -> Result<Self, (Status, Self::Error)> {
+        serde_json::from_slice(&body)
+            .map(Json)
+            .map_err(|fail| (Status::UnprocessableEntity, fail))
+    }
+}
+
+/// Data guard of the routes that honour an `Idempotency-Key`: the whole body (within its limit)
+/// is read and compared with the first request of the key, before `T` is built from it.
+#[derive(Debug)]
+pub struct Idempotent<T>(pub T);
+
+impl<T> Idempotent<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Idempotent<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, T: FromBody<'r>> FromData<'r> for Idempotent<T> {
+    type Error = IdempotencyError<T::Error>;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let body = match data.open(T::limit(req)).into_bytes().await {
+            Ok(body) if body.is_complete() => body.into_inner(),
+            Ok(_) => {
+                return Outcome::Failure((Status::PayloadTooLarge, IdempotencyError::TooLarge))
+            }
+            Err(fail) => {
+                return Outcome::Failure((Status::InternalServerError, IdempotencyError::IO(fail)))
+            }
+        };
+
+        if let Err(failure) = claim(req, &body) {
+            return Outcome::Failure(failure);
+        }
+
+        match T::from_body(req, body) {
+            Ok(guard) => Outcome::Success(Idempotent(guard)),
+            Err((status, fail)) => Outcome::Failure((status, IdempotencyError::Body(fail))),
+        }
+    }
+}
+
+/// Request guard of the routes without a body that honour an `Idempotency-Key`, see
+/// [`Idempotent`].
+#[derive(Debug)]
+pub struct IdempotencyKey;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IdempotencyKey {
+    type Error = IdempotencyError<()>;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match claim(req, &[]) {
+            Ok(()) => Outcome::Success(IdempotencyKey),
+            Err(failure) => Outcome::Failure(failure),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Idempotency {
+    fn info(&self) -> Info {
+        Info {
+            name: "Idempotency",
+            kind: Kind::Ignite | Kind::Liftoff | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let config = match rocket.figment().find_value("idempotency") {
+            Err(_) => IdempotencyConfig::default(),
+            Ok(_) => match rocket
+                .figment()
+                .extract_inner::<IdempotencyConfig>("idempotency")
+            {
+                Ok(config) => config,
+                Err(fail) => {
+                    error!("Invalid `idempotency` configuration: {}", fail);
+                    return Err(rocket);
+                }
+            },
+        };
+
+        Ok(rocket.manage(Keys {
+            config,
+            entries: self.entries.clone(),
+        }))
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let config = match rocket.state::<Keys>() {
+            Some(keys) => keys.config.clone(),
+            None => return,
+        };
+
+        let entries = self.entries.clone();
+        tokio::spawn(async move {
+            let ttl = Duration::from_secs(config.ttl);
+            let mut interval = tokio::time::interval(Duration::from_secs(config.eviction_interval));
+
+            loop {
+                interval.tick().await;
+                entries
+                    .lock()
+                    .unwrap()
+                    .retain(|_, entry| !entry.is_expired(ttl));
+            }
+        });
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let (slot, fingerprint) = match req.local_cache(|| Decision::Skip) {
+            Decision::Skip => return,
+            Decision::Replay(response) => {
+                *res = response.clone().into_response();
+                return;
+            }
+            Decision::Rejected(status, reason) => {
+                *res = Response::build()
+                    .status(*status)
+                    .header(ContentType::Plain)
+                    .sized_body(reason.len(), Cursor::new(*reason))
+                    .finalize();
+                return;
+            }
+            Decision::Fresh { slot, fingerprint } => (slot.clone(), *fingerprint),
+        };
+
+        let body = if is_final(res.status()) {
+            res.body_mut().to_bytes().await.ok()
+        } else {
+            None
+        };
+
+        // NOTE(alex): The key may have been evicted (or taken over, once expired) while the request
+        // ran, then there's nothing of this request left to complete.
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(&slot) {
+            Some(Entry::InFlight {
+                fingerprint: in_flight,
+                ..
+            }) if *in_flight == fingerprint => (),
+            _ => return,
+        }
+
+        let body = match body {
+            Some(body) => body,
+            None => {
+                entries.remove(&slot);
+                return;
+            }
+        };
+
+        res.set_sized_body(body.len(), Cursor::new(body.clone()));
+
+        let response = StoredResponse {
+            status: res.status(),
+            headers: res
+                .headers()
+                .iter()
+                .map(|header| (header.name().to_string(), header.value().to_string()))
+                .collect(),
+            body,
+        };
+
+        entries.insert(
+            slot,
+            Entry::Completed {
+                fingerprint,
+                created: Instant::now(),
+                response,
+            },
+        );
+    }
+}
+
+impl StoredResponse {
+    fn into_response(self) -> Response<'static> {
+        let mut response = Response::build();
+        response.status(self.status);
+
+        for (name, value) in self.headers {
+            response.raw_header_adjoin(name, value);
+        }
+
+        response
+            .raw_header("Idempotent-Replayed", "true")
+            .sized_body(self.body.len(), Cursor::new(self.body))
+            .finalize()
+    }
+}
@@ -0,0 +1,18 @@
+//! Fairings shared by the task services, configured through each service's `Rocket.toml`, and
+//! the guards and responders of their conditional requests and patches.
+
+mod compression;
+mod cors;
+mod csrf;
+mod etag;
+mod idempotency;
+mod patch;
+mod security_headers;
+
+pub use compression::Compression;
+pub use cors::{AllowedOrigins, Cors};
+pub use csrf::{Csrf, CsrfToken};
+pub use etag::{ETagged, PreconditionFailed, Preconditions, Tagged};
+pub use idempotency::{FromBody, Idempotency, IdempotencyError, IdempotencyKey, Idempotent};
+pub use patch::{PatchError, TaskPatch};
+pub use security_headers::SecurityHeaders;
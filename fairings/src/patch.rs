@@ -0,0 +1,103 @@
+use std::io;
+
+use rocket::{
+    data::{self, ByteUnit, Data, FromData, Outcome, ToByteUnit},
+    http::Status,
+    serde::{
+        de::DeserializeOwned,
+        json::{serde_json, Value},
+        Serialize,
+    },
+    Request,
+};
+
+use crate::idempotency::FromBody;
+
+/// Why a [`TaskPatch`] couldn't be read, or applied.
+#[derive(Debug)]
+pub enum PatchError {
+    /// Neither `application/merge-patch+json` nor `application/json-patch+json`.
+    UnsupportedPatch,
+    /// Over the `json` limit.
+    TooLarge,
+    IO(io::Error),
+    /// Not a patch, or the patched document isn't valid anymore.
+    Invalid(String),
+}
+
+/// Body of `PATCH /tasks/<id>`, picked by its `Content-Type`.
+pub enum TaskPatch {
+    /// `application/merge-patch+json` (RFC 7396).
+    Merge(Value),
+    /// `application/json-patch+json` (RFC 6902).
+    Json(json_patch::Patch),
+}
+
+impl TaskPatch {
+    /// Patches the JSON form of `document`, and checks the result still is a `T`.
+    ///
+    /// NOTE(alex): Either every operation applies, or the document is left as it was.
+    pub fn apply<T: Serialize + DeserializeOwned>(&self, document: T) -> Result<T, PatchError> {
+        let mut value =
+            serde_json::to_value(document).map_err(|fail| PatchError::Invalid(fail.to_string()))?;
+
+        match self {
+            TaskPatch::Merge(merge) => json_patch::merge(&mut value, merge),
+            TaskPatch::Json(patch) => json_patch::patch(&mut value, patch)
+                .map_err(|fail| PatchError::Invalid(fail.to_string()))?,
+        }
+
+        serde_json::from_value(value).map_err(|fail| PatchError::Invalid(fail.to_string()))
+    }
+}
+
+impl<'r> FromBody<'r> for TaskPatch {
+    type Error = PatchError;
+
+    fn limit(req: &'r Request<'_>) -> ByteUnit {
+        req.limits().get("json").unwrap_or(1.mebibytes())
+    }
+
+    fn from_body(req: &'r Request<'_>, body: Vec<u8>) -> Result<Self, (Status, Self::Error)> {
+        let is_content_type = |sub: &str| {
+            req.content_type().is_some_and(|content_type| {
+                content_type.top() == "application" && content_type.sub() == sub
+            })
+        };
+
+        let task_patch = if is_content_type("merge-patch+json") {
+            serde_json::from_slice(&body).map(TaskPatch::Merge)
+        } else if is_content_type("json-patch+json") {
+            serde_json::from_slice(&body).map(TaskPatch::Json)
+        } else {
+            return Err((Status::UnsupportedMediaType, PatchError::UnsupportedPatch));
+        };
+
+        task_patch.map_err(|fail| {
+            (
+                Status::UnprocessableEntity,
+                PatchError::Invalid(fail.to_string()),
+            )
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for TaskPatch {
+    type Error = PatchError;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let body = match data.open(Self::limit(req)).into_bytes().await {
+            Ok(body) if body.is_complete() => body.into_inner(),
+            Ok(_) => return Outcome::Failure((Status::PayloadTooLarge, PatchError::TooLarge)),
+            Err(fail) => {
+                return Outcome::Failure((Status::InternalServerError, PatchError::IO(fail)));
+            }
+        };
+
+        match Self::from_body(req, body) {
+            Ok(task_patch) => Outcome::Success(task_patch),
+            Err(failure) => Outcome::Failure(failure),
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::sync::atomic::Ordering;
+
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::AppError,
+    models::{InsertTask, Task, UpdateTask},
+    AppData,
+};
+
+/// Keeps a single batch from holding the `task_list` lock for too long.
+const MAX_OPERATIONS: usize = 1000;
+
+/// One command of a batch, `version` makes it conditional like `If-Match` does for the single
+/// routes.
+///
+/// ```json
+/// { "op": "delete", "id": 1, "version": 2 }
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub(crate) enum Operation {
+    Insert(InsertTask),
+    Update {
+        #[serde(flatten)]
+        update_task: UpdateTask,
+        #[serde(default)]
+        version: Option<u64>,
+    },
+    Delete {
+        id: u64,
+        #[serde(default)]
+        version: Option<u64>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum BatchMode {
+    /// The first failure rolls back the whole batch.
+    AllOrNothing,
+    /// Failed operations change nothing, the rest is kept.
+    ContinueOnError,
+}
+
+impl Default for BatchMode {
+    fn default() -> Self {
+        BatchMode::AllOrNothing
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Batch {
+    #[serde(default)]
+    pub(crate) mode: BatchMode,
+    pub(crate) operations: Vec<Operation>,
+}
+
+/// Result of each `Operation`, in the same order they were sent.
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub(crate) enum OperationResult {
+    /// `task` is the task after the operation (before it, for deletes).
+    Applied {
+        task: Task,
+    },
+    Failed {
+        error: String,
+    },
+    /// Succeeded, but a later failure rolled back the batch (`all_or_nothing`).
+    RolledBack,
+    /// Never ran, an earlier failure aborted the batch (`all_or_nothing`).
+    Skipped,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct BatchResult {
+    pub(crate) committed: bool,
+    pub(crate) results: Vec<OperationResult>,
+}
+
+impl Batch {
+    pub(crate) fn validate(&self) -> Result<(), AppError> {
+        if self.operations.len() > MAX_OPERATIONS {
+            return Err(AppError::InvalidTask(format!(
+                "a batch can't have more than `{}` operations",
+                MAX_OPERATIONS
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Runs every operation under a single `task_list` lock, on a copy of the list that only
+    /// replaces the real one when the batch is committed.
+    pub(crate) fn execute(self, app_data: &AppData) -> Result<BatchResult, AppError> {
+        let mut task_list = app_data
+            .task_list
+            .try_lock()
+            .map_err(|_| AppError::Internal)?;
+
+        let mut working = task_list.clone();
+        let mut results = Vec::with_capacity(self.operations.len());
+        let mut failed = false;
+
+        for operation in self.operations {
+            if failed {
+                results.push(OperationResult::Skipped);
+                continue;
+            }
+
+            match operation.apply(app_data, &mut working) {
+                Ok(task) => results.push(OperationResult::Applied { task }),
+                Err(fail) => {
+                    results.push(OperationResult::Failed {
+                        error: fail.to_string(),
+                    });
+                    failed = self.mode == BatchMode::AllOrNothing;
+                }
+            }
+        }
+
+        if failed {
+            for result in results.iter_mut() {
+                if let OperationResult::Applied { .. } = result {
+                    *result = OperationResult::RolledBack;
+                }
+            }
+
+            return Ok(BatchResult {
+                committed: false,
+                results,
+            });
+        }
+
+        *task_list = working;
+
+        Ok(BatchResult {
+            committed: true,
+            results,
+        })
+    }
+}
+
+impl Operation {
+    /// NOTE(alex): Checks everything before touching `task_list`, so a failed operation leaves it
+    /// as it was.
+    fn apply(self, app_data: &AppData, task_list: &mut Vec<Task>) -> Result<Task, AppError> {
+        match self {
+            Operation::Insert(insert_task) => {
+                if insert_task.non_empty_title.trim().is_empty() {
+                    return Err(AppError::EmptyTitle);
+                }
+
+                let task = Task {
+                    id: app_data.id_tracker.fetch_add(1, Ordering::Relaxed),
+                    title: insert_task.non_empty_title,
+                    details: insert_task.details,
+                    version: 1,
+                };
+                task_list.push(task.clone());
+
+                Ok(task)
+            }
+            Operation::Update {
+                update_task,
+                version,
+            } => {
+                if update_task.new_title.trim().is_empty() {
+                    return Err(AppError::EmptyTitle);
+                }
+
+                let task = task_list
+                    .iter_mut()
+                    .find(|t| t.id == update_task.id)
+                    .ok_or(AppError::IdNotFound(update_task.id))?;
+                check_version(task, version)?;
+
+                task.title = update_task.new_title;
+                task.details = update_task.details;
+                task.version += 1;
+
+                Ok(task.clone())
+            }
+            Operation::Delete { id, version } => {
+                let index = task_list
+                    .iter()
+                    .position(|t| t.id == id)
+                    .ok_or(AppError::IdNotFound(id))?;
+                check_version(&task_list[index], version)?;
+
+                Ok(task_list.remove(index))
+            }
+        }
+    }
+}
+
+fn check_version(task: &Task, version: Option<u64>) -> Result<(), AppError> {
+    match version {
+        Some(version) if version != task.version => Err(AppError::PreconditionFailed),
+        _ => Ok(()),
+    }
+}
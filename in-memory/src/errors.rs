@@ -1,5 +1,6 @@
 use std::io::Cursor;
 
+use fairings::{PatchError, PreconditionFailed};
 use rocket::{
     http::{ContentType, Status},
     response::Responder,
@@ -16,6 +17,15 @@ pub(crate) enum AppError {
     #[error("`{0}` id not found!")]
     IdNotFound(u64),
 
+    #[error("Task was changed by someone else in the meantime!")]
+    PreconditionFailed,
+
+    #[error("Invalid task: {0}")]
+    InvalidTask(String),
+
+    #[error("Patches must be `application/merge-patch+json` or `application/json-patch+json`!")]
+    UnsupportedPatch,
+
     #[error("Internal server error!")]
     Internal,
 
@@ -23,6 +33,23 @@ pub(crate) enum AppError {
     IO(#[from] std::io::Error),
 }
 
+impl From<PreconditionFailed> for AppError {
+    fn from(_: PreconditionFailed) -> Self {
+        AppError::PreconditionFailed
+    }
+}
+
+impl From<PatchError> for AppError {
+    fn from(fail: PatchError) -> Self {
+        match fail {
+            PatchError::UnsupportedPatch => AppError::UnsupportedPatch,
+            PatchError::TooLarge => AppError::Internal,
+            PatchError::IO(fail) => AppError::IO(fail),
+            PatchError::Invalid(reason) => AppError::InvalidTask(reason),
+        }
+    }
+}
+
 impl<'r> Responder<'r, 'static> for AppError {
     fn respond_to(self, _request: &'r rocket::Request<'_>) -> rocket::response::Result<'static> {
         let status = match self {
@@ -31,6 +58,9 @@ impl<'r> Responder<'r, 'static> for AppError {
             // EmptyTitle, but we never got to this error proper.
             AppError::EmptyTitle => Status::UnprocessableEntity,
             AppError::IdNotFound(_) => Status::NotFound,
+            AppError::PreconditionFailed => Status::PreconditionFailed,
+            AppError::InvalidTask(_) => Status::UnprocessableEntity,
+            AppError::UnsupportedPatch => Status::UnsupportedMediaType,
             AppError::Internal => Status::InternalServerError,
             AppError::IO(_) => Status::InternalServerError,
         };
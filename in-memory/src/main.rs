@@ -1,12 +1,14 @@
 use std::sync::{atomic::AtomicU64, Mutex};
 
+use fairings::{Compression, Cors, Idempotency, SecurityHeaders};
 use models::Task;
 use rocket::{
     get, launch, routes,
     serde::{Deserialize, Serialize},
 };
-use routes::{delete, find_all, find_by_id, insert, update};
+use routes::{batch_tasks, delete, find_all, find_by_id, insert, patch_task, replace_task, update};
 
+mod batch;
 mod errors;
 mod models;
 mod routes;
@@ -31,8 +33,22 @@ fn rocket() -> _ {
             id_tracker: AtomicU64::new(0),
             task_list: Mutex::new(Vec::with_capacity(32)),
         })
+        .attach(Idempotency::default())
+        .attach(Cors)
+        .attach(SecurityHeaders)
+        .attach(Compression)
         .mount(
             "/",
-            routes![index, insert, find_all, find_by_id, delete, update],
+            routes![
+                index,
+                insert,
+                batch_tasks,
+                find_all,
+                find_by_id,
+                delete,
+                update,
+                replace_task,
+                patch_task
+            ],
         )
 }
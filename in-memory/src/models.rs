@@ -1,6 +1,7 @@
 use crate::errors::AppError;
+use fairings::{ETagged, FromBody};
 use rocket::{
-    data::{FromData, Outcome, ToByteUnit},
+    data::{ByteUnit, FromData, Outcome, ToByteUnit},
     http::Status,
     serde::{json::serde_json, Deserialize, Serialize},
 };
@@ -10,6 +11,14 @@ pub(crate) struct Task {
     pub(crate) id: u64,
     pub(crate) title: String,
     pub(crate) details: String,
+    pub(crate) version: u64,
+}
+
+/// Strong `ETag` of a task, its `version` changes on every mutation.
+impl ETagged for Task {
+    fn etag(&self) -> String {
+        format!("\"{}\"", self.version)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -25,6 +34,69 @@ pub(crate) struct UpdateTask {
     pub(crate) details: String,
 }
 
+/// The fields of a `Task` clients can write, `PUT /tasks/<id>` replaces them all, and
+/// `PATCH /tasks/<id>` patches this document.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TaskDocument {
+    pub(crate) title: String,
+    pub(crate) details: String,
+}
+
+impl TaskDocument {
+    pub(crate) fn validate(&self) -> Result<(), AppError> {
+        if self.title.trim().is_empty() {
+            Err(AppError::EmptyTitle)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Task {
+    pub(crate) fn document(&self) -> TaskDocument {
+        TaskDocument {
+            title: self.title.clone(),
+            details: self.details.clone(),
+        }
+    }
+
+    /// Overwrites every writable field, and bumps the `version`.
+    pub(crate) fn replace(&mut self, document: TaskDocument) {
+        self.title = document.title;
+        self.details = document.details;
+        self.version += 1;
+    }
+}
+
+impl<'r> FromBody<'r> for InsertTask {
+    type Error = AppError;
+
+    fn limit(req: &'r rocket::Request<'_>) -> ByteUnit {
+        req.limits().get("insert_task").unwrap_or(512.bytes())
+    }
+
+    fn from_body(
+        _req: &'r rocket::Request<'_>,
+        body: Vec<u8>,
+    ) -> Result<Self, (Status, Self::Error)> {
+        let insert_task: InsertTask = serde_json::from_slice(&body).map_err(|fail| {
+            (
+                Status::UnprocessableEntity,
+                AppError::InvalidTask(fail.to_string()),
+            )
+        })?;
+
+        if insert_task.non_empty_title.trim().is_empty() {
+            // TODO(alex) [high] 2021-06-22: This is where the error is returned from (the actual
+            // response), read the note in errors.rs.
+            return Err((Status::UnprocessableEntity, AppError::EmptyTitle));
+        }
+
+        Ok(insert_task)
+    }
+}
+
 #[rocket::async_trait]
 impl<'r> FromData<'r> for InsertTask {
     type Error = AppError;
@@ -33,23 +105,18 @@ impl<'r> FromData<'r> for InsertTask {
         req: &'r rocket::Request<'_>,
         data: rocket::Data<'r>,
     ) -> rocket::data::Outcome<'r, Self> {
-        let limit = req.limits().get("insert_task").unwrap_or(512.bytes());
-        let as_string = match data.open(limit).into_string().await {
-            Ok(string) if string.is_complete() => string.into_inner(),
+        let body = match data.open(Self::limit(req)).into_bytes().await {
+            Ok(body) if body.is_complete() => body.into_inner(),
             Ok(_) => return Outcome::Failure((Status::PayloadTooLarge, AppError::Internal)),
             Err(fail) => {
                 return Outcome::Failure((Status::InternalServerError, AppError::IO(fail)));
             }
         };
 
-        let insert_task: InsertTask = serde_json::from_str(&as_string).unwrap();
-        if insert_task.non_empty_title.trim().is_empty() {
-            // TODO(alex) [high] 2021-06-22: This is where the error is returned from (the actual
-            // response), read the note in errors.rs.
-            return Outcome::Failure((Status::UnprocessableEntity, AppError::EmptyTitle));
+        match Self::from_body(req, body) {
+            Ok(insert_task) => Outcome::Success(insert_task),
+            Err(failure) => Outcome::Failure(failure),
         }
-
-        Outcome::Success(insert_task)
     }
 }
 
@@ -1,23 +1,26 @@
 use rocket::{
     delete, get,
     http::Status,
-    post, put,
+    patch, post, put,
     response::status::{Created, Custom},
     serde::json::Json,
     State,
 };
 use std::sync::atomic::Ordering;
 
+use fairings::{ETagged, Idempotent, Preconditions, Tagged, TaskPatch};
+
 use crate::{
+    batch::{Batch, BatchResult},
     errors::AppError,
-    models::{InsertTask, Task, UpdateTask},
+    models::{InsertTask, Task, TaskDocument, UpdateTask},
     AppData,
 };
 
 #[post("/tasks", data = "<insert_task>")]
 pub(crate) async fn insert(
     app_data: &State<AppData>,
-    insert_task: InsertTask,
+    insert_task: Idempotent<InsertTask>,
 ) -> Result<Created<Json<Task>>, AppError> {
     if insert_task.non_empty_title.trim().is_empty() {
         Err(AppError::EmptyTitle)
@@ -28,6 +31,7 @@ pub(crate) async fn insert(
             id,
             title: insert_task.non_empty_title.to_owned(),
             details: insert_task.details.to_owned(),
+            version: 1,
         };
 
         let mut task_list = app_data
@@ -42,6 +46,26 @@ pub(crate) async fn insert(
     }
 }
 
+/// NOTE(alex): A batch that wasn't committed (`all_or_nothing` mode) is a `422`, the body still
+/// says which operation failed.
+#[post("/tasks/batch", data = "<batch>")]
+pub(crate) async fn batch_tasks(
+    app_data: &State<AppData>,
+    batch: Idempotent<Json<Batch>>,
+) -> Result<Custom<Json<BatchResult>>, AppError> {
+    let batch = batch.into_inner().into_inner();
+    batch.validate()?;
+
+    let result = batch.execute(app_data)?;
+    let status = if result.committed {
+        Status::Ok
+    } else {
+        Status::UnprocessableEntity
+    };
+
+    Ok(Custom(status, Json(result)))
+}
+
 #[get("/tasks")]
 pub(crate) async fn find_all(app_data: &State<AppData>) -> Result<Json<Vec<Task>>, AppError> {
     let task_list = app_data
@@ -54,7 +78,11 @@ pub(crate) async fn find_all(app_data: &State<AppData>) -> Result<Json<Vec<Task>
 }
 
 #[get("/tasks/<id>")]
-pub(crate) async fn find_by_id(app_data: &State<AppData>, id: u64) -> Result<Json<Task>, AppError> {
+pub(crate) async fn find_by_id(
+    app_data: &State<AppData>,
+    preconditions: Preconditions,
+    id: u64,
+) -> Result<Tagged<Json<Task>>, AppError> {
     let task_list = app_data
         .task_list
         .try_lock()
@@ -66,12 +94,17 @@ pub(crate) async fn find_by_id(app_data: &State<AppData>, id: u64) -> Result<Jso
         .ok_or(AppError::IdNotFound(id))?
         .clone();
 
-    Ok(Json(task))
+    if preconditions.is_not_modified(&task) {
+        Ok(Tagged::not_modified(&task))
+    } else {
+        Ok(Tagged::new(Some(task.etag()), Json(task)))
+    }
 }
 
 #[delete("/tasks/<id>")]
 pub(crate) async fn delete(
     app_data: &State<AppData>,
+    preconditions: Preconditions,
     id: u64,
 ) -> Result<Custom<Json<Task>>, AppError> {
     let mut task_list = app_data
@@ -79,12 +112,14 @@ pub(crate) async fn delete(
         .try_lock()
         .map_err(|_| AppError::Internal)?;
 
-    let (index, _) = task_list
+    let (index, task) = task_list
         .iter()
         .enumerate()
         .find(|(_, t)| t.id == id)
         .ok_or(AppError::IdNotFound(id))?;
 
+    preconditions.check_match(Some(task))?;
+
     let task = task_list.remove(index);
 
     Ok(Custom(Status::Ok, Json(task)))
@@ -93,8 +128,9 @@ pub(crate) async fn delete(
 #[put("/tasks", data = "<update_task>")]
 pub(crate) async fn update(
     app_data: &State<AppData>,
+    preconditions: Preconditions,
     update_task: UpdateTask,
-) -> Result<Created<Json<Task>>, AppError> {
+) -> Result<Tagged<Created<Json<Task>>>, AppError> {
     let mut task_list = app_data
         .task_list
         .try_lock()
@@ -105,8 +141,66 @@ pub(crate) async fn update(
         .find(|t| t.id == update_task.id)
         .ok_or(AppError::IdNotFound(update_task.id))?;
 
+    preconditions.check_match(Some(&*task))?;
+
     task.title = update_task.new_title.to_owned();
     task.details = update_task.details.to_owned();
+    task.version += 1;
+
+    Ok(Tagged::new(
+        Some(task.etag()),
+        Created::new("/tasks").body(Json(task.clone())),
+    ))
+}
+
+/// NOTE(alex): Unlike `PUT /tasks`, every writable field must be sent.
+#[put("/tasks/<id>", data = "<document>")]
+pub(crate) async fn replace_task(
+    app_data: &State<AppData>,
+    preconditions: Preconditions,
+    id: u64,
+    document: Json<TaskDocument>,
+) -> Result<Tagged<Json<Task>>, AppError> {
+    document.validate()?;
+
+    let mut task_list = app_data
+        .task_list
+        .try_lock()
+        .map_err(|_| AppError::Internal)?;
+
+    let task = task_list
+        .iter_mut()
+        .find(|t| t.id == id)
+        .ok_or(AppError::IdNotFound(id))?;
+
+    preconditions.check_match(Some(&*task))?;
+    task.replace(document.into_inner());
+
+    Ok(Tagged::new(Some(task.etag()), Json(task.clone())))
+}
+
+#[patch("/tasks/<id>", data = "<task_patch>")]
+pub(crate) async fn patch_task(
+    app_data: &State<AppData>,
+    preconditions: Preconditions,
+    id: u64,
+    task_patch: Idempotent<TaskPatch>,
+) -> Result<Tagged<Json<Task>>, AppError> {
+    let mut task_list = app_data
+        .task_list
+        .try_lock()
+        .map_err(|_| AppError::Internal)?;
+
+    let task = task_list
+        .iter_mut()
+        .find(|t| t.id == id)
+        .ok_or(AppError::IdNotFound(id))?;
+
+    preconditions.check_match(Some(&*task))?;
+
+    let document = task_patch.apply(task.document())?;
+    document.validate()?;
+    task.replace(document);
 
-    Ok(Created::new("/tasks").body(Json(task.clone())))
+    Ok(Tagged::new(Some(task.etag()), Json(task.clone())))
 }
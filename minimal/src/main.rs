@@ -1,3 +1,4 @@
+use fairings::{Compression, Cors, SecurityHeaders};
 use rocket::{get, launch, routes};
 
 const WELCOME: &'static str = include_str!("./../strings/welcome.txt");
@@ -9,5 +10,9 @@ async fn index() -> &'static str {
 
 #[launch]
 fn rocket() -> _ {
-    rocket::build().mount("/", routes![index])
+    rocket::build()
+        .attach(Cors)
+        .attach(SecurityHeaders)
+        .attach(Compression)
+        .mount("/", routes![index])
 }
@@ -9,6 +9,7 @@ const DATABASE_FILENAME: &'static str = concat!(env!("CARGO_PKG_NAME"), ".test.d
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=queries/create_database.sql");
+    println!("cargo:rerun-if-changed=proto/tasks.proto");
 
     let out_dir = env::var("OUT_DIR").unwrap();
     let database_file = &format!("{}/{}", out_dir, DATABASE_FILENAME);
@@ -19,4 +20,11 @@ fn main() {
     println!("cargo:rustc-env=DATABASE_FILE={}", database_file);
     println!("cargo:rustc-env=DATABASE_URL={}", database_url);
     println!("cargo:rustc-env=RUST_LOG=info");
+
+    // NOTE(alex): `protoc` is vendored, so building doesn't need it installed.
+    env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().unwrap());
+    // NOTE(alex): The client is only used by the tests of `grpc.rs`.
+    tonic_build::configure()
+        .compile(&["proto/tasks.proto"], &["proto"])
+        .unwrap();
 }
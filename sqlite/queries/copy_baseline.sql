@@ -0,0 +1,18 @@
+insert into Task (id, workspace_id, title, details)
+select BaselineTask.id,
+    Workspace.id,
+    BaselineTask.title,
+    coalesce(BaselineTask.details, '')
+from BaselineTask
+    join Workspace on Workspace.slug = 'default'
+order by BaselineTask.id;
+
+insert
+    or ignore into Done (task_id)
+select BaselineDone.task_id
+from BaselineDone
+    join Task on Task.id = BaselineDone.task_id;
+
+drop table BaselineDone;
+
+drop table BaselineTask;
\ No newline at end of file
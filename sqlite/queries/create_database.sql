@@ -1,25 +1,319 @@
-drop view if exists OngoingTask;
-drop table if exists Task;
-drop table if exists Done;
+-- NOTE(alex): `feed_token` only opens the read-only calendar feed (`/tasks.ics`), so it can sit in
+-- a calendar app's URL without handing out the API `token`. Both are random, `sqlite token {slug}`
+-- prints the API one.
+create table if not exists Workspace (
+    id integer primary key,
+    slug text not null unique,
+    token text not null unique default (lower(hex(randomblob(16)))),
+    feed_token text not null unique default (lower(hex(randomblob(16)))),
+    max_tasks integer not null default 1000
+);
 
-create table if not exists Task (
+create table if not exists TaskList (
+    id integer primary key,
+    workspace_id int not null,
+    name text not null,
+    unique (workspace_id, name),
+    unique (id, workspace_id),
+    foreign key (workspace_id) references Workspace(id) on delete cascade
+);
+
+create table if not exists Tag (
     id integer primary key,
+    workspace_id int not null,
+    name text not null,
+    unique (workspace_id, name),
+    unique (id, workspace_id),
+    foreign key (workspace_id) references Workspace(id) on delete cascade
+);
+
+-- NOTE(alex): `autoincrement` so the id of a deleted (tombstoned) `Task` is never handed out
+-- again, `seq` is the change sequence of its last mutation, and `version` counts its mutations
+-- (the `ETag`), both are set by the triggers below.
+create table if not exists Task (
+    id integer primary key autoincrement,
+    workspace_id int not null,
+    list_id int,
     title text not null,
-    details text
+    details text,
+    -- NOTE(alex): `YYYY-MM-DD`, or `YYYY-MM-DDTHH:MM:SS` (`Z` suffix when in UTC), `priority` is
+    -- a letter from `A` (highest) to `Z`, and `created_on` a `YYYY-MM-DD`. Only set by imports
+    -- (calendar, todo.txt, Markdown) for now.
+    due text,
+    priority text,
+    created_on text,
+    seq integer not null default 0,
+    version integer not null default 1,
+    unique (id, workspace_id),
+    foreign key (workspace_id) references Workspace(id) on delete cascade,
+    foreign key (list_id, workspace_id) references TaskList(id, workspace_id)
 );
 
 create table if not exists Done (
+    task_id int not null unique,
+    completed_at text not null default (strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    foreign key (task_id) references Task(id) on delete cascade
+);
+
+-- NOTE(alex): The composite keys make it impossible to tag a `Task` with a `Tag` that belongs to
+-- another `Workspace`.
+create table if not exists TaskTag (
+    task_id int not null,
+    tag_id int not null,
+    workspace_id int not null,
+    primary key (task_id, tag_id),
+    foreign key (task_id, workspace_id) references Task(id, workspace_id) on delete cascade,
+    foreign key (tag_id, workspace_id) references Tag(id, workspace_id) on delete cascade
+);
+
+-- NOTE(alex): A single counter shared by every `Workspace`, bumped on every mutation of a `Task`
+-- (or its `Done` row), this is what the sync tokens are made of.
+create table if not exists ChangeSequence (value integer not null);
+
+insert into ChangeSequence (value)
+select 0
+where not exists (
+        select value
+        from ChangeSequence
+    );
+
+-- NOTE(alex): Where an imported `Task` came from (a Taskwarrior `uuid`, a Trello card `id`), so
+-- importing the same export again updates it instead of inserting a copy.
+create table if not exists ImportedSource (
+    workspace_id int not null,
+    source text not null,
+    source_id text not null,
+    task_id int not null unique,
+    primary key (workspace_id, source, source_id),
+    foreign key (workspace_id) references Workspace(id) on delete cascade,
+    foreign key (task_id) references Task(id) on delete cascade
+);
+
+-- NOTE(alex): A row per creation, edit, completion and undo of a `Task`, written by the triggers
+-- below (with the `title` it had then) for the Atom feed, `seq` is its `ChangeSequence` value.
+-- Deleting the `Task` deletes its changes.
+create table if not exists TaskChange (
+    seq integer primary key,
+    workspace_id int not null,
     task_id int not null,
+    kind text not null,
+    title text not null,
+    changed_at text not null default (strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    foreign key (workspace_id) references Workspace(id) on delete cascade,
     foreign key (task_id) references Task(id) on delete cascade
 );
 
+create index if not exists TaskChangeByWorkspace on TaskChange (workspace_id, seq);
+
+create table if not exists Tombstone (
+    task_id int primary key,
+    workspace_id int not null,
+    seq integer not null,
+    foreign key (workspace_id) references Workspace(id) on delete cascade
+);
+
+-- NOTE(alex): Tasks whose writes the change triggers skip (no new `version`, `seq` or
+-- `TaskChange`), a row only lives inside the transaction that adds it: details re-sealed with a new
+-- key (`encryption.rs`), or an imported task that gets its fields and tags right after its insert.
+create table if not exists Untracked (
+    workspace_id int not null,
+    task_id int primary key
+);
+
+create trigger if not exists TaskInserted
+after
+insert on Task begin
+update ChangeSequence
+set value = value + 1;
+update Task
+set seq = (
+        select value
+        from ChangeSequence
+    )
+where Task.id = new.id;
+insert into TaskChange (seq, workspace_id, task_id, kind, title)
+select ChangeSequence.value,
+    Task.workspace_id,
+    Task.id,
+    'created',
+    Task.title
+from ChangeSequence,
+    Task
+where Task.id = new.id;
+end;
+
+-- NOTE(alex): Sealed `details` are bound to the id of their `Task`, so an insert writes them as
+-- `null` and seals them right after (same transaction), that first write isn't an update. The app
+-- never writes a `null` otherwise. Dropped first so an existing database gets this version.
+drop trigger if exists TaskUpdated;
+
+create trigger TaskUpdated
+after
+update of list_id,
+    title,
+    details,
+    due,
+    priority,
+    created_on on Task
+    when old.details is not null
+    and not exists (
+        select task_id
+        from Untracked
+        where Untracked.task_id = new.id
+    ) begin
+update ChangeSequence
+set value = value + 1;
+update Task
+set seq = (
+        select value
+        from ChangeSequence
+    ),
+    version = version + 1
+where Task.id = new.id;
+insert into TaskChange (seq, workspace_id, task_id, kind, title)
+select ChangeSequence.value,
+    Task.workspace_id,
+    Task.id,
+    'updated',
+    Task.title
+from ChangeSequence,
+    Task
+where Task.id = new.id;
+end;
+
+create trigger if not exists TaskDeleted
+after delete on Task begin
+update ChangeSequence
+set value = value + 1;
+insert into Tombstone (task_id, workspace_id, seq)
+select old.id,
+    old.workspace_id,
+    ChangeSequence.value
+from ChangeSequence;
+end;
+
+-- NOTE(alex): (Un)tagging is an update of the `Task`, except when the `Task` itself is being
+-- deleted (the cascade).
+create trigger if not exists TaskTagged
+after
+insert on TaskTag
+    when not exists (
+        select task_id
+        from Untracked
+        where Untracked.task_id = new.task_id
+    ) begin
+update ChangeSequence
+set value = value + 1;
+update Task
+set seq = (
+        select value
+        from ChangeSequence
+    ),
+    version = version + 1
+where Task.id = new.task_id;
+insert into TaskChange (seq, workspace_id, task_id, kind, title)
+select ChangeSequence.value,
+    Task.workspace_id,
+    Task.id,
+    'updated',
+    Task.title
+from ChangeSequence,
+    Task
+where Task.id = new.task_id;
+end;
+
+create trigger if not exists TaskUntagged
+after delete on TaskTag
+    when exists (
+        select id
+        from Task
+        where Task.id = old.task_id
+    )
+    and not exists (
+        select task_id
+        from Untracked
+        where Untracked.task_id = old.task_id
+    ) begin
+update ChangeSequence
+set value = value + 1;
+update Task
+set seq = (
+        select value
+        from ChangeSequence
+    ),
+    version = version + 1
+where Task.id = old.task_id;
+insert into TaskChange (seq, workspace_id, task_id, kind, title)
+select ChangeSequence.value,
+    Task.workspace_id,
+    Task.id,
+    'updated',
+    Task.title
+from ChangeSequence,
+    Task
+where Task.id = old.task_id;
+end;
+
+create trigger if not exists TaskDone
+after
+insert on Done begin
+update ChangeSequence
+set value = value + 1;
+update Task
+set seq = (
+        select value
+        from ChangeSequence
+    ),
+    version = version + 1
+where Task.id = new.task_id;
+insert into TaskChange (seq, workspace_id, task_id, kind, title)
+select ChangeSequence.value,
+    Task.workspace_id,
+    Task.id,
+    'done',
+    Task.title
+from ChangeSequence,
+    Task
+where Task.id = new.task_id;
+end;
+
+create trigger if not exists TaskUndone
+after delete on Done begin
+update ChangeSequence
+set value = value + 1;
+update Task
+set seq = (
+        select value
+        from ChangeSequence
+    ),
+    version = version + 1
+where Task.id = old.task_id;
+insert into TaskChange (seq, workspace_id, task_id, kind, title)
+select ChangeSequence.value,
+    Task.workspace_id,
+    Task.id,
+    'undone',
+    Task.title
+from ChangeSequence,
+    Task
+where Task.id = old.task_id;
+end;
+
 create view if not exists OngoingTask as
 select Task.id,
+    Task.workspace_id,
+    Task.list_id,
     Task.title,
-    Task.details
+    Task.details,
+    Task.version
 from Task
 where
     Task.id not in (
         select task_id
         from Done
-    );
\ No newline at end of file
+    );
+
+insert
+    or ignore into Workspace (slug, max_tasks)
+values ('default', 1000),
+    ('demo', 10);
@@ -0,0 +1,3 @@
+select ChangeSequence.value
+from ChangeSequence
+    join Workspace on Workspace.id = $1
\ No newline at end of file
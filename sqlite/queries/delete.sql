@@ -1,2 +1,7 @@
 delete from Task
-where Task.id = $1
\ No newline at end of file
+where Task.workspace_id = $1
+    and Task.id = $2
+    and (
+        $3 is null
+        or Task.version = $3
+    )
\ No newline at end of file
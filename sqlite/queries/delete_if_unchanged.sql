@@ -0,0 +1,4 @@
+delete from Task
+where Task.workspace_id = $1
+    and Task.id = $2
+    and Task.seq = $3
\ No newline at end of file
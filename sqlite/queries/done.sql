@@ -1,2 +1,15 @@
+-- NOTE(alex): A task that is already done is skipped, instead of getting a second `Done` row.
 insert into Done (task_id)
-values ($1)
\ No newline at end of file
+select Task.id
+from Task
+where Task.workspace_id = $1
+    and Task.id = $2
+    and (
+        $3 is null
+        or Task.version = $3
+    )
+    and not exists (
+        select 1
+        from Done
+        where Done.task_id = Task.id
+    )
\ No newline at end of file
@@ -0,0 +1,4 @@
+select TaskList.id,
+    TaskList.name
+from TaskList
+where TaskList.workspace_id = $1;
\ No newline at end of file
@@ -0,0 +1,4 @@
+select Tag.id,
+    Tag.name
+from Tag
+where Tag.workspace_id = $1;
\ No newline at end of file
@@ -1,3 +1,4 @@
 select *
 from Task
-where Task.id = $1
\ No newline at end of file
+where Task.workspace_id = $1
+    and Task.id = $2
\ No newline at end of file
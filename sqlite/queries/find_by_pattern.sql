@@ -1,4 +1,4 @@
 select *
 from Task
-where
-    Task.title like $1;
\ No newline at end of file
+where Task.workspace_id = $1
+    and Task.title like $2;
\ No newline at end of file
@@ -0,0 +1,12 @@
+select TaskChange.task_id,
+    TaskChange.seq,
+    TaskChange.kind,
+    TaskChange.title,
+    TaskChange.changed_at
+from TaskChange
+where TaskChange.workspace_id = $1
+    and TaskChange.task_id in (
+        select value
+        from json_each($2)
+    )
+order by TaskChange.seq
\ No newline at end of file
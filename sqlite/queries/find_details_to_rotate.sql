@@ -0,0 +1,9 @@
+select Task.id,
+    Task.workspace_id,
+    Task.details
+from Task
+where Task.details is not null
+    and Task.details not like $1
+    and Task.id > $2
+order by Task.id
+limit $3;
\ No newline at end of file
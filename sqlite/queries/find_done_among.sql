@@ -0,0 +1,8 @@
+select Done.task_id
+from Done
+    join Task on Task.id = Done.task_id
+where Task.workspace_id = $1
+    and Done.task_id in (
+        select value
+        from json_each($2)
+    )
\ No newline at end of file
@@ -0,0 +1,22 @@
+-- NOTE(alex): Tags are joined with the unit separator, tag names may contain commas.
+select Task.id,
+    Task.title,
+    Task.details,
+    Task.due,
+    Task.priority,
+    Task.created_on,
+    Task.version,
+    TaskList.name as list,
+    Done.completed_at,
+    (
+        select group_concat(Tag.name, char(31))
+        from TaskTag
+            join Tag on Tag.id = TaskTag.tag_id
+        where TaskTag.task_id = Task.id
+    ) as tags,
+    strftime('%Y%m%dT%H%M%SZ', 'now') as stamp
+from Task
+    left join TaskList on TaskList.id = Task.list_id
+    left join Done on Done.task_id = Task.id
+where Task.workspace_id = $1
+order by Task.id
\ No newline at end of file
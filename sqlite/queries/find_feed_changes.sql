@@ -0,0 +1,32 @@
+-- NOTE(alex): `$2` (list id) and `$3` (tag id) are optional filters, `$4` the number of changes.
+select TaskChange.seq,
+    TaskChange.task_id,
+    TaskChange.kind,
+    TaskChange.title,
+    TaskChange.changed_at,
+    cast(strftime('%s', TaskChange.changed_at) as integer) as changed_epoch,
+    Task.details,
+    (
+        select group_concat(Tag.name, char(31))
+        from TaskTag
+            join Tag on Tag.id = TaskTag.tag_id
+        where TaskTag.task_id = Task.id
+    ) as tags
+from TaskChange
+    join Task on Task.id = TaskChange.task_id
+where TaskChange.workspace_id = $1
+    and (
+        $2 is null
+        or Task.list_id = $2
+    )
+    and (
+        $3 is null
+        or exists (
+            select 1
+            from TaskTag
+            where TaskTag.task_id = Task.id
+                and TaskTag.tag_id = $3
+        )
+    )
+order by TaskChange.seq desc
+limit $4
\ No newline at end of file
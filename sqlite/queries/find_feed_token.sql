@@ -0,0 +1,3 @@
+select Workspace.feed_token
+from Workspace
+where Workspace.id = $1
\ No newline at end of file
@@ -0,0 +1,14 @@
+select Task.id,
+    Task.list_id,
+    Task.title,
+    Task.details,
+    Task.due,
+    Task.priority,
+    Task.created_on,
+    Done.task_id is not null as done
+from ImportedSource
+    join Task on Task.id = ImportedSource.task_id
+    left join Done on Done.task_id = Task.id
+where ImportedSource.workspace_id = $1
+    and ImportedSource.source = $2
+    and ImportedSource.source_id = $3
\ No newline at end of file
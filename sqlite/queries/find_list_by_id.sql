@@ -0,0 +1,5 @@
+select TaskList.id,
+    TaskList.name
+from TaskList
+where TaskList.workspace_id = $1
+    and TaskList.id = $2
\ No newline at end of file
@@ -0,0 +1,4 @@
+select TaskList.id
+from TaskList
+where TaskList.workspace_id = $1
+    and TaskList.name = $2
\ No newline at end of file
@@ -0,0 +1,29 @@
+-- NOTE(alex): Every filter is optional (`null`), `$4` is a JSON array of ids.
+select Task.id,
+    Task.id in (
+        select task_id
+        from Done
+    ) as done
+from Task
+where Task.workspace_id = $1
+    and (
+        $2 is null
+        or Task.title like $2
+    )
+    and (
+        $3 is null
+        or (
+            Task.id in (
+                select task_id
+                from Done
+            )
+        ) = $3
+    )
+    and (
+        $4 is null
+        or Task.id in (
+            select value
+            from json_each($4)
+        )
+    )
+order by Task.id
\ No newline at end of file
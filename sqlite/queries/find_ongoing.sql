@@ -1,2 +1,3 @@
 select *
-from OngoingTask;
\ No newline at end of file
+from OngoingTask
+where OngoingTask.workspace_id = $1;
\ No newline at end of file
@@ -0,0 +1,14 @@
+select Task.id,
+    Task.list_id,
+    Task.title,
+    Task.details,
+    Task.seq,
+    Task.version,
+    exists (
+        select 1
+        from Done
+        where Done.task_id = Task.id
+    ) as done
+from Task
+where Task.workspace_id = $1
+    and Task.id = $2
\ No newline at end of file
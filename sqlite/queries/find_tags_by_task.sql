@@ -0,0 +1,7 @@
+select Tag.id,
+    Tag.name
+from Tag
+    join TaskTag on TaskTag.tag_id = Tag.id
+where TaskTag.workspace_id = $1
+    and TaskTag.task_id = $2
+order by Tag.name
\ No newline at end of file
@@ -0,0 +1,11 @@
+select TaskTag.task_id,
+    Tag.id,
+    Tag.name
+from Tag
+    join TaskTag on TaskTag.tag_id = Tag.id
+where TaskTag.workspace_id = $1
+    and TaskTag.task_id in (
+        select value
+        from json_each($2)
+    )
+order by Tag.name
\ No newline at end of file
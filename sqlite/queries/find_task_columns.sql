@@ -0,0 +1,2 @@
+select name
+from pragma_table_info('Task')
\ No newline at end of file
@@ -0,0 +1,4 @@
+select Tombstone.task_id
+from Tombstone
+where Tombstone.workspace_id = $1
+    and Tombstone.task_id = $2
\ No newline at end of file
@@ -0,0 +1,7 @@
+select Tombstone.task_id as id,
+    Tombstone.seq
+from Tombstone
+where Tombstone.workspace_id = $1
+    and Tombstone.seq > $2
+    and Tombstone.seq <= $3
+order by Tombstone.seq
\ No newline at end of file
@@ -0,0 +1,3 @@
+select *
+from Workspace
+where Workspace.feed_token = $1
\ No newline at end of file
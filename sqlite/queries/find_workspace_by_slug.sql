@@ -0,0 +1,3 @@
+select *
+from Workspace
+where Workspace.slug = $1
\ No newline at end of file
@@ -1,2 +1,16 @@
-insert into Task (title, details)
-values ($1, $2);
\ No newline at end of file
+-- NOTE(alex): The quota check and the insert happen in the same statement, so two concurrent
+-- inserts can't both squeeze past the limit.
+insert into Task (workspace_id, list_id, title, details)
+select $1,
+    $2,
+    $3,
+    $4
+where (
+        select count(*)
+        from Task
+        where Task.workspace_id = $1
+    ) < (
+        select Workspace.max_tasks
+        from Workspace
+        where Workspace.id = $1
+    );
@@ -0,0 +1,2 @@
+insert into ImportedSource (workspace_id, source, source_id, task_id)
+values ($1, $2, $3, $4)
\ No newline at end of file
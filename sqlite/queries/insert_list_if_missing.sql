@@ -0,0 +1,2 @@
+insert or ignore into TaskList (workspace_id, name)
+values ($1, $2)
\ No newline at end of file
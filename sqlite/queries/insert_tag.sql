@@ -0,0 +1,2 @@
+insert into Tag (workspace_id, name)
+values ($1, $2);
\ No newline at end of file
@@ -0,0 +1,7 @@
+select exists (
+        select 1
+        from Done
+            join Task on Task.id = Done.task_id
+        where Task.workspace_id = $1
+            and Task.id = $2
+    )
\ No newline at end of file
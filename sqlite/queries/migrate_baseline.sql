@@ -0,0 +1,7 @@
+drop view if exists OngoingTask;
+
+alter table Task
+    rename to BaselineTask;
+
+alter table Done
+    rename to BaselineDone;
\ No newline at end of file
@@ -0,0 +1,10 @@
+update Task
+set list_id = $2,
+    title = $3,
+    details = $4
+where Task.workspace_id = $1
+    and Task.id = $5
+    and (
+        $6 is null
+        or Task.version = $6
+    )
\ No newline at end of file
@@ -0,0 +1,4 @@
+update Task
+set details = $1
+where Task.id = $2
+    and Task.details = $3
\ No newline at end of file
@@ -0,0 +1,3 @@
+update Workspace
+set feed_token = lower(hex(randomblob(16)))
+where Workspace.id = $1
\ No newline at end of file
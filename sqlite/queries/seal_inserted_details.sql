@@ -0,0 +1,5 @@
+update Task
+set details = $2
+where Task.workspace_id = $1
+    and Task.id = $3
+    and Task.details is null
\ No newline at end of file
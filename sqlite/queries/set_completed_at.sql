@@ -0,0 +1,8 @@
+update Done
+set completed_at = $3
+where Done.task_id = (
+        select Task.id
+        from Task
+        where Task.workspace_id = $1
+            and Task.id = $2
+    )
\ No newline at end of file
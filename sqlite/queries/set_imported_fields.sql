@@ -0,0 +1,6 @@
+update Task
+set due = $3,
+    priority = $4,
+    created_on = $5
+where Task.workspace_id = $1
+    and Task.id = $2
\ No newline at end of file
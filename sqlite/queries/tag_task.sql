@@ -0,0 +1,9 @@
+insert or ignore into TaskTag (workspace_id, task_id, tag_id)
+select Task.workspace_id,
+    Task.id,
+    Tag.id
+from Task
+    join Tag on Tag.workspace_id = Task.workspace_id
+where Task.workspace_id = $1
+    and Task.id = $2
+    and Tag.id = $3;
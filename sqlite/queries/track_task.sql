@@ -0,0 +1,3 @@
+delete from Untracked
+where Untracked.workspace_id = $1
+    and Untracked.task_id = $2
\ No newline at end of file
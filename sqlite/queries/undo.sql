@@ -1,2 +1,11 @@
 delete from Done
-where todo_id = $1
\ No newline at end of file
+where Done.task_id in (
+        select Task.id
+        from Task
+        where Task.workspace_id = $1
+            and Task.id = $2
+            and (
+                $3 is null
+                or Task.version = $3
+            )
+    )
\ No newline at end of file
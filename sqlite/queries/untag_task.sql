@@ -0,0 +1,4 @@
+delete from TaskTag
+where TaskTag.workspace_id = $1
+    and TaskTag.task_id = $2
+    and TaskTag.tag_id = $3
\ No newline at end of file
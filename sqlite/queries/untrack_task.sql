@@ -0,0 +1,2 @@
+insert into Untracked (workspace_id, task_id)
+values ($1, $2)
\ No newline at end of file
@@ -1,4 +1,9 @@
 update Task
-set title = $1,
-    details = $2
-where Task.id = $3
\ No newline at end of file
+set title = $2,
+    details = $3
+where Task.workspace_id = $1
+    and Task.id = $4
+    and (
+        $5 is null
+        or Task.version = $5
+    )
\ No newline at end of file
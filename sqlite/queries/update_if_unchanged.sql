@@ -0,0 +1,8 @@
+-- NOTE(alex): Only applies the client change if nobody touched the `Task` since the client last
+-- saw it (`seq`).
+update Task
+set title = $2,
+    details = $3
+where Task.workspace_id = $1
+    and Task.id = $4
+    and Task.seq = $5
\ No newline at end of file
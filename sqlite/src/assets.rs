@@ -0,0 +1,456 @@
+use std::{
+    borrow::Cow,
+    io::{self, Cursor, SeekFrom},
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+    time::SystemTime,
+};
+
+use httpdate::HttpDate;
+#[cfg(not(feature = "embed-assets"))]
+use log::error;
+use log::warn;
+use rocket::{
+    fairing::{self, Fairing, Info, Kind},
+    get,
+    http::{ContentType, Header, Status},
+    request::{FromRequest, Outcome},
+    response::{self, Responder},
+    serde::Deserialize,
+    tokio::{
+        fs::File,
+        io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, ReadBuf, Take},
+    },
+    Build, Request, Response, Rocket, State,
+};
+
+/// NOTE(alex): Built with `--features embed-assets`, the `static` directory is compiled into the
+/// binary, and served when `assets.dir` doesn't exist, a single file is all a deploy needs.
+#[cfg(feature = "embed-assets")]
+static EMBEDDED: include_dir::Dir<'static> =
+    include_dir::include_dir!("$CARGO_MANIFEST_DIR/static");
+
+/// Fingerprinted assets (`ui.3f2a9c1b.css`) never change, a new version gets a new name.
+const IMMUTABLE: &'static str = "public, max-age=31536000, immutable";
+
+/// Every other asset is revalidated with `If-Modified-Since`.
+const REVALIDATE: &'static str = "public, no-cache";
+
+/// Precompressed variants, in order of preference, sitting next to the asset (`ui.css.br`).
+const ENCODINGS: &[(&'static str, &'static str)] = &[("br", "br"), ("gzip", "gz")];
+
+/// `[global.assets]` in `Rocket.toml`.
+#[derive(Debug, Clone, Deserialize)]
+struct AssetsConfig {
+    #[serde(default = "default_dir")]
+    dir: PathBuf,
+}
+
+fn default_dir() -> PathBuf {
+    PathBuf::from("static")
+}
+
+/// Where assets are read from, see [`StaticAssets`].
+pub(crate) enum Assets {
+    /// The canonical path of `assets.dir`.
+    Directory(PathBuf),
+    #[cfg(feature = "embed-assets")]
+    Embedded,
+}
+
+enum Contents {
+    File(File),
+    #[cfg(feature = "embed-assets")]
+    Embedded(&'static [u8]),
+}
+
+/// An asset found by [`Assets::open`], not read yet.
+struct Asset {
+    contents: Contents,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl Assets {
+    /// `path` was already checked by its `FromSegments` (no `..`, no hidden files), files are also
+    /// canonicalized and must still be inside the directory, so a symlink can't point outside of
+    /// it.
+    async fn open(&self, path: &Path) -> Option<Asset> {
+        match self {
+            Assets::Directory(root) => {
+                let path = rocket::tokio::fs::canonicalize(root.join(path))
+                    .await
+                    .ok()?;
+                if !path.starts_with(root) {
+                    warn!(
+                        "Asset `{}` is outside of `{}`!",
+                        path.display(),
+                        root.display()
+                    );
+                    return None;
+                }
+
+                let file = File::open(&path).await.ok()?;
+                let metadata = file.metadata().await.ok()?;
+                if !metadata.is_file() {
+                    return None;
+                }
+
+                Some(Asset {
+                    contents: Contents::File(file),
+                    len: metadata.len(),
+                    modified: metadata.modified().ok(),
+                })
+            }
+            #[cfg(feature = "embed-assets")]
+            Assets::Embedded => {
+                let file = EMBEDDED.get_file(path)?;
+
+                Some(Asset {
+                    contents: Contents::Embedded(file.contents()),
+                    len: file.contents().len() as u64,
+                    modified: file.metadata().map(|metadata| metadata.modified()),
+                })
+            }
+        }
+    }
+}
+
+/// Serves `assets.dir` of `[global.assets]` when it exists, the embedded assets otherwise (refuses
+/// to launch without them).
+pub(crate) struct StaticAssets;
+
+#[rocket::async_trait]
+impl Fairing for StaticAssets {
+    fn info(&self) -> Info {
+        Info {
+            name: "Static assets",
+            kind: Kind::Ignite,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let config = rocket
+            .figment()
+            .extract_inner::<AssetsConfig>("assets")
+            .unwrap_or_else(|_| AssetsConfig { dir: default_dir() });
+
+        match config.dir.canonicalize() {
+            Ok(root) => Ok(rocket.manage(Assets::Directory(root))),
+            #[cfg(feature = "embed-assets")]
+            Err(_) => Ok(rocket.manage(Assets::Embedded)),
+            #[cfg(not(feature = "embed-assets"))]
+            Err(fail) => {
+                error!("Assets directory `{}`: {}", config.dir.display(), fail);
+                Err(rocket)
+            }
+        }
+    }
+}
+
+/// The headers of an asset request.
+pub(crate) struct AssetRequest {
+    accept_encoding: Option<String>,
+    range: Option<String>,
+    if_range: Option<String>,
+    if_modified_since: Option<HttpDate>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AssetRequest {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let header = |name| req.headers().get_one(name).map(|value| value.to_string());
+
+        Outcome::Success(AssetRequest {
+            accept_encoding: header("Accept-Encoding"),
+            range: header("Range"),
+            if_range: header("If-Range"),
+            if_modified_since: header("If-Modified-Since").and_then(|date| date.parse().ok()),
+        })
+    }
+}
+
+impl AssetRequest {
+    /// `q=0` refuses an encoding, `*` accepts any.
+    fn accepts(&self, encoding: &str) -> bool {
+        let accept_encoding = match &self.accept_encoding {
+            Some(accept_encoding) => accept_encoding,
+            None => return false,
+        };
+
+        accept_encoding.split(',').any(|coding| {
+            let mut parts = coding.split(';').map(|part| part.trim());
+            let name = parts.next().unwrap_or_default();
+            let refused = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q <= 0.0)
+            });
+
+            (name.eq_ignore_ascii_case(encoding) || name == "*") && !refused
+        })
+    }
+
+    /// The `Range` to serve, `Some(Err(()))` when it can't be satisfied.
+    ///
+    /// NOTE(alex): Only a single `bytes` range is supported, anything else (and an `If-Range` that
+    /// doesn't match `Last-Modified`) gets the whole asset, as the RFC allows.
+    fn range(&self, len: u64, last_modified: Option<&str>) -> Option<Result<(u64, u64), ()>> {
+        if let Some(if_range) = &self.if_range {
+            if Some(if_range.as_str()) != last_modified {
+                return None;
+            }
+        }
+
+        let spec = self.range.as_deref()?.strip_prefix("bytes=")?.trim();
+        if spec.contains(',') {
+            return None;
+        }
+
+        let (start, end) = spec.split_once('-')?;
+        let (start, end) = match (start.trim(), end.trim()) {
+            ("", suffix) => (None, Some(suffix.parse::<u64>().ok()?)),
+            (start, "") => (Some(start.parse::<u64>().ok()?), None),
+            (start, end) => (
+                Some(start.parse::<u64>().ok()?),
+                Some(end.parse::<u64>().ok()?),
+            ),
+        };
+
+        let last = match len.checked_sub(1) {
+            Some(last) => last,
+            None => return Some(Err(())),
+        };
+
+        match (start, end) {
+            (None, Some(0)) => Some(Err(())),
+            (None, Some(suffix)) => Some(Ok((len.saturating_sub(suffix), last))),
+            (Some(start), _) if start > last => Some(Err(())),
+            (Some(start), None) => Some(Ok((start, last))),
+            (Some(start), Some(end)) if end < start => None,
+            (Some(start), Some(end)) => Some(Ok((start, end.min(last)))),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Names with a hex segment of 8 or more characters before the extension, `ui.3f2a9c1b.css`.
+fn is_fingerprinted(path: &Path) -> bool {
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => return false,
+    };
+
+    let parts = name.split('.').collect::<Vec<_>>();
+    parts.len() > 2
+        && parts[1..parts.len() - 1]
+            .iter()
+            .any(|part| part.len() >= 8 && part.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// `len` bytes of a file from `start` on, read as they're sent instead of buffered.
+///
+/// NOTE(alex): `sized_body` needs `AsyncSeek` (it doesn't seek when given the size), seeking is
+/// relative to `start`, like the range was the whole file.
+struct FileRange {
+    body: Take<File>,
+    start: u64,
+    len: u64,
+}
+
+impl FileRange {
+    async fn new(mut file: File, start: u64, len: u64) -> io::Result<Self> {
+        file.seek(SeekFrom::Start(start)).await?;
+
+        Ok(Self {
+            body: file.take(len),
+            start,
+            len,
+        })
+    }
+}
+
+impl AsyncRead for FileRange {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.body).poll_read(cx, buf)
+    }
+}
+
+impl AsyncSeek for FileRange {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let (base, offset) = match position {
+            SeekFrom::Start(offset) => (0, offset as i64),
+            SeekFrom::Current(offset) => (self.len - self.body.limit(), offset),
+            SeekFrom::End(offset) => (self.len, offset),
+        };
+        let relative = (base as i64)
+            .checked_add(offset)
+            .filter(|relative| *relative >= 0)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the range"))?;
+
+        let start = self.start;
+        Pin::new(self.body.get_mut()).start_seek(SeekFrom::Start(start + relative as u64))
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let position = match Pin::new(self.body.get_mut()).poll_complete(cx) {
+            Poll::Ready(Ok(position)) => position,
+            Poll::Ready(Err(fail)) => return Poll::Ready(Err(fail)),
+            Poll::Pending => return Poll::Pending,
+        };
+
+        let relative = position.saturating_sub(self.start);
+        let remaining = self.len.saturating_sub(relative);
+        self.body.set_limit(remaining);
+
+        Poll::Ready(Ok(relative))
+    }
+}
+
+/// An asset ready to be sent, whole (`200`), in part (`206`), or not at all (`304`, `416`).
+pub(crate) struct AssetResponse {
+    status: Status,
+    content_type: ContentType,
+    encoding: Option<&'static str>,
+    cache_control: &'static str,
+    last_modified: Option<String>,
+    content_range: Option<String>,
+    body: Option<Cow<'static, [u8]>>,
+    file: Option<FileRange>,
+}
+
+impl<'r> Responder<'r, 'static> for AssetResponse {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response
+            .status(self.status)
+            .header(self.content_type)
+            .raw_header("Cache-Control", self.cache_control)
+            .raw_header("Vary", "Accept-Encoding")
+            .raw_header("Accept-Ranges", "bytes");
+
+        if let Some(encoding) = self.encoding {
+            response.raw_header("Content-Encoding", encoding);
+        }
+        if let Some(last_modified) = self.last_modified {
+            response.header(Header::new("Last-Modified", last_modified));
+        }
+        if let Some(content_range) = self.content_range {
+            response.header(Header::new("Content-Range", content_range));
+        }
+
+        match (self.body, self.file) {
+            (Some(body), _) => response.sized_body(body.len(), Cursor::new(body)),
+            (None, Some(file)) => response.sized_body(file.len as usize, file),
+            (None, None) => &mut response,
+        };
+
+        response.ok()
+    }
+}
+
+/// Serves the files of the assets directory.
+///
+/// NOTE(alex): `PathBuf` segments never contain `..` (or start with `.`), so the path can only go
+/// down from the assets directory, `Assets::open` takes care of symlinks.
+#[get("/static/<path..>")]
+pub(crate) async fn asset(
+    assets: &State<Assets>,
+    request: AssetRequest,
+    path: PathBuf,
+) -> Option<AssetResponse> {
+    // NOTE(alex): Ranges are over the identity encoding, so a precompressed variant is only picked
+    // for whole responses.
+    let mut found = None;
+    if request.range.is_none() {
+        for (encoding, extension) in ENCODINGS {
+            if !request.accepts(encoding) {
+                continue;
+            }
+
+            let mut variant = path.clone().into_os_string();
+            variant.push(".");
+            variant.push(extension);
+
+            if let Some(asset) = assets.open(Path::new(&variant)).await {
+                found = Some((asset, Some(*encoding)));
+                break;
+            }
+        }
+    }
+
+    let (asset, encoding) = match found {
+        Some(found) => found,
+        None => (assets.open(&path).await?, None),
+    };
+
+    let content_type = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(ContentType::from_extension)
+        .unwrap_or(ContentType::Binary);
+    let cache_control = if is_fingerprinted(&path) {
+        IMMUTABLE
+    } else {
+        REVALIDATE
+    };
+    let modified = asset.modified.map(HttpDate::from);
+
+    let mut response = AssetResponse {
+        status: Status::Ok,
+        content_type,
+        encoding,
+        cache_control,
+        last_modified: modified.map(|modified| modified.to_string()),
+        content_range: None,
+        body: None,
+        file: None,
+    };
+
+    if let (Some(modified), Some(since)) = (modified, request.if_modified_since) {
+        if modified <= since {
+            response.status = Status::NotModified;
+            return Some(response);
+        }
+    }
+
+    let range = match request.range(asset.len, response.last_modified.as_deref()) {
+        Some(Ok(range)) => Some(range),
+        Some(Err(())) => {
+            response.status = Status::RangeNotSatisfiable;
+            response.content_range = Some(format!("bytes */{}", asset.len));
+            return Some(response);
+        }
+        None => None,
+    };
+
+    match (asset.contents, range) {
+        (Contents::File(file), None) => {
+            response.file = Some(FileRange::new(file, 0, asset.len).await.ok()?)
+        }
+        (Contents::File(file), Some((start, end))) => {
+            response.file = Some(FileRange::new(file, start, end - start + 1).await.ok()?)
+        }
+        #[cfg(feature = "embed-assets")]
+        (Contents::Embedded(contents), None) => response.body = Some(Cow::Borrowed(contents)),
+        #[cfg(feature = "embed-assets")]
+        (Contents::Embedded(contents), Some((start, end))) => {
+            response.body = Some(Cow::Borrowed(&contents[start as usize..=end as usize]))
+        }
+    }
+
+    if let Some((start, end)) = range {
+        response.status = Status::PartialContent;
+        response.content_range = Some(format!("bytes {}-{}/{}", start, end, asset.len));
+    }
+
+    Some(response)
+}
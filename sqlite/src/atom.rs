@@ -0,0 +1,202 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fairings::Preconditions;
+use httpdate::HttpDate;
+use rocket::{
+    http::{ContentType, Header, Status},
+    response::{self, Responder},
+    Request, Response,
+};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+
+use crate::{
+    errors::AppError,
+    workspace::{Scoped, WorkspaceDb, PATH_PREFIX},
+};
+
+const FIND_FEED_CHANGES: Scoped = Scoped::new(include_str!("./../queries/find_feed_changes.sql"));
+
+/// Feed readers only ever look at the top of a feed.
+const MAX_ENTRIES: i64 = 50;
+
+/// Authority and date of the `tag:` URIs (RFC 4151) of the feed and its entries.
+const TAG_AUTHORITY: &'static str = "learning-rocket,2021";
+
+/// A change of a task, see `find_feed_changes.sql`.
+#[derive(Debug, FromRow)]
+struct FeedChange {
+    seq: i64,
+    task_id: i64,
+    kind: String,
+    title: String,
+    changed_at: String,
+    changed_epoch: i64,
+    details: String,
+    tags: Option<String>,
+}
+
+impl FeedChange {
+    fn verb(&self) -> &'static str {
+        match self.kind.as_str() {
+            "created" => "Created",
+            "done" => "Completed",
+            "undone" => "Reopened",
+            _ => "Edited",
+        }
+    }
+}
+
+/// `&`, `<`, `>` and quotes are escaped, and characters XML 1.0 doesn't allow at all are dropped.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => (),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// An `application/atom+xml` feed, or a `304 Not Modified` when the client has it already.
+pub(crate) struct AtomFeed {
+    body: Option<String>,
+    etag: String,
+    last_modified: Option<SystemTime>,
+}
+
+impl<'r> Responder<'r, 'static> for AtomFeed {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = match self.body {
+            Some(body) => (ContentType::new("application", "atom+xml"), body).respond_to(req)?,
+            None => Response::build().status(Status::NotModified).finalize(),
+        };
+
+        response.set_header(Header::new("ETag", self.etag));
+        response.set_header(Header::new("Cache-Control", "no-cache"));
+        if let Some(last_modified) = self.last_modified {
+            let last_modified = HttpDate::from(last_modified).to_string();
+            response.set_header(Header::new("Last-Modified", last_modified));
+        }
+
+        Ok(response)
+    }
+}
+
+/// The latest changes (creations, edits, completions, undos) of the workspace, newest first, of
+/// the tasks in `list` and with `tag` when given.
+///
+/// NOTE(alex): The `ETag` is a hash of the feed, not of the latest change, a task can leave the
+/// filtered feed (untagged, moved to another list) without a change of its own.
+pub(crate) async fn render(
+    workspace_db: &WorkspaceDb<'_>,
+    list: Option<i64>,
+    tag: Option<i64>,
+    preconditions: &Preconditions,
+) -> Result<AtomFeed, AppError> {
+    let mut tx = workspace_db.begin().await?;
+    let changes: Vec<FeedChange> = tx
+        .query_as(FIND_FEED_CHANGES)
+        .bind(list)
+        .bind(tag)
+        .bind(MAX_ENTRIES)
+        .fetch_all()
+        .await?;
+
+    let slug = &workspace_db.workspace.slug;
+    let ui = format!("{}{}/ui", PATH_PREFIX, slug);
+    let updated = changes
+        .first()
+        .map_or("1970-01-01T00:00:00Z", |change| change.changed_at.as_str());
+
+    let mut feed = String::new();
+    feed.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    feed.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    feed.push_str(&format!(
+        "  <id>tag:{}:{}/tasks</id>\n",
+        TAG_AUTHORITY,
+        escape(slug)
+    ));
+    feed.push_str(&format!("  <title>Tasks of {}</title>\n", escape(slug)));
+    feed.push_str(&format!("  <updated>{}</updated>\n", updated));
+    feed.push_str(&format!(
+        "  <author><name>{}</name></author>\n",
+        escape(slug)
+    ));
+    feed.push_str(&format!(
+        "  <link rel=\"alternate\" href=\"{}\"/>\n",
+        escape(&ui)
+    ));
+
+    for change in &changes {
+        let details = match workspace_db.cipher() {
+            Some(cipher) => {
+                cipher.open(workspace_db.workspace.id, change.task_id, &change.details)?
+            }
+            None => change.details.clone(),
+        };
+
+        feed.push_str("  <entry>\n");
+        feed.push_str(&format!(
+            "    <id>tag:{}:{}/tasks/{}/changes/{}</id>\n",
+            TAG_AUTHORITY,
+            escape(slug),
+            change.task_id,
+            change.seq
+        ));
+        feed.push_str(&format!(
+            "    <title>{}: {}</title>\n",
+            change.verb(),
+            escape(&change.title)
+        ));
+        feed.push_str(&format!("    <updated>{}</updated>\n", change.changed_at));
+        feed.push_str(&format!(
+            "    <link rel=\"alternate\" href=\"{}/tasks/{}\"/>\n",
+            escape(&ui),
+            change.task_id
+        ));
+        feed.push_str(&format!("    <category term=\"{}\"/>\n", change.kind));
+        for tag in change.tags.iter().flat_map(|tags| tags.split('\u{1f}')) {
+            feed.push_str(&format!("    <category term=\"{}\"/>\n", escape(tag)));
+        }
+        feed.push_str(&format!(
+            "    <content type=\"text\">{}</content>\n",
+            escape(&details)
+        ));
+        feed.push_str("  </entry>\n");
+    }
+
+    feed.push_str("</feed>\n");
+
+    let digest = Sha256::digest(feed.as_bytes());
+    let hash = digest
+        .iter()
+        .take(16)
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    let etag = format!("\"{}\"", hash);
+
+    let last_modified = changes
+        .first()
+        .map(|change| UNIX_EPOCH + Duration::from_secs(change.changed_epoch.max(0) as u64));
+
+    let body = if preconditions.is_unchanged(&etag, last_modified) {
+        None
+    } else {
+        Some(feed)
+    };
+
+    Ok(AtomFeed {
+        body,
+        etag,
+        last_modified,
+    })
+}
@@ -0,0 +1,328 @@
+use rocket::serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    errors::AppError,
+    events::{TaskEventKind, TaskEvents},
+    models::{InsertTask, Task, UpdateTask},
+    workspace::{Scoped, WorkspaceDb, WorkspaceTx},
+};
+
+const FIND_BY_ID: Scoped = Scoped::new(include_str!("./../queries/find_by_id.sql"));
+const INSERT: Scoped = Scoped::new(include_str!("./../queries/insert.sql"));
+const UPDATE: Scoped = Scoped::new(include_str!("./../queries/update.sql"));
+const DELETE: Scoped = Scoped::new(include_str!("./../queries/delete.sql"));
+
+const COMPLETED: Scoped = Scoped::new(include_str!("./../queries/done.sql"));
+const UNDO: Scoped = Scoped::new(include_str!("./../queries/undo.sql"));
+
+/// Keeps a single batch from holding the write lock for too long.
+const MAX_OPERATIONS: usize = 1000;
+
+/// One command of a batch, `version` makes it conditional like `If-Match` does for the single
+/// routes.
+///
+/// ```json
+/// { "op": "done", "id": 1, "version": 2 }
+/// ```
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub(crate) enum Operation {
+    Insert(InsertTask),
+    Update {
+        #[serde(flatten)]
+        update_task: UpdateTask,
+        #[serde(default)]
+        version: Option<i64>,
+    },
+    Delete {
+        id: i64,
+        #[serde(default)]
+        version: Option<i64>,
+    },
+    Done {
+        id: i64,
+        #[serde(default)]
+        version: Option<i64>,
+    },
+    Undo {
+        id: i64,
+        #[serde(default)]
+        version: Option<i64>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum BatchMode {
+    /// The first failure rolls back the whole batch.
+    AllOrNothing,
+    /// Failed operations are rolled back on their own, the rest is committed.
+    ContinueOnError,
+}
+
+impl Default for BatchMode {
+    fn default() -> Self {
+        BatchMode::AllOrNothing
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub(crate) struct Batch {
+    #[serde(default)]
+    pub(crate) mode: BatchMode,
+    pub(crate) operations: Vec<Operation>,
+}
+
+/// Result of each `Operation`, in the same order they were sent.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub(crate) enum OperationResult {
+    /// `task` is the task after the operation, `None` for deletes.
+    Applied {
+        task: Option<Task>,
+    },
+    Failed {
+        error: String,
+    },
+    /// Succeeded, but a later failure rolled back the batch (`all_or_nothing`).
+    RolledBack,
+    /// Never ran, an earlier failure aborted the batch (`all_or_nothing`).
+    Skipped,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct BatchResult {
+    pub(crate) committed: bool,
+    pub(crate) results: Vec<OperationResult>,
+}
+
+/// What an operation did, events are only published once the batch is committed.
+pub(crate) struct Applied {
+    pub(crate) task: Option<Task>,
+    pub(crate) event: Option<(TaskEventKind, Task)>,
+}
+
+impl Batch {
+    pub(crate) fn validate(&self) -> Result<(), AppError> {
+        if self.operations.len() > MAX_OPERATIONS {
+            return Err(AppError::InvalidTask(format!(
+                "a batch can't have more than `{}` operations",
+                MAX_OPERATIONS
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Runs every operation in one transaction, each in its own savepoint, so a failed operation
+    /// can be undone without losing the ones before it.
+    ///
+    /// NOTE(alex): The transaction holds SQLite's write lock from the first write until the
+    /// commit, other writers wait (`busy_timeout`) instead of interleaving with the batch.
+    pub(crate) async fn execute(
+        self,
+        workspace_db: &WorkspaceDb<'_>,
+        task_events: &TaskEvents,
+    ) -> Result<BatchResult, AppError> {
+        let mode = self.mode;
+        let mut tx = workspace_db.begin().await?;
+        let mut results = Vec::with_capacity(self.operations.len());
+        let mut events = Vec::new();
+        let mut failed = false;
+
+        for operation in self.operations {
+            if failed {
+                results.push(OperationResult::Skipped);
+                continue;
+            }
+
+            tx.savepoint().await?;
+
+            match operation.apply(workspace_db, &mut tx).await {
+                Ok(applied) => {
+                    tx.release_savepoint().await?;
+
+                    events.extend(applied.event);
+                    results.push(OperationResult::Applied { task: applied.task });
+                }
+                Err(fail) => {
+                    tx.rollback_to_savepoint().await?;
+                    tx.release_savepoint().await?;
+
+                    results.push(OperationResult::Failed {
+                        error: fail.to_string(),
+                    });
+                    failed = mode == BatchMode::AllOrNothing;
+                }
+            }
+        }
+
+        if failed {
+            tx.rollback().await?;
+
+            for result in results.iter_mut() {
+                if let OperationResult::Applied { .. } = result {
+                    *result = OperationResult::RolledBack;
+                }
+            }
+
+            return Ok(BatchResult {
+                committed: false,
+                results,
+            });
+        }
+
+        tx.commit().await?;
+
+        let workspace_id = workspace_db.workspace.id;
+        for (kind, task) in events {
+            task_events.publish(workspace_id, kind, task);
+        }
+
+        Ok(BatchResult {
+            committed: true,
+            results,
+        })
+    }
+}
+
+impl Operation {
+    pub(crate) async fn apply(
+        self,
+        workspace_db: &WorkspaceDb<'_>,
+        tx: &mut WorkspaceTx,
+    ) -> Result<Applied, AppError> {
+        match self {
+            Operation::Insert(insert_task) => {
+                insert_task.validate()?;
+
+                let result = tx
+                    .query(INSERT)
+                    .bind(&insert_task.list_id)
+                    .bind(&insert_task.non_empty_title)
+                    .bind(Task::inserted_details(workspace_db, &insert_task.details))
+                    .execute()
+                    .await?;
+
+                if result.rows_affected() == 0 {
+                    return Err(AppError::QuotaExceeded(workspace_db.workspace.max_tasks));
+                }
+
+                let id = result.last_insert_rowid();
+                Task::seal_inserted(workspace_db, &mut *tx, id, &insert_task.details).await?;
+                Self::changed(workspace_db, tx, id, TaskEventKind::Created).await
+            }
+            Operation::Update {
+                update_task,
+                version,
+            } => {
+                update_task.validate()?;
+
+                let result = tx
+                    .query(UPDATE)
+                    .bind(&update_task.new_title)
+                    .bind(&Task::sealed_details(
+                        workspace_db,
+                        update_task.id,
+                        &update_task.details,
+                    )?)
+                    .bind(update_task.id)
+                    .bind(version)
+                    .execute()
+                    .await?;
+
+                if result.rows_affected() == 0 {
+                    return Err(Self::unapplied(workspace_db, tx, update_task.id).await);
+                }
+
+                Self::changed(workspace_db, tx, update_task.id, TaskEventKind::Updated).await
+            }
+            Operation::Delete { id, version } => {
+                let task = Self::find_by_id(workspace_db, tx, id).await?;
+
+                let result = tx.query(DELETE).bind(id).bind(version).execute().await?;
+
+                match task {
+                    Some(task) if result.rows_affected() > 0 => Ok(Applied {
+                        task: None,
+                        event: Some((TaskEventKind::Deleted, task)),
+                    }),
+                    _ => Err(Self::unapplied(workspace_db, tx, id).await),
+                }
+            }
+            Operation::Done { id, version } => {
+                Self::toggle(
+                    workspace_db,
+                    tx,
+                    id,
+                    version,
+                    COMPLETED,
+                    TaskEventKind::Done,
+                )
+                .await
+            }
+            Operation::Undo { id, version } => {
+                Self::toggle(workspace_db, tx, id, version, UNDO, TaskEventKind::Undone).await
+            }
+        }
+    }
+
+    /// `done` and `undo` change nothing when the task already is in that state, which isn't a
+    /// failure (unless the task is missing, or not at `version`).
+    async fn toggle(
+        workspace_db: &WorkspaceDb<'_>,
+        tx: &mut WorkspaceTx,
+        id: i64,
+        version: Option<i64>,
+        sql: Scoped,
+        kind: TaskEventKind,
+    ) -> Result<Applied, AppError> {
+        let result = tx.query(sql).bind(id).bind(version).execute().await?;
+
+        if result.rows_affected() > 0 {
+            return Self::changed(workspace_db, tx, id, kind).await;
+        }
+
+        match Self::find_by_id(workspace_db, tx, id).await? {
+            Some(task) if version.map_or(true, |version| version == task.version) => Ok(Applied {
+                task: Some(task),
+                event: None,
+            }),
+            _ => Err(Self::unapplied(workspace_db, tx, id).await),
+        }
+    }
+
+    async fn changed(
+        workspace_db: &WorkspaceDb<'_>,
+        tx: &mut WorkspaceTx,
+        id: i64,
+        kind: TaskEventKind,
+    ) -> Result<Applied, AppError> {
+        let task = Self::find_by_id(workspace_db, tx, id).await?;
+
+        Ok(Applied {
+            event: task.clone().map(|task| (kind, task)),
+            task,
+        })
+    }
+
+    /// Why a write to `id` changed nothing: the task is missing, or at another version.
+    async fn unapplied(workspace_db: &WorkspaceDb<'_>, tx: &mut WorkspaceTx, id: i64) -> AppError {
+        match Self::find_by_id(workspace_db, tx, id).await {
+            Ok(Some(_)) => AppError::PreconditionFailed,
+            Ok(None) => AppError::IdNotFound(id),
+            Err(fail) => fail,
+        }
+    }
+
+    pub(crate) async fn find_by_id(
+        workspace_db: &WorkspaceDb<'_>,
+        tx: &mut WorkspaceTx,
+        id: i64,
+    ) -> Result<Option<Task>, AppError> {
+        let task: Option<Task> = tx.query_as(FIND_BY_ID).bind(id).fetch_optional().await?;
+
+        task.map(|task| task.opened(workspace_db)).transpose()
+    }
+}
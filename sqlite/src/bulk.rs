@@ -0,0 +1,160 @@
+use rocket::serde::{json::serde_json, Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use crate::{
+    batch::Operation,
+    errors::AppError,
+    events::TaskEvents,
+    workspace::{Scoped, WorkspaceDb},
+};
+
+const FIND_MATCHING: Scoped = Scoped::new(include_str!("./../queries/find_matching.sql"));
+
+/// `bulk_confirm_threshold` in `Rocket.toml`, actions affecting more tasks than this need
+/// `confirm: true`.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct BulkConfig {
+    #[serde(default = "default_confirm_threshold")]
+    bulk_confirm_threshold: usize,
+}
+
+fn default_confirm_threshold() -> usize {
+    20
+}
+
+/// Tasks must match every filter that is set, an empty filter matches every task.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub(crate) struct BulkFilter {
+    /// Same as `GET /tasks?{pattern}`.
+    pub(crate) pattern: Option<String>,
+    pub(crate) done: Option<bool>,
+    pub(crate) ids: Option<Vec<i64>>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum BulkAction {
+    Done,
+    Undo,
+    Delete,
+}
+
+/// ```json
+/// { "filter": { "pattern": "%sprint%", "done": false }, "action": "done", "dry_run": true }
+/// ```
+#[derive(Debug, Deserialize, ToSchema)]
+pub(crate) struct Bulk {
+    #[serde(default)]
+    pub(crate) filter: BulkFilter,
+    pub(crate) action: BulkAction,
+    #[serde(default)]
+    pub(crate) dry_run: bool,
+    #[serde(default)]
+    pub(crate) confirm: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct BulkResult {
+    pub(crate) dry_run: bool,
+    /// Whether applying it (again) needs `confirm: true`.
+    pub(crate) needs_confirm: bool,
+    pub(crate) affected: usize,
+    pub(crate) ids: Vec<i64>,
+}
+
+#[derive(Debug, FromRow)]
+struct Matching {
+    id: i64,
+    done: bool,
+}
+
+impl BulkAction {
+    /// Tasks already in the target state are left out, `done` on a done task changes nothing.
+    fn affects(&self, matching: &Matching) -> bool {
+        match self {
+            BulkAction::Done => !matching.done,
+            BulkAction::Undo => matching.done,
+            BulkAction::Delete => true,
+        }
+    }
+
+    fn operation(&self, id: i64) -> Operation {
+        match self {
+            BulkAction::Done => Operation::Done { id, version: None },
+            BulkAction::Undo => Operation::Undo { id, version: None },
+            BulkAction::Delete => Operation::Delete { id, version: None },
+        }
+    }
+}
+
+impl Bulk {
+    /// Finds the affected tasks and applies the action to them in the same transaction, so the
+    /// tasks changed are exactly the ones counted.
+    ///
+    /// NOTE(alex): Goes through the batch `Operation`s, every task gets the same triggers (sync
+    /// sequence, `version`) and events as if it was changed on its own.
+    pub(crate) async fn execute(
+        self,
+        workspace_db: &WorkspaceDb<'_>,
+        task_events: &TaskEvents,
+        config: &BulkConfig,
+    ) -> Result<BulkResult, AppError> {
+        let ids = match &self.filter.ids {
+            Some(ids) => Some(serde_json::to_string(ids).map_err(|_| AppError::Internal)?),
+            None => None,
+        };
+
+        let mut tx = workspace_db.begin().await?;
+        let matching: Vec<Matching> = tx
+            .query_as(FIND_MATCHING)
+            .bind(&self.filter.pattern)
+            .bind(self.filter.done)
+            .bind(ids)
+            .fetch_all()
+            .await?;
+
+        let ids: Vec<i64> = matching
+            .iter()
+            .filter(|matching| self.action.affects(matching))
+            .map(|matching| matching.id)
+            .collect();
+
+        let needs_confirm = ids.len() > config.bulk_confirm_threshold;
+        let result = BulkResult {
+            dry_run: self.dry_run,
+            needs_confirm,
+            affected: ids.len(),
+            ids,
+        };
+
+        if self.dry_run {
+            tx.rollback().await?;
+            return Ok(result);
+        }
+
+        if needs_confirm && !self.confirm {
+            tx.rollback().await?;
+            return Err(AppError::ConfirmationRequired(result.affected));
+        }
+
+        let mut events = Vec::with_capacity(result.ids.len());
+        for id in result.ids.iter() {
+            let applied = self
+                .action
+                .operation(*id)
+                .apply(workspace_db, &mut tx)
+                .await?;
+            events.extend(applied.event);
+        }
+
+        tx.commit().await?;
+
+        let workspace_id = workspace_db.workspace.id;
+        for (kind, task) in events {
+            task_events.publish(workspace_id, kind, task);
+        }
+
+        Ok(result)
+    }
+}
@@ -0,0 +1,641 @@
+use fairings::FromBody;
+use rocket::{
+    data::{ByteUnit, FromData, ToByteUnit},
+    http::{ContentType, Status},
+    serde::Serialize,
+};
+use utoipa::ToSchema;
+
+use crate::{
+    errors::AppError,
+    events::TaskEvents,
+    exchange::{is_date, is_due, ExportedTask, ImportedTask},
+    models::Task,
+    negotiate::{body_text, read_body},
+    workspace::WorkspaceDb,
+};
+
+/// Keeps a single import from holding the write lock for too long, like `MAX_OPERATIONS` of a
+/// batch.
+const MAX_ITEMS: usize = 1000;
+
+/// The plain text formats of `GET /tasks/export` and `POST /tasks/import`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ChecklistFormat {
+    /// A task per line, see <https://github.com/todotxt/todo.txt>.
+    TodoTxt,
+    /// A `- [ ]` (or `- [x]`) item per task, with the todo.txt line after the checkbox, and the
+    /// details indented below it.
+    Markdown,
+}
+
+impl ChecklistFormat {
+    /// Names of the `?format=` query.
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "todotxt" => Some(ChecklistFormat::TodoTxt),
+            "markdown" => Some(ChecklistFormat::Markdown),
+            _ => None,
+        }
+    }
+
+    fn from_content_type(content_type: &ContentType) -> Option<Self> {
+        let top = content_type.top().as_str().to_ascii_lowercase();
+        let sub = content_type.sub().as_str().to_ascii_lowercase();
+
+        match (top.as_str(), sub.as_str()) {
+            ("text", "plain") => Some(ChecklistFormat::TodoTxt),
+            ("text", "markdown") | ("text", "x-markdown") => Some(ChecklistFormat::Markdown),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn content_type(&self) -> ContentType {
+        match self {
+            ChecklistFormat::TodoTxt => ContentType::Plain,
+            ChecklistFormat::Markdown => ContentType::new("text", "markdown"),
+        }
+    }
+}
+
+/// Body of `POST /tasks/import`, the format is the `?format=` query, or else the `Content-Type`.
+pub(crate) struct Checklist {
+    format: ChecklistFormat,
+    text: String,
+}
+
+impl<'r> FromBody<'r> for Checklist {
+    type Error = AppError;
+
+    fn limit(req: &'r rocket::Request<'_>) -> ByteUnit {
+        req.limits().get("checklist").unwrap_or(1.mebibytes())
+    }
+
+    fn from_body(
+        req: &'r rocket::Request<'_>,
+        body: Vec<u8>,
+    ) -> Result<Self, (Status, Self::Error)> {
+        let format = match req.query_value::<&str>("format") {
+            Some(name) => name.ok().and_then(ChecklistFormat::from_name),
+            None => req
+                .content_type()
+                .and_then(ChecklistFormat::from_content_type),
+        };
+        let format = match format {
+            Some(format) => format,
+            None => return Err((Status::UnsupportedMediaType, AppError::UnsupportedChecklist)),
+        };
+
+        Ok(Checklist {
+            format,
+            text: body_text(body)?,
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for Checklist {
+    type Error = AppError;
+
+    async fn from_data(
+        req: &'r rocket::Request<'_>,
+        data: rocket::Data<'r>,
+    ) -> rocket::data::Outcome<'r, Self> {
+        read_body(req, data).await
+    }
+}
+
+/// An item of a checklist, with the line it starts on.
+type ParsedItem = (usize, Result<ImportedTask, String>);
+
+/// What happened to one item of `POST /tasks/import`, `line` is where it starts (from 1).
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub(crate) enum ChecklistResult {
+    /// A dry run, the item would be imported as `item`.
+    Preview {
+        line: usize,
+        item: ImportedTask,
+    },
+    Imported {
+        line: usize,
+        item: ImportedTask,
+        task: Task,
+    },
+    Failed {
+        line: usize,
+        error: String,
+    },
+}
+
+/// `(A)` to `(Z)`, the letter.
+fn priority(word: &str) -> Option<String> {
+    match word.as_bytes() {
+        [b'(', letter, b')'] if letter.is_ascii_uppercase() => Some((*letter as char).to_string()),
+        _ => None,
+    }
+}
+
+/// The `key:value`s [`parse_item`] reads, a title word that looks like one is escaped.
+const KEYS: [&str; 4] = ["due", "pri", "completed", "details"];
+
+/// Percent-encodes `%` and whitespace, so `text` is a single todo.txt word (names with spaces, or
+/// the details with their newlines).
+fn escape(text: &str) -> String {
+    let mut word = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c == '%' || c.is_whitespace() {
+            for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                word.push_str(&format!("%{:02X}", byte));
+            }
+        } else {
+            word.push(c);
+        }
+    }
+
+    word
+}
+
+/// The reverse of [`escape`], a `%` that isn't followed by 2 hex digits is kept as it is.
+fn unescape(word: &str) -> String {
+    let bytes = word.as_bytes();
+    let mut text = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let hex = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match hex {
+            Some(byte) => {
+                text.push(byte);
+                index += 3;
+            }
+            None => {
+                text.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&text).into_owned()
+}
+
+/// Splits `title` on the single spaces between words, the other whitespace (leading, trailing,
+/// repeated, tabs and newlines) is [`escape`]d into the words, so the title comes back as it was.
+fn title_words(title: &str) -> Vec<String> {
+    let chars: Vec<char> = title.chars().collect();
+    let mut words = vec![String::new()];
+
+    for (index, c) in chars.iter().enumerate() {
+        let separator = *c == ' '
+            && index > 0
+            && index + 1 < chars.len()
+            && !chars[index - 1].is_whitespace()
+            && !chars[index + 1].is_whitespace();
+
+        if separator {
+            words.push(String::new());
+        } else if let Some(word) = words.last_mut() {
+            word.push(*c);
+        }
+    }
+
+    words.iter().map(|word| escape(word)).collect()
+}
+
+/// A word of the title that [`parse_item`] would read as something else (a `+project`, an
+/// `@context`, a `key:value`, or the `x `, priority or a date at the start) gets its first
+/// character, or its `:`, percent-encoded.
+fn quote(word: String, first: bool) -> String {
+    let leading = (word.starts_with(['+', '@']) && word.len() > 1)
+        || first && (word == "x" || priority(&word).is_some() || is_date(&word));
+    if leading {
+        // NOTE(alex): Every case above starts with an ASCII character.
+        return format!("%{:02X}{}", word.as_bytes()[0], &word[1..]);
+    }
+
+    match word.split_once(':') {
+        Some((key, value)) if KEYS.contains(&key) => format!("{}%3A{}", key, value),
+        _ => word,
+    }
+}
+
+/// Reads a todo.txt line, without its `x ` (`done` says whether it had one).
+///
+/// The first `+project` is the list, the others are kept as tags with their `+`, `@contexts` are
+/// the tags, `due:`, `pri:`, `completed:` (the time of a done task) and `details:` are read, and
+/// every other word (`key:value`s included) stays in the title. Names, the details and the title
+/// are [`unescape`]d.
+fn parse_item(text: &str, done: bool) -> Result<ImportedTask, String> {
+    let mut item = ImportedTask {
+        done,
+        ..ImportedTask::default()
+    };
+    let mut words = text.split_whitespace().peekable();
+
+    // NOTE(alex): A done task starts with its completion date, then its creation date, an
+    // ongoing one with its priority, then its creation date.
+    if done {
+        if let Some(date) = words.next_if(|word| is_date(word)) {
+            item.completed_at = Some(format!("{}T00:00:00Z", date));
+            item.created_on = words.next_if(|word| is_date(word)).map(str::to_string);
+        }
+    }
+    item.priority = words.peek().and_then(|word| priority(word));
+    if item.priority.is_some() {
+        words.next();
+    }
+    if item.created_on.is_none() {
+        item.created_on = words.next_if(|word| is_date(word)).map(str::to_string);
+    }
+
+    let mut title = Vec::new();
+    for word in words {
+        match word.split_at(word.chars().next().map_or(0, char::len_utf8)) {
+            ("+", project) if !project.is_empty() => match item.list {
+                Some(_) => item.tags.push(format!("+{}", unescape(project))),
+                None => item.list = Some(unescape(project)),
+            },
+            ("@", context) if !context.is_empty() => item.tags.push(unescape(context)),
+            _ => match word.split_once(':') {
+                Some(("due", due)) if is_due(due) => item.due = Some(due.to_string()),
+                Some(("due", due)) => {
+                    return Err(format!("`due:{}` is not a date or date-time", due))
+                }
+                Some(("pri", letter)) if item.priority.is_none() => {
+                    item.priority = priority(&format!("({})", letter));
+                    if item.priority.is_none() {
+                        return Err(format!("`pri:{}` is not a letter from `A` to `Z`", letter));
+                    }
+                }
+                Some(("completed", at)) if done && is_due(at) => {
+                    item.completed_at = Some(at.to_string())
+                }
+                Some(("details", details)) => item.details = unescape(details),
+                _ => title.push(unescape(word)),
+            },
+        }
+    }
+    item.title = title.join(" ");
+
+    Ok(item)
+}
+
+/// The reverse of [`parse_item`], in the order of the todo.txt format (the title, then the list,
+/// tags and due date), with the `details:` when `with_details`.
+///
+/// NOTE(alex): A done task keeps its priority as `pri:`, like most todo.txt clients do, and its
+/// `completed:` time when it isn't midnight.
+fn render_item(task: &ExportedTask, with_details: bool) -> String {
+    let mut words = Vec::new();
+
+    match &task.completed_at {
+        Some(completed_at) => {
+            words.push(completed_at.chars().take(10).collect());
+            words.extend(task.created_on.clone());
+        }
+        None => {
+            words.extend(task.priority.as_ref().map(|letter| format!("({})", letter)));
+            words.extend(task.created_on.clone());
+        }
+    }
+
+    let title = title_words(&task.title).into_iter().enumerate();
+    words.extend(title.map(|(index, word)| quote(word, index == 0)));
+    words.extend(task.list.as_ref().map(|list| format!("+{}", escape(list))));
+    for tag in task.tags() {
+        match tag.strip_prefix('+') {
+            Some(project) => words.push(format!("+{}", escape(project))),
+            None => words.push(format!("@{}", escape(tag))),
+        }
+    }
+    words.extend(task.due.as_ref().map(|due| format!("due:{}", due)));
+    if let Some(completed_at) = &task.completed_at {
+        words.extend(
+            task.priority
+                .as_ref()
+                .map(|letter| format!("pri:{}", letter)),
+        );
+        if !completed_at.ends_with("T00:00:00Z") {
+            words.push(format!("completed:{}", completed_at));
+        }
+    }
+    if with_details && !task.details.is_empty() {
+        words.push(format!("details:{}", escape(&task.details)));
+    }
+
+    words.retain(|word| !word.is_empty());
+    words.join(" ")
+}
+
+/// Every task of the workspace in `format`, ongoing and done ones in the order they were created.
+///
+/// NOTE(alex): todo.txt has no place for the details, they're an escaped `details:`, Markdown
+/// indents them below the item.
+pub(crate) async fn render(
+    workspace_db: &WorkspaceDb<'_>,
+    format: ChecklistFormat,
+) -> Result<String, AppError> {
+    let tasks = ExportedTask::find_all(workspace_db).await?;
+    let mut text = String::new();
+
+    if format == ChecklistFormat::Markdown {
+        text.push_str(&format!("# {}\n\n", workspace_db.workspace.slug));
+    }
+
+    for task in tasks {
+        render_task(&mut text, &task, format);
+    }
+
+    Ok(text)
+}
+
+/// One item of [`render`].
+fn render_task(text: &mut String, task: &ExportedTask, format: ChecklistFormat) {
+    let done = task.completed_at.is_some();
+
+    match format {
+        ChecklistFormat::TodoTxt => {
+            let item = render_item(task, true);
+            if done {
+                text.push_str(&format!("x {}\n", item));
+            } else {
+                text.push_str(&format!("{}\n", item));
+            }
+        }
+        // NOTE(alex): Details with whitespace at their end, or lines of only whitespace, wouldn't
+        // come back as they were from below the item, they're a `details:` then.
+        ChecklistFormat::Markdown if !indentable(&task.details) => {
+            let checkbox = if done { "[x]" } else { "[ ]" };
+            text.push_str(&format!("- {} {}\n", checkbox, render_item(task, true)));
+        }
+        ChecklistFormat::Markdown => {
+            let checkbox = if done { "[x]" } else { "[ ]" };
+            text.push_str(&format!("- {} {}\n", checkbox, render_item(task, false)));
+
+            for line in task.details.lines() {
+                match line {
+                    "" => text.push('\n'),
+                    line => text.push_str(&format!("  {}\n", line)),
+                }
+            }
+        }
+    }
+}
+
+/// Whether `details` read back from its lines indented below an item (by [`parse_markdown`]) are
+/// the same.
+fn indentable(details: &str) -> bool {
+    details == details.trim_end()
+        && !details.contains('\r')
+        && details
+            .lines()
+            .all(|line| line.trim().is_empty() == line.is_empty())
+}
+
+/// `- [ ] ...`, `* [x] ...` or `+ [X] ...` at the start of a line, whether it's checked and what
+/// comes after the checkbox.
+fn checkbox(line: &str) -> Option<(bool, &str)> {
+    let rest = line
+        .strip_prefix(&['-', '*', '+'][..])?
+        .strip_prefix(' ')?
+        .trim_start();
+
+    let (done, rest) = match rest.get(..3)? {
+        "[ ]" => (false, &rest[3..]),
+        "[x]" | "[X]" => (true, &rest[3..]),
+        _ => return None,
+    };
+
+    if rest.is_empty() || rest.starts_with(' ') {
+        Some((done, rest))
+    } else {
+        None
+    }
+}
+
+/// Every non-empty line is an item.
+fn parse_todo_txt(text: &str) -> Vec<ParsedItem> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            let item = match line.strip_prefix("x ") {
+                Some(rest) => parse_item(rest, true),
+                None => parse_item(line, false),
+            };
+
+            (index + 1, item)
+        })
+        .collect()
+}
+
+/// Every checklist item is an item, the indented lines below it are its details, and the rest
+/// (headings, paragraphs, other lists) is skipped.
+fn parse_markdown(text: &str) -> Vec<ParsedItem> {
+    let mut items = Vec::new();
+    let mut current: Option<(ParsedItem, Vec<&str>)> = None;
+
+    let finish = |current: Option<(ParsedItem, Vec<&str>)>| {
+        current.map(|((line, item), details)| {
+            let item = item.map(|mut item| {
+                let details = details.join("\n").trim_end().to_string();
+                if !details.is_empty() {
+                    item.details = details;
+                }
+                item
+            });
+
+            (line, item)
+        })
+    };
+
+    for (index, line) in text.lines().enumerate() {
+        if let Some((done, rest)) = checkbox(line) {
+            items.extend(finish(current.take()));
+            current = Some(((index + 1, parse_item(rest, done)), Vec::new()));
+            continue;
+        }
+
+        match current.as_mut() {
+            Some((_, details)) if line.trim().is_empty() => details.push(""),
+            Some((_, details)) if line.starts_with("  ") => details.push(&line[2..]),
+            Some((_, details)) if line.starts_with('\t') => details.push(&line[1..]),
+            _ => items.extend(finish(current.take())),
+        }
+    }
+    items.extend(finish(current.take()));
+
+    items
+}
+
+/// Imports every item of `checklist` on its own, a failed one doesn't stop the others. A
+/// `dry_run` imports nothing, and says what each item would be imported as.
+pub(crate) async fn import(
+    workspace_db: &WorkspaceDb<'_>,
+    task_events: &TaskEvents,
+    checklist: Checklist,
+    dry_run: bool,
+) -> Result<Vec<ChecklistResult>, AppError> {
+    let items = match checklist.format {
+        ChecklistFormat::TodoTxt => parse_todo_txt(&checklist.text),
+        ChecklistFormat::Markdown => parse_markdown(&checklist.text),
+    };
+
+    if items.len() > MAX_ITEMS {
+        return Err(AppError::InvalidTask(format!(
+            "a checklist can't have more than `{}` items",
+            MAX_ITEMS
+        )));
+    }
+
+    let mut results = Vec::new();
+    for (line, item) in items {
+        let item = item.map_err(AppError::InvalidTask).and_then(|item| {
+            item.validate()?;
+            Ok(item)
+        });
+
+        let result = match item {
+            Ok(item) if dry_run => ChecklistResult::Preview { line, item },
+            Ok(item) => match item.insert(workspace_db, task_events).await {
+                Ok(task) => ChecklistResult::Imported { line, item, task },
+                Err(fail) => ChecklistResult::Failed {
+                    line,
+                    error: fail.to_string(),
+                },
+            },
+            Err(fail) => ChecklistResult::Failed {
+                line,
+                error: fail.to_string(),
+            },
+        };
+
+        results.push(result);
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(title: &str) -> ExportedTask {
+        ExportedTask {
+            id: 1,
+            title: title.to_string(),
+            details: String::new(),
+            due: None,
+            priority: None,
+            created_on: None,
+            version: 1,
+            list: None,
+            completed_at: None,
+            tags: None,
+            stamp: String::new(),
+        }
+    }
+
+    /// Renders `task` in `format` and reads it back.
+    fn round_trip(task: &ExportedTask, format: ChecklistFormat) -> ImportedTask {
+        let mut text = String::new();
+        render_task(&mut text, task, format);
+
+        let mut items = match format {
+            ChecklistFormat::TodoTxt => parse_todo_txt(&text),
+            ChecklistFormat::Markdown => parse_markdown(&text),
+        };
+        assert_eq!(items.len(), 1, "{:?} is a single item", text);
+
+        items.remove(0).1.expect("the rendered item parses")
+    }
+
+    fn assert_round_trips(task: &ExportedTask) {
+        for format in [ChecklistFormat::TodoTxt, ChecklistFormat::Markdown] {
+            let item = round_trip(task, format);
+
+            assert_eq!(item.title, task.title, "{:?}", format);
+            assert_eq!(item.details, task.details, "{:?}", format);
+            assert_eq!(item.due, task.due, "{:?}", format);
+            assert_eq!(item.priority, task.priority, "{:?}", format);
+            assert_eq!(item.created_on, task.created_on, "{:?}", format);
+            assert_eq!(item.list, task.list, "{:?}", format);
+            assert_eq!(item.tags, task.tags(), "{:?}", format);
+            assert_eq!(item.done, task.completed_at.is_some(), "{:?}", format);
+            assert_eq!(item.completed_at, task.completed_at, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn every_field_round_trips() {
+        let mut task = task("Call the bank");
+        task.details = "Ask about the fees.\n\n- the card\n  - the account".to_string();
+        task.due = Some("2024-05-01T09:30:00Z".to_string());
+        task.priority = Some("B".to_string());
+        task.created_on = Some("2024-04-01".to_string());
+        task.list = Some("Home Admin".to_string());
+        task.tags = Some("phone\u{1f}+second project\u{1f}50% off".to_string());
+        assert_round_trips(&task);
+
+        task.completed_at = Some("2024-04-20T17:45:12Z".to_string());
+        assert_round_trips(&task);
+
+        task.completed_at = Some("2024-04-20T00:00:00Z".to_string());
+        task.created_on = None;
+        assert_round_trips(&task);
+    }
+
+    #[test]
+    fn metadata_looking_titles_round_trip() {
+        let titles = [
+            "x",
+            "x marks the spot",
+            "(A) is the best grade",
+            "2024-01-01 was a Monday",
+            "Buy +milk and @home due:never",
+            "Set pri:A and due:2024-05-01",
+            "completed:2024-01-01T10:00:00Z details:none",
+            "+ @ : % %20 100%",
+            "  spaced   out\ttitle\n",
+        ];
+
+        for title in titles {
+            assert_round_trips(&task(title));
+
+            let mut done = task(title);
+            done.completed_at = Some("2024-04-20T17:45:12Z".to_string());
+            assert_round_trips(&done);
+        }
+    }
+
+    #[test]
+    fn details_that_cant_be_indented_round_trip() {
+        for details in [
+            "trailing space ",
+            "a\n   \nb",
+            "ends with a newline\n",
+            "cr\r\nlf",
+        ] {
+            let mut task = task("Details");
+            task.details = details.to_string();
+            assert_round_trips(&task);
+        }
+    }
+
+    #[test]
+    fn plain_items_stay_readable() {
+        let mut task = task("Water the plants");
+        task.list = Some("Home".to_string());
+        task.tags = Some("garden".to_string());
+        task.due = Some("2024-05-01".to_string());
+
+        let mut text = String::new();
+        render_task(&mut text, &task, ChecklistFormat::TodoTxt);
+        assert_eq!(text, "Water the plants +Home @garden due:2024-05-01\n");
+    }
+}
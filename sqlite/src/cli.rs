@@ -0,0 +1,69 @@
+use rocket::serde::json;
+use sqlx::SqlitePool;
+
+use crate::{
+    encryption::DetailsCipher,
+    errors::AppError,
+    events::TaskEvents,
+    sources::{self, Source},
+    workspace::{Workspace, WorkspaceDb},
+};
+
+const USAGE: &'static str = "usage:
+  sqlite import <taskwarrior|trello> <file> [--workspace <slug>] (defaults to `default`)
+  sqlite token <slug>";
+
+/// Runs the command of `args` (without the name of the binary) instead of launching Rocket.
+///
+/// NOTE(alex): A migration is easier from the shell (right after `task export > tasks.json`) than
+/// through the API, and big exports don't have to fit the body limits nor `sources::MAX_ITEMS`
+/// (the quota of the workspace still applies). `token` is how the API token of a workspace is
+/// found, the routes never hand it out.
+pub(crate) async fn run(db_pool: &SqlitePool, args: &[String]) -> Result<(), String> {
+    match args {
+        [command, slug] if command == "token" => {
+            let workspace = Workspace::find_by_slug(db_pool, slug)
+                .await
+                .map_err(|fail| fail.to_string())?
+                .ok_or_else(|| AppError::WorkspaceNotFound.to_string())?;
+            let token = workspace
+                .token(db_pool)
+                .await
+                .map_err(|fail| fail.to_string())?;
+            println!("{}", token);
+
+            Ok(())
+        }
+        [command, source, file, rest @ ..] if command == "import" => {
+            let source = Source::from_name(source)
+                .ok_or_else(|| format!("Unknown source `{}`!\n{}", source, USAGE))?;
+            let slug = match rest {
+                [] => "default",
+                [flag, slug] if flag == "--workspace" => slug.as_str(),
+                _ => return Err(USAGE.to_string()),
+            };
+
+            let export = std::fs::read_to_string(file)
+                .map_err(|fail| format!("Could not read `{}`: {}", file, fail))?;
+
+            let workspace = Workspace::find_by_slug(db_pool, slug)
+                .await
+                .map_err(|fail| fail.to_string())?
+                .ok_or_else(|| AppError::WorkspaceNotFound.to_string())?;
+            let cipher = DetailsCipher::from_figment(&rocket::Config::figment())?;
+            let workspace_db = WorkspaceDb::new(workspace, db_pool, cipher.as_ref());
+
+            let results =
+                sources::import(&workspace_db, &TaskEvents::default(), source, &export, None)
+                    .await
+                    .map_err(|fail| fail.to_string())?;
+
+            let output =
+                json::serde_json::to_string_pretty(&results).map_err(|fail| fail.to_string())?;
+            println!("{}", output);
+
+            Ok(())
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
@@ -0,0 +1,406 @@
+use chacha20poly1305::{
+    aead::{Aead, NewAead, Payload},
+    ChaCha20Poly1305, Nonce,
+};
+use hkdf::Hkdf;
+use log::{error, info};
+use rocket::{
+    fairing::{self, Fairing, Info, Kind},
+    figment::Figment,
+    request::{FromRequest, Outcome},
+    serde::Deserialize,
+    tokio, Build, Orbit, Request, Rocket,
+};
+use sha2::Sha256;
+use sqlx::SqlitePool;
+
+use crate::errors::AppError;
+
+const FIND_DETAILS_TO_ROTATE: &'static str =
+    include_str!("./../queries/find_details_to_rotate.sql");
+const ROTATE_DETAILS: &'static str = include_str!("./../queries/rotate_details.sql");
+const UNTRACK_TASK: &'static str = include_str!("./../queries/untrack_task.sql");
+const TRACK_TASK: &'static str = include_str!("./../queries/track_task.sql");
+
+/// Sealed details look like `enc:<key id>:<hex(nonce || ciphertext)>`, anything else is treated
+/// as plaintext (rows written before encryption was enabled).
+const SEALED_PREFIX: &'static str = "enc:";
+const NONCE_LEN: usize = 12;
+
+const KEY_INFO: &'static [u8] = b"hello-rocket task details key";
+const KEY_ID_INFO: &'static [u8] = b"hello-rocket task details key id";
+
+/// The `[global.details_encryption]` section of `Rocket.toml`.
+#[derive(Debug, Deserialize)]
+struct EncryptionConfig {
+    #[serde(default)]
+    enabled: bool,
+    /// Old `secret_key`s, rows sealed with them are re-encrypted with the current key on liftoff.
+    #[serde(default)]
+    previous_secret_keys: Vec<String>,
+    #[serde(default = "default_rotation_batch_size")]
+    rotation_batch_size: i64,
+}
+
+fn default_rotation_batch_size() -> i64 {
+    100
+}
+
+#[derive(Clone)]
+struct DetailsKey {
+    id: String,
+    cipher: ChaCha20Poly1305,
+}
+
+impl DetailsKey {
+    /// Derives the AEAD key (and a short id to tell keys apart) from a Rocket `secret_key`.
+    fn derive(secret_key: &str) -> Self {
+        let hkdf = Hkdf::<Sha256>::new(None, secret_key.as_bytes());
+
+        let mut key = [0u8; 32];
+        hkdf.expand(KEY_INFO, &mut key).unwrap();
+
+        let mut id = [0u8; 4];
+        hkdf.expand(KEY_ID_INFO, &mut id).unwrap();
+
+        Self {
+            id: to_hex(&id),
+            cipher: ChaCha20Poly1305::new_from_slice(&key).unwrap(),
+        }
+    }
+}
+
+/// Encrypts the `details` of a `Task` at rest, with a key derived from Rocket's `secret_key`.
+///
+/// NOTE(alex): Only `details` is encrypted, search (`find_by_pattern`) looks at `title` alone, so
+/// it keeps working without a plaintext index.
+#[derive(Clone)]
+pub(crate) struct DetailsCipher {
+    current: DetailsKey,
+    previous: Vec<DetailsKey>,
+}
+
+impl DetailsCipher {
+    /// The cipher of `[global.details_encryption]`, `None` when it isn't enabled.
+    ///
+    /// NOTE(alex): Takes the figment rather than the `Rocket`, the commands of `cli` write to the
+    /// database without launching it.
+    pub(crate) fn from_figment(figment: &Figment) -> Result<Option<Self>, String> {
+        Ok(Self::configured(figment)?.map(|(cipher, _)| cipher))
+    }
+
+    fn configured(figment: &Figment) -> Result<Option<(Self, EncryptionConfig)>, String> {
+        // NOTE(alex): Only a missing section means disabled, a malformed one fails the launch.
+        if figment.find_value("details_encryption").is_err() {
+            return Ok(None);
+        }
+
+        let config = figment
+            .extract_inner::<EncryptionConfig>("details_encryption")
+            .map_err(|fail| format!("Invalid `details_encryption` configuration: {}", fail))?;
+        if !config.enabled {
+            return Ok(None);
+        }
+
+        let secret_key = figment.extract_inner::<String>("secret_key").map_err(|_| {
+            "`details_encryption` is enabled, but there is no `secret_key` set!".to_string()
+        })?;
+
+        let cipher = Self {
+            current: DetailsKey::derive(&secret_key),
+            previous: config
+                .previous_secret_keys
+                .iter()
+                .map(|secret_key| DetailsKey::derive(secret_key))
+                .collect(),
+        };
+
+        Ok(Some((cipher, config)))
+    }
+
+    pub(crate) fn seal(
+        &self,
+        workspace_id: i64,
+        task_id: i64,
+        details: &str,
+    ) -> Result<String, AppError> {
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let aad = associated_data(workspace_id, task_id);
+        let ciphertext = self
+            .current
+            .cipher
+            .encrypt(
+                &Nonce::from(nonce),
+                Payload {
+                    msg: details.as_bytes(),
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| AppError::Internal)?;
+
+        Ok(format!(
+            "{}{}:{}{}",
+            SEALED_PREFIX,
+            self.current.id,
+            to_hex(&nonce),
+            to_hex(&ciphertext)
+        ))
+    }
+
+    pub(crate) fn open(
+        &self,
+        workspace_id: i64,
+        task_id: i64,
+        stored: &str,
+    ) -> Result<String, AppError> {
+        let sealed = match stored.strip_prefix(SEALED_PREFIX) {
+            Some(sealed) => sealed,
+            None => return Ok(stored.to_string()),
+        };
+
+        let (key_id, payload) = sealed.split_once(':').ok_or(AppError::Decryption)?;
+        let key = std::iter::once(&self.current)
+            .chain(self.previous.iter())
+            .find(|key| key.id == key_id)
+            .ok_or(AppError::Decryption)?;
+
+        let payload = from_hex(payload).ok_or(AppError::Decryption)?;
+        if payload.len() < NONCE_LEN {
+            return Err(AppError::Decryption);
+        }
+
+        let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+        let mut nonce_bytes = [0u8; NONCE_LEN];
+        nonce_bytes.copy_from_slice(nonce);
+
+        let aad = associated_data(workspace_id, task_id);
+        let plaintext = key
+            .cipher
+            .decrypt(
+                &Nonce::from(nonce_bytes),
+                Payload {
+                    msg: ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| AppError::Decryption)?;
+
+        String::from_utf8(plaintext).map_err(|_| AppError::Decryption)
+    }
+
+    /// Re-encrypts every row that isn't sealed with the current key, in batches of `batch_size`.
+    ///
+    /// NOTE(alex): This is a maintenance job, so it goes straight to the pool and runs across
+    /// every workspace, it only ever moves ciphertext around.
+    async fn rotate(&self, db_pool: &SqlitePool, batch_size: i64) -> Result<u64, AppError> {
+        let current = format!("{}{}:%", SEALED_PREFIX, self.current.id);
+        let mut last_id = 0;
+        let mut rotated = 0;
+
+        loop {
+            let rows: Vec<(i64, i64, String)> = sqlx::query_as(FIND_DETAILS_TO_ROTATE)
+                .bind(&current)
+                .bind(last_id)
+                .bind(batch_size)
+                .fetch_all(db_pool)
+                .await?;
+
+            if rows.is_empty() {
+                break;
+            }
+
+            for (id, workspace_id, details) in rows {
+                last_id = id;
+
+                let sealed = match self
+                    .open(workspace_id, id, &details)
+                    .and_then(|plain| self.seal(workspace_id, id, &plain))
+                {
+                    Ok(sealed) => sealed,
+                    Err(fail) => {
+                        error!("Could not re-encrypt details of task `{}`: {}", id, fail);
+                        continue;
+                    }
+                };
+
+                // NOTE(alex): Only replaces the row if nobody updated it in the meantime, as an
+                // `Untracked` task, it's the same details so its `version` and `seq` stay.
+                let mut tx = db_pool.begin().await?;
+                sqlx::query(UNTRACK_TASK)
+                    .bind(workspace_id)
+                    .bind(id)
+                    .execute(&mut tx)
+                    .await?;
+                let result = sqlx::query(ROTATE_DETAILS)
+                    .bind(&sealed)
+                    .bind(id)
+                    .bind(&details)
+                    .execute(&mut tx)
+                    .await?;
+                sqlx::query(TRACK_TASK)
+                    .bind(workspace_id)
+                    .bind(id)
+                    .execute(&mut tx)
+                    .await?;
+                tx.commit().await?;
+
+                rotated += result.rows_affected();
+            }
+        }
+
+        Ok(rotated)
+    }
+}
+
+/// NOTE(alex): The ciphertext is bound to its row, sealed details copied into another task (or
+/// another workspace) fail to open instead of showing up there.
+fn associated_data(workspace_id: i64, task_id: i64) -> Vec<u8> {
+    format!("{}:{}", workspace_id, task_id).into_bytes()
+}
+
+/// NOTE(alex): `Option<&State<DetailsCipher>>` would abort the launch when encryption is off (the
+/// sentinel of `State`), this guard forwards instead, take it as `Option<&DetailsCipher>`.
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for &'r DetailsCipher {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match req.rocket().state::<DetailsCipher>() {
+            Some(cipher) => Outcome::Success(cipher),
+            None => Outcome::Forward(()),
+        }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Opt-in encryption at rest for task details, see `[global.details_encryption]`.
+pub(crate) struct DetailsEncryption;
+
+#[rocket::async_trait]
+impl Fairing for DetailsEncryption {
+    fn info(&self) -> Info {
+        Info {
+            name: "Details encryption",
+            kind: Kind::Ignite | Kind::Liftoff,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        match DetailsCipher::configured(rocket.figment()) {
+            Ok(Some((cipher, config))) => Ok(rocket.manage(cipher).manage(config)),
+            Ok(None) => Ok(rocket),
+            Err(fail) => {
+                error!("{}", fail);
+                Err(rocket)
+            }
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let (cipher, config, db_pool) = match (
+            rocket.state::<DetailsCipher>(),
+            rocket.state::<EncryptionConfig>(),
+            rocket.state::<SqlitePool>(),
+        ) {
+            (Some(cipher), Some(config), Some(db_pool)) => {
+                (cipher.clone(), config, db_pool.clone())
+            }
+            _ => return,
+        };
+
+        let batch_size = config.rotation_batch_size;
+        tokio::spawn(async move {
+            match cipher.rotate(&db_pool, batch_size).await {
+                Ok(rotated) => info!("Re-encrypted details of {} tasks.", rotated),
+                Err(fail) => error!("Failed to re-encrypt task details: {}", fail),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::figment::{providers::Serialized, Figment};
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::*;
+
+    fn cipher(secret_key: &str, previous_secret_keys: &[&str]) -> DetailsCipher {
+        let figment = Figment::new()
+            .merge(Serialized::default("secret_key", secret_key))
+            .merge(Serialized::default("details_encryption.enabled", true))
+            .merge(Serialized::default(
+                "details_encryption.previous_secret_keys",
+                previous_secret_keys,
+            ));
+
+        DetailsCipher::from_figment(&figment).unwrap().unwrap()
+    }
+
+    #[test]
+    fn malformed_section_fails() {
+        let missing = Figment::new();
+        assert!(DetailsCipher::from_figment(&missing).unwrap().is_none());
+
+        let malformed =
+            Figment::new().merge(Serialized::default("details_encryption.enabled", "yes"));
+        assert!(DetailsCipher::from_figment(&malformed).is_err());
+    }
+
+    #[rocket::async_test]
+    async fn rotation_keeps_version_and_seq() {
+        let db_pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::schema::create_database(&db_pool).await.unwrap();
+
+        let old_key = "0123456789abcdef0123456789abcdef0123456789abcdef";
+        let old_cipher = cipher(old_key, &[]);
+        let task_id = sqlx::query("insert into Task (workspace_id, title) values (1, 'rotate')")
+            .execute(&db_pool)
+            .await
+            .unwrap()
+            .last_insert_rowid();
+        sqlx::query("update Task set details = $1 where id = $2")
+            .bind(old_cipher.seal(1, task_id, "secret").unwrap())
+            .bind(task_id)
+            .execute(&db_pool)
+            .await
+            .unwrap();
+
+        let query = "select version, seq, (select count(*) from TaskChange), details from Task";
+        let (version, seq, changes, sealed): (i64, i64, i64, String) =
+            sqlx::query_as(query).fetch_one(&db_pool).await.unwrap();
+
+        let new_cipher = cipher(
+            "fedcba9876543210fedcba9876543210fedcba9876543210",
+            &[old_key],
+        );
+        assert_eq!(new_cipher.rotate(&db_pool, 10).await.unwrap(), 1);
+
+        let (rotated_version, rotated_seq, rotated_changes, resealed): (i64, i64, i64, String) =
+            sqlx::query_as(query).fetch_one(&db_pool).await.unwrap();
+        assert_ne!(resealed, sealed);
+        assert_eq!(new_cipher.open(1, task_id, &resealed).unwrap(), "secret");
+        assert_eq!(
+            (rotated_version, rotated_seq, rotated_changes),
+            (version, seq, changes)
+        );
+    }
+}
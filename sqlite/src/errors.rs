@@ -1,5 +1,6 @@
 use std::io::Cursor;
 
+use fairings::{PatchError, PreconditionFailed};
 use rocket::{
     http::{ContentType, Status},
     response::Responder,
@@ -16,6 +17,45 @@ pub(crate) enum AppError {
     #[error("`{0}` id not found!")]
     IdNotFound(i64),
 
+    #[error("Workspace not found!")]
+    WorkspaceNotFound,
+
+    #[error("Invalid workspace token!")]
+    InvalidToken,
+
+    #[error("Token does not belong to workspace `{0}`!")]
+    WorkspaceMismatch(String),
+
+    #[error("Workspace reached its quota of `{0}` tasks!")]
+    QuotaExceeded(i64),
+
+    #[error("Task was changed by someone else in the meantime!")]
+    PreconditionFailed,
+
+    #[error("Invalid task: {0}")]
+    InvalidTask(String),
+
+    #[error("Patches must be `application/merge-patch+json` or `application/json-patch+json`!")]
+    UnsupportedPatch,
+
+    #[error("None of the `Accept`ed formats is supported (JSON, MessagePack, CBOR, CSV or YAML)!")]
+    NotAcceptable,
+
+    #[error("Bodies must be JSON, MessagePack, CBOR, CSV or YAML!")]
+    UnsupportedFormat,
+
+    #[error("Checklists are `todotxt` (`text/plain`) or `markdown` (`text/markdown`)!")]
+    UnsupportedChecklist,
+
+    #[error("Bulk action affects `{0}` tasks, send `confirm: true` to apply it!")]
+    ConfirmationRequired(usize),
+
+    #[error("Too many requests, retry in `{0}` seconds!")]
+    RateLimited(u64),
+
+    #[error("Failed to decrypt task details!")]
+    Decryption,
+
     #[error("Internal server error!")]
     Internal,
 
@@ -26,6 +66,23 @@ pub(crate) enum AppError {
     Sql(#[from] sqlx::Error),
 }
 
+impl From<PreconditionFailed> for AppError {
+    fn from(_: PreconditionFailed) -> Self {
+        AppError::PreconditionFailed
+    }
+}
+
+impl From<PatchError> for AppError {
+    fn from(fail: PatchError) -> Self {
+        match fail {
+            PatchError::UnsupportedPatch => AppError::UnsupportedPatch,
+            PatchError::TooLarge => AppError::Internal,
+            PatchError::IO(fail) => AppError::IO(fail),
+            PatchError::Invalid(reason) => AppError::InvalidTask(reason),
+        }
+    }
+}
+
 impl<'r> Responder<'r, 'static> for AppError {
     fn respond_to(self, _request: &'r rocket::Request<'_>) -> rocket::response::Result<'static> {
         let status = match self {
@@ -34,6 +91,19 @@ impl<'r> Responder<'r, 'static> for AppError {
             // EmptyTitle, but we never got to this error proper.
             AppError::EmptyTitle => Status::UnprocessableEntity,
             AppError::IdNotFound(_) => Status::NotFound,
+            AppError::WorkspaceNotFound => Status::NotFound,
+            AppError::InvalidToken => Status::Unauthorized,
+            AppError::WorkspaceMismatch(_) => Status::Forbidden,
+            AppError::QuotaExceeded(_) => Status::Forbidden,
+            AppError::PreconditionFailed => Status::PreconditionFailed,
+            AppError::InvalidTask(_) => Status::UnprocessableEntity,
+            AppError::UnsupportedPatch => Status::UnsupportedMediaType,
+            AppError::NotAcceptable => Status::NotAcceptable,
+            AppError::UnsupportedFormat => Status::UnsupportedMediaType,
+            AppError::UnsupportedChecklist => Status::UnsupportedMediaType,
+            AppError::ConfirmationRequired(_) => Status::PreconditionRequired,
+            AppError::RateLimited(_) => Status::TooManyRequests,
+            AppError::Decryption => Status::InternalServerError,
             AppError::Internal => Status::InternalServerError,
             AppError::IO(_) => Status::InternalServerError,
             AppError::Sql(_) => Status::InternalServerError,
@@ -0,0 +1,32 @@
+use fairings::{ETagged, Preconditions};
+
+use crate::{errors::AppError, models::Task};
+
+/// Strong `ETag` of a task, its `version` changes on every mutation.
+impl ETagged for Task {
+    fn etag(&self) -> String {
+        format!("\"{}\"", self.version)
+    }
+}
+
+/// Checks `If-Match` against the `current` task, and returns the version a conditional write must
+/// still find to apply (`None` when the request is unconditional).
+pub(crate) fn expected_version(
+    preconditions: &Preconditions,
+    current: Option<&Task>,
+) -> Result<Option<i64>, AppError> {
+    let conditional = preconditions.check_match(current)?;
+
+    Ok(current.filter(|_| conditional).map(|task| task.version))
+}
+
+/// A conditional write that changed nothing either lost the race against another write (the task
+/// moved past `expected`), or simply had nothing to do.
+pub(crate) fn check_unchanged(expected: Option<i64>, task: Option<&Task>) -> Result<(), AppError> {
+    match expected {
+        Some(expected) if task.map(|task| task.version) != Some(expected) => {
+            Err(AppError::PreconditionFailed)
+        }
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,148 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rocket::{
+    request::{FromRequest, Outcome},
+    serde::Serialize,
+    tokio::sync::broadcast::{self, Receiver, Sender},
+    Request,
+};
+
+use crate::models::Task;
+
+/// How many events are kept around for clients resuming with `Last-Event-ID`.
+const REPLAY_CAPACITY: usize = 256;
+const CHANNEL_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum TaskEventKind {
+    Created,
+    Updated,
+    Deleted,
+    Done,
+    Undone,
+}
+
+impl TaskEventKind {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            TaskEventKind::Created => "created",
+            TaskEventKind::Updated => "updated",
+            TaskEventKind::Deleted => "deleted",
+            TaskEventKind::Done => "done",
+            TaskEventKind::Undone => "undone",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct TaskEvent {
+    pub(crate) id: u64,
+    #[serde(skip)]
+    pub(crate) workspace_id: i64,
+    pub(crate) kind: TaskEventKind,
+    pub(crate) task: Task,
+}
+
+/// Change feed for tasks, every mutating handler publishes here.
+///
+/// NOTE(alex): Event ids are global (shared by every workspace), subscribers filter by their own
+/// workspace. They start at the boot time in microseconds, so the ids of a new boot are above the
+/// ones clients remember from the previous one. Publishing and subscribing both hold the `replay`
+/// lock, so a subscriber never misses (or sees twice) an event published while it was catching
+/// up. Clones share the same feed.
+#[derive(Clone)]
+pub(crate) struct TaskEvents {
+    next_id: Arc<AtomicU64>,
+    sender: Sender<TaskEvent>,
+    replay: Arc<Mutex<VecDeque<TaskEvent>>>,
+}
+
+impl Default for TaskEvents {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+
+        let boot_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(1, |since| since.as_micros() as u64);
+
+        Self {
+            next_id: Arc::new(AtomicU64::new(boot_epoch)),
+            sender,
+            replay: Arc::new(Mutex::new(VecDeque::with_capacity(REPLAY_CAPACITY))),
+        }
+    }
+}
+
+impl TaskEvents {
+    pub(crate) fn publish(&self, workspace_id: i64, kind: TaskEventKind, task: Task) {
+        let mut replay = self.replay.lock().unwrap();
+
+        let event = TaskEvent {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            workspace_id,
+            kind,
+            task,
+        };
+
+        if replay.len() == REPLAY_CAPACITY {
+            replay.pop_front();
+        }
+        replay.push_back(event.clone());
+
+        // NOTE(alex): Fails only when nobody is listening, which is fine.
+        let _ = self.sender.send(event);
+    }
+
+    /// Events of `workspace_id` after `last_event_id` still in the replay buffer, the id of the
+    /// last event the subscriber has (live events at or below it are skipped), and a receiver for
+    /// everything published from now on.
+    pub(crate) fn subscribe(
+        &self,
+        workspace_id: i64,
+        last_event_id: Option<u64>,
+    ) -> (Vec<TaskEvent>, Option<u64>, Receiver<TaskEvent>) {
+        let replay = self.replay.lock().unwrap();
+        let receiver = self.sender.subscribe();
+
+        // NOTE(alex): An id this feed never handed out (another boot, a clock that went back)
+        // would filter out every new event, so the subscriber starts over instead.
+        let last_event_id = last_event_id.filter(|id| *id < self.next_id.load(Ordering::Relaxed));
+
+        let missed = match last_event_id {
+            Some(last_event_id) => replay
+                .iter()
+                .filter(|event| event.workspace_id == workspace_id && event.id > last_event_id)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        let last_sent = missed.last().map(|event| event.id).or(last_event_id);
+
+        (missed, last_sent, receiver)
+    }
+}
+
+/// The `Last-Event-ID` header browsers send when reconnecting to an `EventSource`.
+pub(crate) struct LastEventId(pub(crate) Option<u64>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let last_event_id = req
+            .headers()
+            .get_one("Last-Event-ID")
+            .and_then(|id| id.trim().parse().ok());
+
+        Outcome::Success(LastEventId(last_event_id))
+    }
+}
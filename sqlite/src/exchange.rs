@@ -0,0 +1,441 @@
+use rocket::{
+    futures::{Stream, StreamExt, TryStreamExt},
+    serde::Serialize,
+};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use crate::{
+    batch::Operation,
+    errors::AppError,
+    events::{TaskEventKind, TaskEvents},
+    models::{InsertTask, Task},
+    workspace::{Scoped, WorkspaceDb, WorkspaceTx},
+};
+
+const FIND_EXPORTED_TASKS: Scoped =
+    Scoped::new(include_str!("./../queries/find_exported_tasks.sql"));
+const SET_IMPORTED_FIELDS: Scoped =
+    Scoped::new(include_str!("./../queries/set_imported_fields.sql"));
+const SET_COMPLETED_AT: Scoped = Scoped::new(include_str!("./../queries/set_completed_at.sql"));
+const INSERT_TAG_IF_MISSING: Scoped =
+    Scoped::new(include_str!("./../queries/insert_tag_if_missing.sql"));
+const TAG_TASK_BY_NAME: Scoped = Scoped::new(include_str!("./../queries/tag_task_by_name.sql"));
+const INSERT_LIST_IF_MISSING: Scoped =
+    Scoped::new(include_str!("./../queries/insert_list_if_missing.sql"));
+const FIND_LIST_ID_BY_NAME: Scoped =
+    Scoped::new(include_str!("./../queries/find_list_id_by_name.sql"));
+const REPLACE: Scoped = Scoped::new(include_str!("./../queries/replace.sql"));
+const UNTRACK_TASK: Scoped = Scoped::new(include_str!("./../queries/untrack_task.sql"));
+const TRACK_TASK: Scoped = Scoped::new(include_str!("./../queries/track_task.sql"));
+const FIND_IMPORTED_SOURCE: Scoped =
+    Scoped::new(include_str!("./../queries/find_imported_source.sql"));
+const INSERT_IMPORTED_SOURCE: Scoped =
+    Scoped::new(include_str!("./../queries/insert_imported_source.sql"));
+
+/// `YYYY-MM-DD`.
+pub(crate) fn is_date(value: &str) -> bool {
+    let bytes = value.as_bytes();
+
+    bytes.len() == 10
+        && bytes.iter().enumerate().all(|(index, byte)| match index {
+            4 | 7 => *byte == b'-',
+            _ => byte.is_ascii_digit(),
+        })
+}
+
+/// A date, or a `YYYY-MM-DDTHH:MM:SS` (with an optional `Z`), what `Task.due` holds.
+pub(crate) fn is_due(value: &str) -> bool {
+    match value.split_once('T') {
+        None => is_date(value),
+        Some((date, time)) => {
+            let time = time.strip_suffix('Z').unwrap_or(time).as_bytes();
+
+            is_date(date)
+                && time.len() == 8
+                && time.iter().enumerate().all(|(index, byte)| match index {
+                    2 | 5 => *byte == b':',
+                    _ => byte.is_ascii_digit(),
+                })
+        }
+    }
+}
+
+/// The basic format of ISO 8601 (iCalendar, Taskwarrior), `YYYYMMDD` or `YYYYMMDDTHHMMSS` (with an
+/// optional `Z`), as [`is_due`] wants it.
+pub(crate) fn from_basic(value: &str) -> Option<String> {
+    let digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+
+    match (value.len(), value.split_once('T')) {
+        (8, None) if digits(value) => Some(format!(
+            "{}-{}-{}",
+            &value[0..4],
+            &value[4..6],
+            &value[6..8]
+        )),
+        (15, Some((date, time))) | (16, Some((date, time)))
+            if date.len() == 8 && digits(date) && digits(time.trim_end_matches('Z')) =>
+        {
+            Some(format!(
+                "{}-{}-{}T{}:{}:{}{}",
+                &date[0..4],
+                &date[4..6],
+                &date[6..8],
+                &time[0..2],
+                &time[2..4],
+                &time[4..6],
+                if time.ends_with('Z') { "Z" } else { "" }
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// A task with everything the export formats (iCalendar, todo.txt, Markdown) write, see
+/// `find_exported_tasks.sql`.
+#[derive(Debug, FromRow)]
+pub(crate) struct ExportedTask {
+    pub(crate) id: i64,
+    pub(crate) title: String,
+    pub(crate) details: String,
+    pub(crate) due: Option<String>,
+    pub(crate) priority: Option<String>,
+    pub(crate) created_on: Option<String>,
+    pub(crate) version: i64,
+    pub(crate) list: Option<String>,
+    pub(crate) completed_at: Option<String>,
+    /// Joined with the unit separator, see [`ExportedTask::tags`].
+    pub(crate) tags: Option<String>,
+    /// When it was exported, as an iCalendar `DATE-TIME`.
+    pub(crate) stamp: String,
+}
+
+impl ExportedTask {
+    /// Every task of the workspace, ongoing and done, with opened `details`, one row at a time
+    /// (read in `tx`).
+    pub(crate) fn fetch<'t>(
+        workspace_db: &WorkspaceDb<'t>,
+        tx: &'t mut WorkspaceTx,
+    ) -> impl Stream<Item = Result<Self, AppError>> + Send + 't {
+        let cipher = workspace_db.cipher();
+        let workspace_id = workspace_db.workspace.id;
+
+        tx.query_as::<Self>(FIND_EXPORTED_TASKS)
+            .fetch()
+            .map(move |task| {
+                let mut task = task?;
+                if let Some(cipher) = cipher {
+                    task.details = cipher.open(workspace_id, task.id, &task.details)?;
+                }
+
+                Ok(task)
+            })
+    }
+
+    pub(crate) async fn find_all(workspace_db: &WorkspaceDb<'_>) -> Result<Vec<Self>, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let tasks = Self::fetch(workspace_db, &mut tx).try_collect().await;
+
+        tasks
+    }
+
+    pub(crate) fn tags(&self) -> Vec<&str> {
+        self.tags
+            .as_deref()
+            .map_or_else(Vec::new, |tags| tags.split('\u{1f}').collect())
+    }
+}
+
+/// What [`ImportedTask::upsert`] did.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Upserted {
+    Inserted,
+    Updated,
+    Unchanged,
+}
+
+/// A task that was imported before, as it is now, see `find_imported_source.sql`.
+#[derive(Debug, FromRow)]
+struct ImportedSource {
+    id: i64,
+    list_id: Option<i64>,
+    title: String,
+    details: String,
+    due: Option<String>,
+    priority: Option<String>,
+    created_on: Option<String>,
+    done: bool,
+}
+
+/// A task read from one of the import formats, the list and tags are named, and created when
+/// missing.
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub(crate) struct ImportedTask {
+    pub(crate) title: String,
+    pub(crate) details: String,
+    pub(crate) list: Option<String>,
+    pub(crate) tags: Vec<String>,
+    pub(crate) due: Option<String>,
+    pub(crate) priority: Option<String>,
+    pub(crate) created_on: Option<String>,
+    pub(crate) done: bool,
+    /// Defaults to the time of the import, for a done task.
+    pub(crate) completed_at: Option<String>,
+}
+
+impl ImportedTask {
+    fn insert_task(&self, list_id: Option<i64>) -> InsertTask {
+        InsertTask {
+            non_empty_title: self.title.clone(),
+            details: self.details.clone(),
+            list_id,
+        }
+    }
+
+    /// What can be checked without writing anything, for the previews of a dry run.
+    pub(crate) fn validate(&self) -> Result<(), AppError> {
+        self.insert_task(None).validate()
+    }
+
+    /// Inserts the task, with its list and tags, and marks it done, all or nothing.
+    pub(crate) async fn insert(
+        &self,
+        workspace_db: &WorkspaceDb<'_>,
+        task_events: &TaskEvents,
+    ) -> Result<Task, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let (task, events) = self.apply(workspace_db, &mut tx).await?;
+        tx.commit().await?;
+
+        for (kind, task) in events {
+            task_events.publish(workspace_db.workspace.id, kind, task);
+        }
+
+        Ok(task)
+    }
+
+    /// [`ImportedTask::insert`] in the transaction of a bigger import, the events are for after
+    /// it's committed.
+    pub(crate) async fn apply(
+        &self,
+        workspace_db: &WorkspaceDb<'_>,
+        tx: &mut WorkspaceTx,
+    ) -> Result<(Task, Vec<(TaskEventKind, Task)>), AppError> {
+        let mut events = Vec::new();
+
+        // NOTE(alex): The list goes in with the insert, setting it afterwards would bump the
+        // `version` of a task that was just created.
+        let list_id = self.list_id(tx).await?;
+
+        let applied = Operation::Insert(self.insert_task(list_id))
+            .apply(workspace_db, tx)
+            .await?;
+        events.extend(applied.event);
+        let mut task = applied.task.ok_or(AppError::Internal)?;
+
+        // NOTE(alex): Same for the fields and tags, the task is `Untracked` until it's complete.
+        tx.query(UNTRACK_TASK).bind(task.id).execute().await?;
+        tx.query(SET_IMPORTED_FIELDS)
+            .bind(task.id)
+            .bind(&self.due)
+            .bind(&self.priority)
+            .bind(&self.created_on)
+            .execute()
+            .await?;
+        self.tag(tx, task.id).await?;
+        tx.query(TRACK_TASK).bind(task.id).execute().await?;
+
+        if self.done {
+            let applied = Operation::Done {
+                id: task.id,
+                version: None,
+            }
+            .apply(workspace_db, tx)
+            .await?;
+            events.extend(applied.event);
+            task = applied.task.unwrap_or(task);
+
+            if let Some(completed_at) = &self.completed_at {
+                tx.query(SET_COMPLETED_AT)
+                    .bind(task.id)
+                    .bind(completed_at)
+                    .execute()
+                    .await?;
+            }
+        }
+
+        Ok((task, events))
+    }
+
+    /// Inserts the task the first time `source_id` is imported from `source`, and brings the task
+    /// it became in line with it on every import after that, all or nothing.
+    ///
+    /// NOTE(alex): The source wins, what was edited here since the last import (title, details,
+    /// list, due date, priority, done) is overwritten. Tags are only ever added, never removed.
+    pub(crate) async fn upsert(
+        &self,
+        workspace_db: &WorkspaceDb<'_>,
+        task_events: &TaskEvents,
+        source: &str,
+        source_id: &str,
+    ) -> Result<(Upserted, Task), AppError> {
+        let mut tx = workspace_db.begin().await?;
+
+        let imported: Option<ImportedSource> = tx
+            .query_as(FIND_IMPORTED_SOURCE)
+            .bind(source)
+            .bind(source_id)
+            .fetch_optional()
+            .await?;
+
+        let (upserted, task, events) = match imported {
+            Some(imported) => self.reapply(workspace_db, &mut tx, imported).await?,
+            None => {
+                let (task, events) = self.apply(workspace_db, &mut tx).await?;
+                tx.query(INSERT_IMPORTED_SOURCE)
+                    .bind(source)
+                    .bind(source_id)
+                    .bind(task.id)
+                    .execute()
+                    .await?;
+
+                (Upserted::Inserted, task, events)
+            }
+        };
+
+        tx.commit().await?;
+
+        for (kind, task) in events {
+            task_events.publish(workspace_db.workspace.id, kind, task);
+        }
+
+        Ok((upserted, task))
+    }
+
+    /// Only writes what differs from `imported`, so a re-import of the same export changes nothing
+    /// (not even the `version`).
+    async fn reapply(
+        &self,
+        workspace_db: &WorkspaceDb<'_>,
+        tx: &mut WorkspaceTx,
+        imported: ImportedSource,
+    ) -> Result<(Upserted, Task, Vec<(TaskEventKind, Task)>), AppError> {
+        self.validate()?;
+
+        let mut events = Vec::new();
+        let mut updated = false;
+        let id = imported.id;
+
+        let details = match workspace_db.cipher() {
+            Some(cipher) => cipher.open(workspace_db.workspace.id, id, &imported.details)?,
+            None => imported.details,
+        };
+        let list_id = self.list_id(tx).await?;
+
+        if imported.list_id != list_id || imported.title != self.title || details != self.details {
+            tx.query(REPLACE)
+                .bind(list_id)
+                .bind(&self.title)
+                .bind(&Task::sealed_details(workspace_db, id, &self.details)?)
+                .bind(id)
+                .bind(None::<i64>)
+                .execute()
+                .await?;
+            updated = true;
+        }
+
+        if (&imported.due, &imported.priority, &imported.created_on)
+            != (&self.due, &self.priority, &self.created_on)
+        {
+            tx.query(SET_IMPORTED_FIELDS)
+                .bind(id)
+                .bind(&self.due)
+                .bind(&self.priority)
+                .bind(&self.created_on)
+                .execute()
+                .await?;
+            updated = true;
+        }
+
+        updated |= self.tag(tx, id).await?;
+
+        // NOTE(alex): A single `Updated` for whatever changed, before the completion (if any).
+        if updated {
+            let task = Operation::find_by_id(workspace_db, tx, id)
+                .await?
+                .ok_or(AppError::IdNotFound(id))?;
+            events.push((TaskEventKind::Updated, task));
+        }
+
+        let mut upserted = if updated {
+            Upserted::Updated
+        } else {
+            Upserted::Unchanged
+        };
+
+        if self.done != imported.done {
+            let operation = if self.done {
+                Operation::Done { id, version: None }
+            } else {
+                Operation::Undo { id, version: None }
+            };
+            let applied = operation.apply(workspace_db, tx).await?;
+            events.extend(applied.event);
+
+            if let (true, Some(completed_at)) = (self.done, &self.completed_at) {
+                tx.query(SET_COMPLETED_AT)
+                    .bind(id)
+                    .bind(completed_at)
+                    .execute()
+                    .await?;
+            }
+            upserted = Upserted::Updated;
+        }
+
+        let task = Operation::find_by_id(workspace_db, tx, id)
+            .await?
+            .ok_or(AppError::IdNotFound(id))?;
+
+        Ok((upserted, task, events))
+    }
+
+    /// The id of the list, created when missing.
+    async fn list_id(&self, tx: &mut WorkspaceTx) -> Result<Option<i64>, AppError> {
+        let list = match &self.list {
+            Some(list) => list,
+            None => return Ok(None),
+        };
+
+        tx.query(INSERT_LIST_IF_MISSING)
+            .bind(list)
+            .execute()
+            .await?;
+        let (list_id,): (i64,) = tx
+            .query_as(FIND_LIST_ID_BY_NAME)
+            .bind(list)
+            .fetch_one()
+            .await?;
+
+        Ok(Some(list_id))
+    }
+
+    /// Tags the task, creating the tags that are missing, `true` when it wasn't tagged with all of
+    /// them already.
+    async fn tag(&self, tx: &mut WorkspaceTx, task_id: i64) -> Result<bool, AppError> {
+        let mut tagged = false;
+
+        for tag in &self.tags {
+            tx.query(INSERT_TAG_IF_MISSING).bind(tag).execute().await?;
+            let result = tx
+                .query(TAG_TASK_BY_NAME)
+                .bind(task_id)
+                .bind(tag)
+                .execute()
+                .await?;
+
+            tagged |= result.rows_affected() > 0;
+        }
+
+        Ok(tagged)
+    }
+}
@@ -0,0 +1,683 @@
+use async_graphql::{Context, Object, Result, Schema, SimpleObject, Subscription};
+use fairings::CsrfToken;
+use log::error;
+use rocket::{
+    fairing::{self, Fairing, Info, Kind},
+    futures::{stream, Stream},
+    get,
+    http::Header,
+    post,
+    request::{FromRequest, Outcome},
+    response::{self, content, Responder},
+    serde::{
+        json::{serde_json, Json},
+        Deserialize,
+    },
+    tokio::sync::broadcast::error::RecvError,
+    Build, Request, Rocket, State,
+};
+use sqlx::SqlitePool;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use crate::{
+    encryption::DetailsCipher,
+    etag::check_unchanged,
+    events::{TaskEvent, TaskEventKind, TaskEvents},
+    models::{Change, InsertTag, InsertTask, InsertTaskList, Tag, Task, TaskList, UpdateTask},
+    workspace::{Workspace, WorkspaceDb},
+};
+
+const GRAPHIQL: &'static str = include_str!("./../strings/graphiql.html");
+
+/// GraphiQL pulls its scripts and styles from unpkg, and talks to the websocket port.
+const GRAPHIQL_CSP: &'static str = "default-src 'self'; \
+    script-src 'self' 'unsafe-inline' https://unpkg.com; \
+    style-src 'self' 'unsafe-inline' https://unpkg.com; \
+    img-src 'self' data: https://graphql.org; \
+    connect-src 'self' ws: wss:; \
+    frame-ancestors 'none'";
+
+pub(crate) type TaskSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
+
+/// The `[global.graphql]` section of `Rocket.toml`, queries nested deeper (or costing more, every
+/// field is `1`) than this are rejected before they run.
+#[derive(Debug, Clone, Deserialize)]
+struct GraphQLConfig {
+    #[serde(default = "default_max_depth")]
+    max_depth: usize,
+    #[serde(default = "default_max_complexity")]
+    max_complexity: usize,
+}
+
+fn default_max_depth() -> usize {
+    8
+}
+
+fn default_max_complexity() -> usize {
+    250
+}
+
+impl Default for GraphQLConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: default_max_depth(),
+            max_complexity: default_max_complexity(),
+        }
+    }
+}
+
+/// What the resolvers run against, owned (unlike `WorkspaceDb`) so it outlives the request for
+/// subscriptions.
+pub(crate) struct GraphQLContext {
+    workspace: Workspace,
+    db_pool: SqlitePool,
+    cipher: Option<DetailsCipher>,
+    task_events: TaskEvents,
+}
+
+impl GraphQLContext {
+    pub(crate) fn new(
+        workspace: Workspace,
+        db_pool: SqlitePool,
+        cipher: Option<DetailsCipher>,
+        task_events: TaskEvents,
+    ) -> Self {
+        Self {
+            workspace,
+            db_pool,
+            cipher,
+            task_events,
+        }
+    }
+
+    fn workspace_db(&self) -> WorkspaceDb<'_> {
+        WorkspaceDb::new(self.workspace.clone(), &self.db_pool, self.cipher.as_ref())
+    }
+
+    fn publish(&self, kind: TaskEventKind, task: &Task) {
+        self.task_events
+            .publish(self.workspace.id, kind, task.clone());
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "Workspace")]
+struct WorkspaceObject {
+    id: i64,
+    slug: String,
+    max_tasks: i64,
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "TaskList")]
+struct TaskListObject {
+    id: i64,
+    name: String,
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "Tag")]
+struct TagObject {
+    id: i64,
+    name: String,
+}
+
+impl From<Workspace> for WorkspaceObject {
+    fn from(workspace: Workspace) -> Self {
+        Self {
+            id: workspace.id,
+            slug: workspace.slug,
+            max_tasks: workspace.max_tasks,
+        }
+    }
+}
+
+impl From<TaskList> for TaskListObject {
+    fn from(list: TaskList) -> Self {
+        Self {
+            id: list.id,
+            name: list.name,
+        }
+    }
+}
+
+impl From<Tag> for TagObject {
+    fn from(tag: Tag) -> Self {
+        Self {
+            id: tag.id,
+            name: tag.name,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "HistoryEntry")]
+struct HistoryObject {
+    seq: i64,
+    kind: String,
+    /// The title right after the change.
+    title: String,
+    changed_at: String,
+}
+
+impl From<Change> for HistoryObject {
+    fn from(change: Change) -> Self {
+        Self {
+            seq: change.seq,
+            kind: change.kind,
+            title: change.title,
+            changed_at: change.changed_at,
+        }
+    }
+}
+
+/// What a list of tasks asks for of every task, loaded up front with one query per field instead
+/// of one per task. `None` when the field isn't selected.
+#[derive(Default)]
+struct Preloaded {
+    done: Option<HashSet<i64>>,
+    tags: Option<HashMap<i64, Vec<Tag>>>,
+    history: Option<HashMap<i64, Vec<Change>>>,
+}
+
+impl Preloaded {
+    /// NOTE(alex): Batches the way a `DataLoader` would, but from the look-ahead of the list
+    /// field, so it needs neither the `dataloader` feature nor a request-scoped loader.
+    async fn load(ctx: &Context<'_>, tasks: &[Task]) -> Result<Self> {
+        let mut preloaded = Preloaded::default();
+        if tasks.is_empty() {
+            return Ok(preloaded);
+        }
+
+        let context = ctx.data::<GraphQLContext>()?;
+        let workspace_db = context.workspace_db();
+        let look_ahead = ctx.look_ahead();
+        let ids: Vec<i64> = tasks.iter().map(|task| task.id).collect();
+
+        if look_ahead.field("done").exists() {
+            preloaded.done = Some(Task::find_done_among(&workspace_db, &ids).await?);
+        }
+
+        if look_ahead.field("tags").exists() {
+            let mut tags: HashMap<i64, Vec<Tag>> = HashMap::new();
+            for (task_id, tag) in Tag::find_by_tasks(&workspace_db, &ids).await? {
+                tags.entry(task_id).or_default().push(tag);
+            }
+            preloaded.tags = Some(tags);
+        }
+
+        if look_ahead.field("history").exists() {
+            let mut history: HashMap<i64, Vec<Change>> = HashMap::new();
+            for change in Change::find_by_tasks(&workspace_db, &ids).await? {
+                history.entry(change.task_id).or_default().push(change);
+            }
+            preloaded.history = Some(history);
+        }
+
+        Ok(preloaded)
+    }
+}
+
+struct TaskObject {
+    task: Task,
+    preloaded: Arc<Preloaded>,
+}
+
+impl TaskObject {
+    fn new(task: Task) -> Self {
+        Self {
+            task,
+            preloaded: Arc::default(),
+        }
+    }
+
+    async fn load_all(ctx: &Context<'_>, tasks: Vec<Task>) -> Result<Vec<Self>> {
+        let preloaded = Arc::new(Preloaded::load(ctx, &tasks).await?);
+
+        Ok(tasks
+            .into_iter()
+            .map(|task| Self {
+                task,
+                preloaded: preloaded.clone(),
+            })
+            .collect())
+    }
+}
+
+#[Object(name = "Task")]
+impl TaskObject {
+    async fn id(&self) -> i64 {
+        self.task.id
+    }
+
+    async fn list_id(&self) -> Option<i64> {
+        self.task.list_id
+    }
+
+    async fn title(&self) -> &str {
+        &self.task.title
+    }
+
+    async fn details(&self) -> &str {
+        &self.task.details
+    }
+
+    /// Same as the `ETag` of `GET /tasks/{id}`, pass it as `version` to make a mutation
+    /// conditional.
+    async fn version(&self) -> i64 {
+        self.task.version
+    }
+
+    async fn done(&self, ctx: &Context<'_>) -> Result<bool> {
+        if let Some(done) = &self.preloaded.done {
+            return Ok(done.contains(&self.task.id));
+        }
+
+        let context = ctx.data::<GraphQLContext>()?;
+
+        Ok(Task::is_done(&context.workspace_db(), self.task.id).await?)
+    }
+
+    async fn tags(&self, ctx: &Context<'_>) -> Result<Vec<TagObject>> {
+        let tags = match &self.preloaded.tags {
+            Some(tags) => tags.get(&self.task.id).cloned().unwrap_or_default(),
+            None => {
+                let context = ctx.data::<GraphQLContext>()?;
+                Tag::find_by_task(&context.workspace_db(), self.task.id).await?
+            }
+        };
+
+        Ok(tags.into_iter().map(TagObject::from).collect())
+    }
+
+    /// Every change since the task was created, oldest first.
+    async fn history(&self, ctx: &Context<'_>) -> Result<Vec<HistoryObject>> {
+        let history = match &self.preloaded.history {
+            Some(history) => history.get(&self.task.id).cloned().unwrap_or_default(),
+            None => {
+                let context = ctx.data::<GraphQLContext>()?;
+                Change::find_by_tasks(&context.workspace_db(), &[self.task.id]).await?
+            }
+        };
+
+        Ok(history.into_iter().map(HistoryObject::from).collect())
+    }
+}
+
+struct TaskChange(TaskEvent);
+
+#[Object]
+impl TaskChange {
+    /// Same as the SSE event id.
+    async fn id(&self) -> u64 {
+        self.0.id
+    }
+
+    async fn kind(&self) -> &str {
+        self.0.kind.as_str()
+    }
+
+    /// The task right after the change.
+    async fn task(&self) -> TaskObject {
+        TaskObject::new(self.0.task.clone())
+    }
+}
+
+pub(crate) struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn workspace(&self, ctx: &Context<'_>) -> Result<WorkspaceObject> {
+        let context = ctx.data::<GraphQLContext>()?;
+
+        Ok(context.workspace.clone().into())
+    }
+
+    /// Every task, only the ones not done yet with `ongoing`, or the ones with a title like
+    /// `pattern`.
+    async fn tasks(
+        &self,
+        ctx: &Context<'_>,
+        pattern: Option<String>,
+        #[graphql(default)] ongoing: bool,
+    ) -> Result<Vec<TaskObject>> {
+        let context = ctx.data::<GraphQLContext>()?;
+        let workspace_db = context.workspace_db();
+
+        let tasks = match (pattern, ongoing) {
+            (Some(pattern), _) => Task::find_by_pattern(&workspace_db, &pattern).await?,
+            (None, true) => Task::find_ongoing(&workspace_db).await?,
+            (None, false) => Task::find_all(&workspace_db).await?,
+        };
+
+        TaskObject::load_all(ctx, tasks).await
+    }
+
+    async fn task(&self, ctx: &Context<'_>, id: i64) -> Result<Option<TaskObject>> {
+        let context = ctx.data::<GraphQLContext>()?;
+        let task = Task::find_by_id(&context.workspace_db(), id).await?;
+
+        Ok(task.map(TaskObject::new))
+    }
+
+    async fn lists(&self, ctx: &Context<'_>) -> Result<Vec<TaskListObject>> {
+        let context = ctx.data::<GraphQLContext>()?;
+        let lists = TaskList::find_all(&context.workspace_db()).await?;
+
+        Ok(lists.into_iter().map(TaskListObject::from).collect())
+    }
+
+    async fn tags(&self, ctx: &Context<'_>) -> Result<Vec<TagObject>> {
+        let context = ctx.data::<GraphQLContext>()?;
+        let tags = Tag::find_all(&context.workspace_db()).await?;
+
+        Ok(tags.into_iter().map(TagObject::from).collect())
+    }
+}
+
+/// NOTE(alex): Mirrors `routes.rs`, `version` plays the part of `If-Match`.
+pub(crate) struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    async fn insert_task(
+        &self,
+        ctx: &Context<'_>,
+        title: String,
+        details: String,
+        list_id: Option<i64>,
+    ) -> Result<TaskObject> {
+        let context = ctx.data::<GraphQLContext>()?;
+        let insert_task = InsertTask {
+            non_empty_title: title,
+            details,
+            list_id,
+        };
+        insert_task.validate()?;
+
+        let task = insert_task.insert(&context.workspace_db()).await?;
+        context.publish(TaskEventKind::Created, &task);
+
+        Ok(TaskObject::new(task))
+    }
+
+    async fn update_task(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+        title: String,
+        details: String,
+        version: Option<i64>,
+    ) -> Result<Option<TaskObject>> {
+        let context = ctx.data::<GraphQLContext>()?;
+        let workspace_db = context.workspace_db();
+        let update_task = UpdateTask {
+            id,
+            new_title: title,
+            details,
+        };
+        update_task.validate()?;
+
+        let rows_affected = update_task.update(&workspace_db, version).await?;
+        let task = Task::find_by_id(&workspace_db, id).await?;
+
+        if rows_affected == 0 {
+            check_unchanged(version, task.as_ref())?;
+        } else if let Some(task) = &task {
+            context.publish(TaskEventKind::Updated, task);
+        }
+
+        Ok(task.map(TaskObject::new))
+    }
+
+    /// `false` when there was no task `id`.
+    async fn delete_task(&self, ctx: &Context<'_>, id: i64, version: Option<i64>) -> Result<bool> {
+        let context = ctx.data::<GraphQLContext>()?;
+        let workspace_db = context.workspace_db();
+
+        let task = Task::find_by_id(&workspace_db, id).await?;
+        let rows_affected = Task::delete(&workspace_db, id, version).await?;
+
+        if rows_affected == 0 {
+            let current = Task::find_by_id(&workspace_db, id).await?;
+            check_unchanged(version, current.as_ref())?;
+        } else if let Some(task) = &task {
+            context.publish(TaskEventKind::Deleted, task);
+        }
+
+        Ok(rows_affected > 0)
+    }
+
+    async fn done_task(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+        version: Option<i64>,
+    ) -> Result<Option<TaskObject>> {
+        let context = ctx.data::<GraphQLContext>()?;
+        let workspace_db = context.workspace_db();
+
+        let created_id = Task::done(&workspace_db, id, version).await?;
+        let task = Task::find_by_id(&workspace_db, id).await?;
+
+        if created_id == 0 {
+            check_unchanged(version, task.as_ref())?;
+        } else if let Some(task) = &task {
+            context.publish(TaskEventKind::Done, task);
+        }
+
+        Ok(task.map(TaskObject::new))
+    }
+
+    async fn undo_task(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+        version: Option<i64>,
+    ) -> Result<Option<TaskObject>> {
+        let context = ctx.data::<GraphQLContext>()?;
+        let workspace_db = context.workspace_db();
+
+        let num_modified = Task::undo(&workspace_db, id, version).await?;
+        let task = Task::find_by_id(&workspace_db, id).await?;
+
+        if num_modified == 0 {
+            check_unchanged(version, task.as_ref())?;
+        } else if let Some(task) = &task {
+            context.publish(TaskEventKind::Undone, task);
+        }
+
+        Ok(task.map(TaskObject::new))
+    }
+
+    async fn insert_list(&self, ctx: &Context<'_>, name: String) -> Result<TaskListObject> {
+        let context = ctx.data::<GraphQLContext>()?;
+        let list = InsertTaskList { name }
+            .insert(&context.workspace_db())
+            .await?;
+
+        Ok(list.into())
+    }
+
+    async fn insert_tag(&self, ctx: &Context<'_>, name: String) -> Result<TagObject> {
+        let context = ctx.data::<GraphQLContext>()?;
+        let tag = InsertTag { name }.insert(&context.workspace_db()).await?;
+
+        Ok(tag.into())
+    }
+
+    /// `false` when the task already had the tag.
+    async fn tag_task(&self, ctx: &Context<'_>, id: i64, tag_id: i64) -> Result<bool> {
+        let context = ctx.data::<GraphQLContext>()?;
+
+        Ok(Task::tag(&context.workspace_db(), id, tag_id).await? > 0)
+    }
+
+    async fn untag_task(&self, ctx: &Context<'_>, id: i64, tag_id: i64) -> Result<bool> {
+        let context = ctx.data::<GraphQLContext>()?;
+
+        Ok(Task::untag(&context.workspace_db(), id, tag_id).await? > 0)
+    }
+}
+
+pub(crate) struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Every change to a task of the workspace, from now on.
+    async fn task_changes(&self, ctx: &Context<'_>) -> Result<impl Stream<Item = TaskChange>> {
+        let context = ctx.data::<GraphQLContext>()?;
+        let workspace_id = context.workspace.id;
+        let (_, _, receiver) = context.task_events.subscribe(workspace_id, None);
+
+        Ok(stream::unfold(receiver, move |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) if event.workspace_id == workspace_id => {
+                        return Some((TaskChange(event), receiver))
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        }))
+    }
+}
+
+/// Builds the schema with the limits of `[global.graphql]`, queries and mutations are served by
+/// `POST /graphql`, and subscriptions by the `WebSocket` listener (at `/graphql`).
+pub(crate) struct GraphQL;
+
+#[rocket::async_trait]
+impl Fairing for GraphQL {
+    fn info(&self) -> Info {
+        Info {
+            name: "GraphQL",
+            kind: Kind::Ignite,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let config = match rocket.figment().find_value("graphql") {
+            Err(_) => GraphQLConfig::default(),
+            Ok(_) => match rocket.figment().extract_inner::<GraphQLConfig>("graphql") {
+                Ok(config) => config,
+                Err(fail) => {
+                    error!("Invalid `graphql` configuration: {}", fail);
+                    return Err(rocket);
+                }
+            },
+        };
+
+        let schema: TaskSchema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+            .limit_depth(config.max_depth)
+            .limit_complexity(config.max_complexity)
+            .finish();
+
+        Ok(rocket.manage(schema))
+    }
+}
+
+/// `ws://{host}:{websocket_port}`, where GraphiQL finds the subscriptions.
+pub(crate) struct WebSocketOrigin(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WebSocketOrigin {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let port = req
+            .rocket()
+            .figment()
+            .extract_inner::<u16>("websocket_port")
+            .ok();
+
+        let host = req
+            .headers()
+            .get_one("Host")
+            .and_then(|host| host.split(':').next());
+
+        Outcome::Success(WebSocketOrigin(match (host, port) {
+            (Some(host), Some(port)) => Some(format!("ws://{}:{}", host, port)),
+            _ => None,
+        }))
+    }
+}
+
+/// The GraphiQL page, with its own `Content-Security-Policy` (the default one blocks it).
+pub(crate) struct GraphiQL(String);
+
+impl<'r> Responder<'r, 'static> for GraphiQL {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = content::Html(self.0).respond_to(req)?;
+        response.set_header(Header::new("Content-Security-Policy", GRAPHIQL_CSP));
+
+        Ok(response)
+    }
+}
+
+#[utoipa::path(
+    tag = "graphql",
+    summary = "GraphQL queries and mutations",
+    request_body = Object,
+    responses(
+        (status = 200, description = "The GraphQL response, with its `errors`", body = Object),
+    )
+)]
+#[post("/graphql", data = "<request>")]
+pub(crate) async fn graphql_request(
+    workspace_db: WorkspaceDb<'_>,
+    db_pool: &State<SqlitePool>,
+    task_events: &State<TaskEvents>,
+    schema: &State<TaskSchema>,
+    request: Json<async_graphql::Request>,
+) -> Json<async_graphql::Response> {
+    let context = GraphQLContext::new(
+        workspace_db.workspace.clone(),
+        db_pool.inner().clone(),
+        workspace_db.cipher().cloned(),
+        task_events.inner().clone(),
+    );
+
+    Json(schema.execute(request.into_inner().data(context)).await)
+}
+
+/// NOTE(alex): Debug builds only, it's a `404` in release. The page works for a browser signed in
+/// through `/ui/sign-in`: its cookie authenticates the queries and (sent along on the websocket
+/// handshake) the subscriptions, and the queries echo the CSRF token back.
+#[get("/graphql")]
+pub(crate) async fn graphiql(
+    workspace_db: WorkspaceDb<'_>,
+    websocket_origin: WebSocketOrigin,
+    csrf: CsrfToken,
+) -> Option<GraphiQL> {
+    if !cfg!(debug_assertions) {
+        return None;
+    }
+
+    let subscription_endpoint = websocket_origin
+        .0
+        .map(|origin| format!("{}/w/{}/graphql", origin, workspace_db.workspace.slug));
+
+    Some(GraphiQL(
+        GRAPHIQL
+            .replace("GRAPHQL_URL", &script_string("graphql"))
+            .replace(
+                "SUBSCRIPTION_URL",
+                &subscription_endpoint
+                    .as_deref()
+                    .map_or_else(|| "null".to_string(), script_string),
+            )
+            .replace("CSRF_TOKEN", &script_string(csrf.as_str())),
+    ))
+}
+
+/// A JavaScript string literal that can't close the `<script>` it's in.
+fn script_string(value: &str) -> String {
+    serde_json::to_string(value)
+        .unwrap_or_default()
+        .replace('<', "\\u003c")
+}
@@ -0,0 +1,770 @@
+use std::{collections::HashSet, net::SocketAddr, pin::Pin};
+
+use log::{error, info};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    futures::{stream, Stream},
+    tokio::{self, sync::broadcast::error::RecvError},
+    Orbit, Rocket,
+};
+use sqlx::SqlitePool;
+use tonic::{metadata::MetadataMap, transport::Server, Request, Response, Status};
+
+use crate::{
+    encryption::DetailsCipher,
+    errors::AppError,
+    etag::check_unchanged,
+    events::{TaskEvent, TaskEventKind, TaskEvents},
+    models::{InsertTask, Task, UpdateTask},
+    workspace::{Workspace, WorkspaceDb},
+};
+
+pub(crate) mod proto {
+    tonic::include_proto!("tasks.v1");
+}
+
+use proto::{
+    task_event::Kind as ProtoKind,
+    task_service_server::{TaskService, TaskServiceServer},
+    CreateTaskRequest, DeleteTaskRequest, DeleteTaskResponse, DoneTaskRequest, GetTaskRequest,
+    ListTasksRequest, UndoTaskRequest, UpdateTaskRequest, WatchRequest,
+};
+
+type TaskStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+impl From<AppError> for Status {
+    fn from(fail: AppError) -> Self {
+        let message = fail.to_string();
+
+        match fail {
+            AppError::EmptyTitle | AppError::InvalidTask(_) => Status::invalid_argument(message),
+            AppError::IdNotFound(_) | AppError::WorkspaceNotFound => Status::not_found(message),
+            AppError::InvalidToken => Status::unauthenticated(message),
+            AppError::WorkspaceMismatch(_) => Status::permission_denied(message),
+            AppError::QuotaExceeded(_) | AppError::RateLimited(_) => {
+                Status::resource_exhausted(message)
+            }
+            AppError::PreconditionFailed | AppError::ConfirmationRequired(_) => {
+                Status::failed_precondition(message)
+            }
+            AppError::UnsupportedPatch
+            | AppError::NotAcceptable
+            | AppError::UnsupportedFormat
+            | AppError::UnsupportedChecklist => Status::unimplemented(message),
+            AppError::Decryption | AppError::Internal | AppError::IO(_) | AppError::Sql(_) => {
+                Status::internal(message)
+            }
+        }
+    }
+}
+
+impl From<TaskEventKind> for ProtoKind {
+    fn from(kind: TaskEventKind) -> Self {
+        match kind {
+            TaskEventKind::Created => ProtoKind::Created,
+            TaskEventKind::Updated => ProtoKind::Updated,
+            TaskEventKind::Deleted => ProtoKind::Deleted,
+            TaskEventKind::Done => ProtoKind::Done,
+            TaskEventKind::Undone => ProtoKind::Undone,
+        }
+    }
+}
+
+fn proto_task(task: Task, done: bool) -> proto::Task {
+    proto::Task {
+        id: task.id,
+        list_id: task.list_id,
+        title: task.title,
+        details: task.details,
+        version: task.version,
+        done,
+    }
+}
+
+/// NOTE(alex): Same model methods (and events) as `routes.rs`, `version` plays the part of
+/// `If-Match`.
+pub(crate) struct GrpcTaskService {
+    db_pool: SqlitePool,
+    cipher: Option<DetailsCipher>,
+    task_events: TaskEvents,
+}
+
+impl GrpcTaskService {
+    /// Picks the workspace like `WorkspaceDb` does for HTTP requests, from the metadata.
+    async fn workspace_db(&self, metadata: &MetadataMap) -> Result<WorkspaceDb<'_>, Status> {
+        let entry = |name| metadata.get(name).and_then(|value| value.to_str().ok());
+
+        let token = entry("authorization").and_then(|value| value.strip_prefix("Bearer "));
+        let slug = entry("x-workspace").map(|slug| slug.to_string());
+        let workspace = Workspace::resolve(&self.db_pool, token, slug).await?;
+
+        Ok(WorkspaceDb::new(
+            workspace,
+            &self.db_pool,
+            self.cipher.as_ref(),
+        ))
+    }
+
+    async fn with_done(workspace_db: &WorkspaceDb<'_>, task: Task) -> Result<proto::Task, Status> {
+        let done = Task::is_done(workspace_db, task.id).await?;
+
+        Ok(proto_task(task, done))
+    }
+
+    fn publish(&self, workspace_db: &WorkspaceDb<'_>, kind: TaskEventKind, task: &Task) {
+        self.task_events
+            .publish(workspace_db.workspace.id, kind, task.clone());
+    }
+}
+
+#[tonic::async_trait]
+impl TaskService for GrpcTaskService {
+    type ListStream = TaskStream<proto::Task>;
+    type WatchStream = TaskStream<proto::TaskEvent>;
+
+    async fn create(
+        &self,
+        request: Request<CreateTaskRequest>,
+    ) -> Result<Response<proto::Task>, Status> {
+        let workspace_db = self.workspace_db(request.metadata()).await?;
+        let request = request.into_inner();
+
+        let insert_task = InsertTask {
+            non_empty_title: request.title,
+            details: request.details,
+            list_id: request.list_id,
+        };
+        insert_task.validate()?;
+
+        let task = insert_task.insert(&workspace_db).await?;
+        self.publish(&workspace_db, TaskEventKind::Created, &task);
+
+        Ok(Response::new(proto_task(task, false)))
+    }
+
+    async fn get(&self, request: Request<GetTaskRequest>) -> Result<Response<proto::Task>, Status> {
+        let workspace_db = self.workspace_db(request.metadata()).await?;
+        let id = request.into_inner().id;
+
+        let task = Task::find_by_id(&workspace_db, id)
+            .await?
+            .ok_or(AppError::IdNotFound(id))?;
+
+        Ok(Response::new(Self::with_done(&workspace_db, task).await?))
+    }
+
+    async fn list(
+        &self,
+        request: Request<ListTasksRequest>,
+    ) -> Result<Response<Self::ListStream>, Status> {
+        let workspace_db = self.workspace_db(request.metadata()).await?;
+        let request = request.into_inner();
+
+        let (tasks, all_ongoing) = match (request.pattern, request.ongoing) {
+            (Some(pattern), _) => (Task::find_by_pattern(&workspace_db, &pattern).await?, false),
+            (None, true) => (Task::find_ongoing(&workspace_db).await?, true),
+            (None, false) => (Task::find_all(&workspace_db).await?, false),
+        };
+
+        let done = match all_ongoing {
+            true => HashSet::new(),
+            false => {
+                let ids: Vec<i64> = tasks.iter().map(|task| task.id).collect();
+                Task::find_done_among(&workspace_db, &ids).await?
+            }
+        };
+
+        let listed = tasks.into_iter().map(move |task| {
+            let is_done = done.contains(&task.id);
+            proto_task(task, is_done)
+        });
+
+        Ok(Response::new(Box::pin(stream::iter(listed.map(Ok)))))
+    }
+
+    async fn update(
+        &self,
+        request: Request<UpdateTaskRequest>,
+    ) -> Result<Response<proto::Task>, Status> {
+        let workspace_db = self.workspace_db(request.metadata()).await?;
+        let request = request.into_inner();
+
+        let update_task = UpdateTask {
+            id: request.id,
+            new_title: request.title,
+            details: request.details,
+        };
+        update_task.validate()?;
+
+        let rows_affected = update_task.update(&workspace_db, request.version).await?;
+        let task = Task::find_by_id(&workspace_db, request.id).await?;
+
+        if rows_affected == 0 {
+            check_unchanged(request.version, task.as_ref())?;
+        }
+
+        let task = task.ok_or(AppError::IdNotFound(request.id))?;
+        if rows_affected > 0 {
+            self.publish(&workspace_db, TaskEventKind::Updated, &task);
+        }
+
+        Ok(Response::new(Self::with_done(&workspace_db, task).await?))
+    }
+
+    async fn delete(
+        &self,
+        request: Request<DeleteTaskRequest>,
+    ) -> Result<Response<DeleteTaskResponse>, Status> {
+        let workspace_db = self.workspace_db(request.metadata()).await?;
+        let request = request.into_inner();
+
+        let task = Task::find_by_id(&workspace_db, request.id).await?;
+        let rows_affected = Task::delete(&workspace_db, request.id, request.version).await?;
+
+        if rows_affected == 0 {
+            let current = Task::find_by_id(&workspace_db, request.id).await?;
+            check_unchanged(request.version, current.as_ref())?;
+        } else if let Some(task) = &task {
+            self.publish(&workspace_db, TaskEventKind::Deleted, task);
+        }
+
+        Ok(Response::new(DeleteTaskResponse {
+            deleted: rows_affected > 0,
+        }))
+    }
+
+    async fn done(
+        &self,
+        request: Request<DoneTaskRequest>,
+    ) -> Result<Response<proto::Task>, Status> {
+        let workspace_db = self.workspace_db(request.metadata()).await?;
+        let request = request.into_inner();
+
+        let created_id = Task::done(&workspace_db, request.id, request.version).await?;
+        let task = Task::find_by_id(&workspace_db, request.id).await?;
+
+        if created_id == 0 {
+            check_unchanged(request.version, task.as_ref())?;
+        }
+
+        let task = task.ok_or(AppError::IdNotFound(request.id))?;
+        if created_id != 0 {
+            self.publish(&workspace_db, TaskEventKind::Done, &task);
+        }
+
+        Ok(Response::new(proto_task(task, true)))
+    }
+
+    async fn undo(
+        &self,
+        request: Request<UndoTaskRequest>,
+    ) -> Result<Response<proto::Task>, Status> {
+        let workspace_db = self.workspace_db(request.metadata()).await?;
+        let request = request.into_inner();
+
+        let num_modified = Task::undo(&workspace_db, request.id, request.version).await?;
+        let task = Task::find_by_id(&workspace_db, request.id).await?;
+
+        if num_modified == 0 {
+            check_unchanged(request.version, task.as_ref())?;
+        }
+
+        let task = task.ok_or(AppError::IdNotFound(request.id))?;
+        if num_modified != 0 {
+            self.publish(&workspace_db, TaskEventKind::Undone, &task);
+        }
+
+        Ok(Response::new(proto_task(task, false)))
+    }
+
+    async fn watch(
+        &self,
+        request: Request<WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let workspace_db = self.workspace_db(request.metadata()).await?;
+        let workspace_id = workspace_db.workspace.id;
+        let last_event_id = request.into_inner().last_event_id;
+
+        let (missed, last_sent, receiver) = self.task_events.subscribe(workspace_id, last_event_id);
+
+        let live = stream::unfold(receiver, move |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event)
+                        if event.workspace_id == workspace_id && Some(event.id) > last_sent =>
+                    {
+                        return Some((event, receiver))
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+
+        // NOTE(alex): The task of an event doesn't carry its done state, it's looked up as the
+        // event is sent (like the `done` field of GraphQL subscriptions).
+        let db_pool = self.db_pool.clone();
+        let cipher = self.cipher.clone();
+        let workspace = workspace_db.workspace.clone();
+        let events = stream::StreamExt::then(
+            stream::StreamExt::chain(stream::iter(missed), live),
+            move |event: TaskEvent| {
+                let db_pool = db_pool.clone();
+                let cipher = cipher.clone();
+                let workspace = workspace.clone();
+
+                async move {
+                    let workspace_db = WorkspaceDb::new(workspace, &db_pool, cipher.as_ref());
+                    let done = Task::is_done(&workspace_db, event.task.id).await?;
+
+                    Ok(proto::TaskEvent {
+                        id: event.id,
+                        kind: ProtoKind::from(event.kind) as i32,
+                        task: Some(proto_task(event.task, done)),
+                    })
+                }
+            },
+        );
+
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+/// gRPC server for the `TaskService` of `proto/tasks.proto`, listens on `grpc_port` next to
+/// Rocket.
+///
+/// NOTE(alex): Like `WebSocket`, it runs in the same process, sharing the pool, the event feed and
+/// the model layer.
+pub(crate) struct Grpc;
+
+#[rocket::async_trait]
+impl Fairing for Grpc {
+    fn info(&self) -> Info {
+        Info {
+            name: "gRPC",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let port = match rocket.figment().extract_inner::<u16>("grpc_port") {
+            Ok(port) => port,
+            Err(_) => return,
+        };
+
+        let service = match (rocket.state::<SqlitePool>(), rocket.state::<TaskEvents>()) {
+            (Some(db_pool), Some(task_events)) => GrpcTaskService {
+                db_pool: db_pool.clone(),
+                cipher: rocket.state::<DetailsCipher>().cloned(),
+                task_events: task_events.clone(),
+            },
+            _ => {
+                error!("gRPC requires the pool and task events to be managed!");
+                return;
+            }
+        };
+
+        let address = SocketAddr::new(rocket.config().address, port);
+        info!("gRPC listening on http://{}", address);
+
+        tokio::spawn(async move {
+            let server = Server::builder()
+                .add_service(TaskServiceServer::new(service))
+                .serve(address);
+
+            if let Err(fail) = server.await {
+                error!("gRPC failed to serve `{}`: {}", address, fail);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rocket::{
+        futures::StreamExt,
+        tokio::{io, time::timeout},
+    };
+    use sqlx::sqlite::SqlitePoolOptions;
+    use tonic::{
+        codegen::InterceptedService,
+        metadata::MetadataValue,
+        service::Interceptor,
+        transport::{Channel, Endpoint, Uri},
+        Code, Streaming,
+    };
+    use tower::service_fn;
+
+    use super::*;
+    use proto::{
+        task_event::Kind, task_service_client::TaskServiceClient, CreateTaskRequest,
+        DeleteTaskRequest, DoneTaskRequest, GetTaskRequest, ListTasksRequest, UndoTaskRequest,
+        UpdateTaskRequest, WatchRequest,
+    };
+
+    /// Sends `authorization: Bearer {token}` with every call.
+    #[derive(Clone)]
+    struct Bearer(String);
+
+    impl Interceptor for Bearer {
+        fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+            let value = MetadataValue::from_str(&format!("Bearer {}", self.0))
+                .map_err(|_| Status::invalid_argument("token"))?;
+            request.metadata_mut().insert("authorization", value);
+
+            Ok(request)
+        }
+    }
+
+    type Client = TaskServiceClient<InterceptedService<Channel, Bearer>>;
+
+    /// A database of its own, and the event feed every client of it shares.
+    struct Served {
+        db_pool: SqlitePool,
+        task_events: TaskEvents,
+    }
+
+    impl Served {
+        async fn start() -> Self {
+            let db_pool = SqlitePoolOptions::new()
+                .max_connections(1)
+                .connect("sqlite::memory:")
+                .await
+                .unwrap();
+            crate::schema::create_database(&db_pool).await.unwrap();
+
+            Served {
+                db_pool,
+                task_events: TaskEvents::default(),
+            }
+        }
+
+        async fn token(&self) -> String {
+            Workspace::find_by_slug(&self.db_pool, "default")
+                .await
+                .unwrap()
+                .expect("the default workspace exists")
+                .token(&self.db_pool)
+                .await
+                .unwrap()
+        }
+
+        /// Serves a `GrpcTaskService` on one end of an in-memory duplex, the client gets the other.
+        async fn client_with(&self, token: &str) -> Client {
+            let (client_io, server_io) = io::duplex(64 * 1024);
+            let service = GrpcTaskService {
+                db_pool: self.db_pool.clone(),
+                cipher: None,
+                task_events: self.task_events.clone(),
+            };
+
+            tokio::spawn(
+                Server::builder()
+                    .add_service(TaskServiceServer::new(service))
+                    .serve_with_incoming(stream::iter(vec![Ok::<_, io::Error>(server_io)])),
+            );
+
+            // NOTE(alex): The address is never dialed, the connector hands out the duplex.
+            let mut client_io = Some(client_io);
+            let channel = Endpoint::from_static("http://[::]:50051")
+                .connect_with_connector(service_fn(move |_: Uri| {
+                    let client_io = client_io.take();
+                    async move {
+                        client_io.ok_or_else(|| io::Error::other("the duplex is connected already"))
+                    }
+                }))
+                .await
+                .expect("the gRPC service accepts the connection");
+
+            TaskServiceClient::with_interceptor(channel, Bearer(token.to_string()))
+        }
+
+        async fn client(&self) -> Client {
+            self.client_with(&self.token().await).await
+        }
+
+        /// NOTE(alex): Waits for the queries the service still runs (a `watch` looks up the next
+        /// event ahead), the process may exit under the connection thread of sqlx otherwise.
+        async fn stop(self) {
+            self.db_pool.close().await;
+        }
+    }
+
+    async fn create(client: &mut Client, title: &str) -> proto::Task {
+        client
+            .create(CreateTaskRequest {
+                title: title.to_string(),
+                details: format!("details of {}", title),
+                list_id: None,
+            })
+            .await
+            .expect("create succeeds")
+            .into_inner()
+    }
+
+    async fn list(client: &mut Client, request: ListTasksRequest) -> Vec<proto::Task> {
+        client
+            .list(request)
+            .await
+            .unwrap()
+            .into_inner()
+            .map(|task| task.unwrap())
+            .collect()
+            .await
+    }
+
+    async fn next(events: &mut Streaming<proto::TaskEvent>) -> proto::TaskEvent {
+        timeout(Duration::from_secs(10), events.next())
+            .await
+            .expect("an event arrives")
+            .expect("the stream is still open")
+            .expect("the event is well formed")
+    }
+
+    #[rocket::async_test]
+    async fn create_get_and_list() {
+        let served = Served::start().await;
+        let mut client = served.client().await;
+
+        let first = create(&mut client, "first").await;
+        let second = create(&mut client, "second").await;
+        assert_eq!(first.title, "first");
+        assert_eq!(first.details, "details of first");
+        assert!(!first.done);
+
+        let got = client
+            .get(GetTaskRequest { id: second.id })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(got, second);
+
+        let done = client
+            .done(DoneTaskRequest {
+                id: second.id,
+                version: None,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+
+        let all = list(&mut client, ListTasksRequest::default()).await;
+        assert_eq!(all, vec![first.clone(), done]);
+
+        let found = list(
+            &mut client,
+            ListTasksRequest {
+                pattern: Some("fir%".to_string()),
+                ongoing: false,
+            },
+        )
+        .await;
+        assert_eq!(found, vec![first]);
+
+        served.stop().await;
+    }
+
+    #[rocket::async_test]
+    async fn update_done_undo_and_delete() {
+        let served = Served::start().await;
+        let mut client = served.client().await;
+        let task = create(&mut client, "task").await;
+
+        let updated = client
+            .update(UpdateTaskRequest {
+                id: task.id,
+                title: "renamed".to_string(),
+                details: "new details".to_string(),
+                version: Some(task.version),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(updated.title, "renamed");
+        assert_eq!(updated.details, "new details");
+        assert!(updated.version > task.version);
+
+        let done = client
+            .done(DoneTaskRequest {
+                id: task.id,
+                version: Some(updated.version),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(done.done);
+
+        let ongoing = list(
+            &mut client,
+            ListTasksRequest {
+                pattern: None,
+                ongoing: true,
+            },
+        )
+        .await;
+        assert!(ongoing.is_empty());
+
+        let undone = client
+            .undo(UndoTaskRequest {
+                id: task.id,
+                version: None,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!undone.done);
+
+        let deleted = client
+            .delete(DeleteTaskRequest {
+                id: task.id,
+                version: Some(undone.version),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(deleted.deleted);
+
+        let missing = client
+            .get(GetTaskRequest { id: task.id })
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), Code::NotFound);
+
+        served.stop().await;
+    }
+
+    #[rocket::async_test]
+    async fn stale_versions_fail_their_precondition() {
+        let served = Served::start().await;
+        let mut client = served.client().await;
+        let task = create(&mut client, "task").await;
+        let stale = Some(task.version + 1);
+
+        let update = client
+            .update(UpdateTaskRequest {
+                id: task.id,
+                title: "renamed".to_string(),
+                details: String::new(),
+                version: stale,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(update.code(), Code::FailedPrecondition);
+
+        let done = client
+            .done(DoneTaskRequest {
+                id: task.id,
+                version: stale,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(done.code(), Code::FailedPrecondition);
+
+        let delete = client
+            .delete(DeleteTaskRequest {
+                id: task.id,
+                version: stale,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(delete.code(), Code::FailedPrecondition);
+
+        // NOTE(alex): None of them went through.
+        let current = client
+            .get(GetTaskRequest { id: task.id })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(current, task);
+
+        served.stop().await;
+    }
+
+    #[rocket::async_test]
+    async fn bad_requests_are_refused() {
+        let served = Served::start().await;
+
+        let mut stranger = served.client_with("not-a-token").await;
+        let unauthenticated = stranger
+            .list(ListTasksRequest::default())
+            .await
+            .unwrap_err();
+        assert_eq!(unauthenticated.code(), Code::Unauthenticated);
+
+        let mut client = served.client().await;
+        let empty_title = client
+            .create(CreateTaskRequest {
+                title: String::new(),
+                details: String::new(),
+                list_id: None,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(empty_title.code(), Code::InvalidArgument);
+
+        let not_found = client.get(GetTaskRequest { id: 404 }).await.unwrap_err();
+        assert_eq!(not_found.code(), Code::NotFound);
+
+        served.stop().await;
+    }
+
+    #[rocket::async_test]
+    async fn watch_streams_changes() {
+        let served = Served::start().await;
+        let mut client = served.client().await;
+
+        let mut events = client
+            .watch(WatchRequest::default())
+            .await
+            .unwrap()
+            .into_inner();
+
+        let task = create(&mut client, "watched").await;
+        let done = client
+            .done(DoneTaskRequest {
+                id: task.id,
+                version: None,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        client
+            .update(UpdateTaskRequest {
+                id: task.id,
+                title: "renamed".to_string(),
+                details: String::new(),
+                version: Some(done.version),
+            })
+            .await
+            .unwrap();
+
+        let created = next(&mut events).await;
+        assert_eq!(created.kind, Kind::Created as i32);
+        assert_eq!(created.task.as_ref().map(|task| task.id), Some(task.id));
+
+        let done = next(&mut events).await;
+        assert_eq!(done.kind, Kind::Done as i32);
+        assert!(done.task.is_some_and(|task| task.done));
+        assert!(done.id > created.id);
+
+        // NOTE(alex): Updating a done task keeps it done.
+        let updated = next(&mut events).await;
+        assert_eq!(updated.kind, Kind::Updated as i32);
+        assert!(updated.task.is_some_and(|task| task.done));
+
+        // NOTE(alex): Resuming from the first event replays only the ones after it.
+        let mut resumed = client
+            .watch(WatchRequest {
+                last_event_id: Some(created.id),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let replayed = next(&mut resumed).await;
+        assert_eq!(replayed.id, done.id);
+
+        served.stop().await;
+    }
+}
@@ -0,0 +1,381 @@
+use fairings::FromBody;
+use rocket::{
+    data::{ByteUnit, FromData, ToByteUnit},
+    http::Status,
+    serde::Serialize,
+};
+use utoipa::ToSchema;
+
+use crate::{
+    errors::AppError,
+    events::TaskEvents,
+    exchange::{from_basic, ExportedTask, ImportedTask},
+    models::Task,
+    negotiate::{body_text, read_body},
+    workspace::WorkspaceDb,
+};
+
+/// Keeps a single import from holding the write lock for too long, like `MAX_OPERATIONS` of a
+/// batch.
+const MAX_TODOS: usize = 1000;
+
+/// Content lines are folded at 75 octets (RFC 5545, 3.1).
+const MAX_LINE_OCTETS: usize = 75;
+
+/// Body of `POST /import/ics`, a `text/calendar`.
+pub(crate) struct Calendar(String);
+
+impl<'r> FromBody<'r> for Calendar {
+    type Error = AppError;
+
+    fn limit(req: &'r rocket::Request<'_>) -> ByteUnit {
+        req.limits().get("calendar").unwrap_or(1.mebibytes())
+    }
+
+    fn from_body(
+        req: &'r rocket::Request<'_>,
+        body: Vec<u8>,
+    ) -> Result<Self, (Status, Self::Error)> {
+        let is_calendar = req.content_type().is_some_and(|content_type| {
+            content_type.top() == "text" && content_type.sub() == "calendar"
+        });
+        if !is_calendar {
+            return Err((Status::UnsupportedMediaType, AppError::UnsupportedFormat));
+        }
+
+        body_text(body).map(Calendar)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for Calendar {
+    type Error = AppError;
+
+    async fn from_data(
+        req: &'r rocket::Request<'_>,
+        data: rocket::Data<'r>,
+    ) -> rocket::data::Outcome<'r, Self> {
+        read_body(req, data).await
+    }
+}
+
+/// What happened to one `VTODO` of `POST /import/ics`, in the order of the calendar.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub(crate) enum ImportResult {
+    Imported {
+        uid: Option<String>,
+        task: Task,
+        done: bool,
+        due: Option<String>,
+        categories: Vec<String>,
+    },
+    Failed {
+        uid: Option<String>,
+        error: String,
+    },
+}
+
+/// A `VTODO` of an imported calendar.
+#[derive(Debug, Default)]
+struct Todo {
+    uid: Option<String>,
+    summary: Option<String>,
+    description: Option<String>,
+    due: Option<Result<String, String>>,
+    done: bool,
+    completed_at: Option<Result<String, String>>,
+    categories: Vec<String>,
+}
+
+/// `\`, `;`, `,` and newlines are escaped in `TEXT` values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => (),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') | Some('N') => unescaped.push('\n'),
+            Some(escaped) => unescaped.push(escaped),
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
+
+/// Splits on the commas that aren't escaped, for the list of `CATEGORIES`.
+fn split_list(value: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut item = String::new();
+    let mut escaped = false;
+
+    for c in value.chars() {
+        match c {
+            ',' if !escaped => items.push(std::mem::take(&mut item)),
+            c => item.push(c),
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    items.push(item);
+
+    items
+        .iter()
+        .map(|item| unescape(item).trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Writes `line` with a `CRLF`, folded on character boundaries so no line exceeds 75 octets.
+fn push_line(calendar: &mut String, line: &str) {
+    let mut octets = 0;
+    for c in line.chars() {
+        if octets + c.len_utf8() > MAX_LINE_OCTETS {
+            calendar.push_str("\r\n ");
+            octets = 1;
+        }
+        calendar.push(c);
+        octets += c.len_utf8();
+    }
+    calendar.push_str("\r\n");
+}
+
+/// `2026-10-18` is a `DATE`, `2026-10-18T12:00:00Z` a `DATE-TIME` (floating without the `Z`).
+fn due_property(due: &str) -> String {
+    let compact = due.replace(&['-', ':'][..], "");
+    match compact.len() {
+        8 => format!("DUE;VALUE=DATE:{}", compact),
+        _ => format!("DUE:{}", compact),
+    }
+}
+
+/// The reverse of [`due_property`], `TZID`s are dropped (the time is kept as floating). Also reads
+/// `COMPLETED`, which must be in UTC.
+fn parse_due(value: &str) -> Result<String, String> {
+    let value = value.trim();
+
+    from_basic(value).ok_or_else(|| format!("`DUE` value `{}` is not a date or date-time", value))
+}
+
+/// The `VCALENDAR` of every task in the workspace, ongoing ones as `NEEDS-ACTION`, done ones as
+/// `COMPLETED`, with their tags as `CATEGORIES`.
+pub(crate) async fn render(workspace_db: &WorkspaceDb<'_>) -> Result<String, AppError> {
+    let tasks = ExportedTask::find_all(workspace_db).await?;
+
+    let slug = &workspace_db.workspace.slug;
+    let mut calendar = String::new();
+    push_line(&mut calendar, "BEGIN:VCALENDAR");
+    push_line(&mut calendar, "VERSION:2.0");
+    push_line(&mut calendar, "PRODID:-//learning-rocket//Todo//EN");
+    push_line(&mut calendar, &format!("X-WR-CALNAME:{}", escape(slug)));
+
+    for task in tasks {
+        push_line(&mut calendar, "BEGIN:VTODO");
+        push_line(&mut calendar, &format!("UID:task-{}@{}", task.id, slug));
+        push_line(&mut calendar, &format!("DTSTAMP:{}", task.stamp));
+        // NOTE(alex): `version` starts at 1, `SEQUENCE` at 0.
+        push_line(&mut calendar, &format!("SEQUENCE:{}", task.version - 1));
+        push_line(&mut calendar, &format!("SUMMARY:{}", escape(&task.title)));
+        if !task.details.is_empty() {
+            push_line(
+                &mut calendar,
+                &format!("DESCRIPTION:{}", escape(&task.details)),
+            );
+        }
+        if let Some(due) = &task.due {
+            push_line(&mut calendar, &due_property(due));
+        }
+
+        match &task.completed_at {
+            Some(completed_at) => {
+                push_line(&mut calendar, "STATUS:COMPLETED");
+                let completed = completed_at.replace(&['-', ':'][..], "");
+                push_line(&mut calendar, &format!("COMPLETED:{}", completed));
+            }
+            None => push_line(&mut calendar, "STATUS:NEEDS-ACTION"),
+        }
+
+        let tags = task.tags();
+        if !tags.is_empty() {
+            let categories = tags.into_iter().map(escape).collect::<Vec<_>>().join(",");
+            push_line(&mut calendar, &format!("CATEGORIES:{}", categories));
+        }
+
+        push_line(&mut calendar, "END:VTODO");
+    }
+
+    push_line(&mut calendar, "END:VCALENDAR");
+
+    Ok(calendar)
+}
+
+/// The `VTODO`s of `calendar`, other components (`VEVENT`, a `VALARM` inside a `VTODO`) are
+/// skipped.
+fn parse(calendar: &str) -> Result<Vec<Todo>, AppError> {
+    // NOTE(alex): Unfolds first, a line starting with a space (or tab) continues the previous one.
+    let mut lines: Vec<String> = Vec::new();
+    for line in calendar.split('\n').map(|line| line.trim_end_matches('\r')) {
+        match (line.strip_prefix(&[' ', '\t'][..]), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ if line.is_empty() => (),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    if lines.first().map(|line| line.to_ascii_uppercase()) != Some("BEGIN:VCALENDAR".to_string()) {
+        return Err(AppError::InvalidTask(
+            "body is not an iCalendar (`BEGIN:VCALENDAR`)".to_string(),
+        ));
+    }
+
+    let mut todos = Vec::new();
+    let mut todo: Option<Todo> = None;
+    let mut nested = 0;
+
+    for line in lines {
+        let (name, value) = match line.split_once(':') {
+            Some(parts) => parts,
+            None => continue,
+        };
+        let mut params = name.split(';');
+        let name = params.next().unwrap_or_default().to_ascii_uppercase();
+        let value_type = params
+            .find_map(|param| param.strip_prefix("VALUE="))
+            .unwrap_or_default();
+
+        let current = match (name.as_str(), value.trim().to_ascii_uppercase().as_str()) {
+            ("BEGIN", "VTODO") if todo.is_none() => {
+                todo = Some(Todo::default());
+                continue;
+            }
+            ("END", "VTODO") if nested == 0 => {
+                todos.extend(todo.take());
+                continue;
+            }
+            ("BEGIN", _) if todo.is_some() => {
+                nested += 1;
+                continue;
+            }
+            ("END", _) if todo.is_some() && nested > 0 => {
+                nested -= 1;
+                continue;
+            }
+            _ if nested > 0 => continue,
+            _ => match todo.as_mut() {
+                Some(current) => current,
+                None => continue,
+            },
+        };
+
+        match name.as_str() {
+            "UID" => current.uid = Some(value.to_string()),
+            "SUMMARY" => current.summary = Some(unescape(value)),
+            "DESCRIPTION" => current.description = Some(unescape(value)),
+            "DUE" => {
+                current.due = Some(parse_due(value).map_err(|fail| match value_type {
+                    "" => fail,
+                    value_type => format!("{} (`VALUE={}`)", fail, value_type),
+                }))
+            }
+            "STATUS" => current.done = value.trim().eq_ignore_ascii_case("COMPLETED"),
+            "COMPLETED" => {
+                current.done = true;
+                current.completed_at = Some(match parse_due(value) {
+                    Ok(completed_at) if completed_at.ends_with('Z') => Ok(completed_at),
+                    _ => Err(format!(
+                        "`COMPLETED` value `{}` is not a UTC date-time",
+                        value
+                    )),
+                });
+            }
+            "CATEGORIES" => current.categories.extend(split_list(value)),
+            _ => (),
+        }
+    }
+
+    if todos.len() > MAX_TODOS {
+        return Err(AppError::InvalidTask(format!(
+            "a calendar can't have more than `{}` to-dos",
+            MAX_TODOS
+        )));
+    }
+
+    Ok(todos)
+}
+
+impl Todo {
+    /// Inserts the task, with its due date, tags (created when missing) and done state, all or
+    /// nothing.
+    async fn import(
+        self,
+        workspace_db: &WorkspaceDb<'_>,
+        task_events: &TaskEvents,
+    ) -> Result<ImportResult, AppError> {
+        let imported_task = ImportedTask {
+            title: self.summary.unwrap_or_default(),
+            details: self.description.unwrap_or_default(),
+            tags: self.categories,
+            due: self.due.transpose().map_err(AppError::InvalidTask)?,
+            done: self.done,
+            completed_at: self
+                .completed_at
+                .transpose()
+                .map_err(AppError::InvalidTask)?,
+            ..ImportedTask::default()
+        };
+
+        let task = imported_task.insert(workspace_db, task_events).await?;
+
+        Ok(ImportResult::Imported {
+            uid: self.uid,
+            task,
+            done: imported_task.done,
+            due: imported_task.due,
+            categories: imported_task.tags,
+        })
+    }
+}
+
+/// Imports every `VTODO` of `calendar` on its own, a failed one doesn't stop the others.
+pub(crate) async fn import(
+    workspace_db: &WorkspaceDb<'_>,
+    task_events: &TaskEvents,
+    calendar: Calendar,
+) -> Result<Vec<ImportResult>, AppError> {
+    let mut results = Vec::new();
+
+    for todo in parse(&calendar.0)? {
+        let uid = todo.uid.clone();
+        let result = match todo.import(workspace_db, task_events).await {
+            Ok(imported) => imported,
+            Err(fail) => ImportResult::Failed {
+                uid,
+                error: fail.to_string(),
+            },
+        };
+
+        results.push(result);
+    }
+
+    Ok(results)
+}
@@ -1,32 +1,121 @@
-use errors::AppError;
-use rocket::{get, routes};
+use assets::{asset, StaticAssets};
+use bulk::BulkConfig;
+use encryption::DetailsEncryption;
+use events::TaskEvents;
+use fairings::{Compression, Cors, Csrf, Idempotency, SecurityHeaders};
+use graphql::{graphiql, graphql_request, GraphQL};
+use grpc::Grpc;
+use openapi::{docs, openapi_json, OpenApiDocs};
+use rate_limit::{rate_limited, RateLimiter};
+use rocket::{catchers, fairing::AdHoc, get, routes, Route};
 use routes::{
-    delete, done, find_all, find_by_id, find_by_pattern, find_ongoing, insert, undo, update,
+    batch_tasks, bulk_tasks, calendar_feed, current_workspace, delete, done, export_csv,
+    export_json_lines, export_tasks, find_all, find_all_lists, find_all_tags, find_by_id,
+    find_by_pattern, find_ongoing, import_calendar, import_csv, import_json_lines, import_source,
+    import_tasks, insert, insert_list, insert_tag, patch_task, pull, push, replace_task,
+    rotate_calendar_feed, tag, task_events, tasks_calendar, tasks_feed, undo, untag, update,
 };
 use sqlx::SqlitePool;
+use templates::Templates;
+use ui::{
+    complete_task, create_task, delete_task, edit_page, edit_task, list_page, other_workspace,
+    sign_in, sign_in_page, sign_in_required, sign_out, undo_task,
+};
+use websocket::WebSocket;
+use workspace::Workspaces;
 
+mod assets;
+mod atom;
+mod batch;
+mod bulk;
+mod checklist;
+mod cli;
+mod encryption;
 mod errors;
+mod etag;
+mod events;
+mod exchange;
+mod graphql;
+mod grpc;
+mod ics;
 mod models;
+mod negotiate;
+mod openapi;
+mod patch;
+mod rate_limit;
+mod records;
 mod routes;
+mod schema;
+mod sources;
+mod sync;
+mod templates;
+mod ui;
+mod websocket;
+mod workspace;
 
 const WELCOME: &'static str = include_str!("./../strings/welcome.txt");
-const CREATE_DATABASE: &'static str = include_str!("./../queries/create_database.sql");
 
 #[get("/")]
 async fn index() -> &'static str {
     WELCOME
 }
 
-/// NOTE(alex): This function should be part of some setup script, it's here for convenience. It
-/// could be moved to the `build.rs`, by adding `sqlx` and `tokio` as `dev-dependencies`:
-async fn create_database(db_pool: &SqlitePool) -> Result<u64, AppError> {
-    let mut connection = db_pool.acquire().await?;
-
-    let result = sqlx::query(CREATE_DATABASE)
-        .execute(&mut connection)
-        .await?;
-
-    Ok(result.rows_affected())
+/// Every route of `main`, also what `openapi.rs` checks the document against.
+fn mounted_routes() -> Vec<Route> {
+    routes![
+        index,
+        insert,
+        batch_tasks,
+        bulk_tasks,
+        update,
+        replace_task,
+        patch_task,
+        delete,
+        done,
+        undo,
+        find_all,
+        find_ongoing,
+        find_by_pattern,
+        task_events,
+        find_by_id,
+        current_workspace,
+        calendar_feed,
+        rotate_calendar_feed,
+        tasks_calendar,
+        tasks_feed,
+        import_calendar,
+        export_tasks,
+        import_tasks,
+        export_csv,
+        export_json_lines,
+        import_csv,
+        import_json_lines,
+        import_source,
+        insert_list,
+        find_all_lists,
+        insert_tag,
+        find_all_tags,
+        tag,
+        untag,
+        pull,
+        push,
+        graphql_request,
+        graphiql,
+        openapi_json,
+        docs,
+        sign_in_page,
+        sign_in,
+        sign_out,
+        list_page,
+        edit_page,
+        create_task,
+        edit_task,
+        complete_task,
+        undo_task,
+        delete_task,
+        asset,
+        rate_limited
+    ]
 }
 
 #[rocket::main]
@@ -37,27 +126,45 @@ async fn main() {
 
     let db_pool = SqlitePool::connect_with(db_options.clone()).await.unwrap();
 
+    // NOTE(alex): Any argument is a command of `cli`, which runs and exits without launching,
+    // and before `create_database`, so a command never touches the schema of a running server.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(fail) = cli::run(&db_pool, &args).await {
+            eprintln!("{}", fail);
+            std::process::exit(1);
+        }
+
+        return;
+    }
+
     if let Some(_) = option_env!("CREATE_DATABASE") {
-        create_database(&&db_pool).await.unwrap();
+        if let Err(fail) = schema::create_database(&db_pool).await {
+            eprintln!("{}", fail);
+            std::process::exit(1);
+        }
     }
 
     rocket::build()
         .manage(db_pool)
-        .mount(
-            "/",
-            routes![
-                index,
-                insert,
-                update,
-                delete,
-                done,
-                undo,
-                find_all,
-                find_ongoing,
-                find_by_pattern,
-                find_by_id
-            ],
-        )
+        .manage(TaskEvents::default())
+        .attach(Csrf)
+        .attach(Idempotency::default())
+        .attach(Cors)
+        .attach(SecurityHeaders)
+        .attach(Compression)
+        .attach(Workspaces)
+        .attach(RateLimiter::default())
+        .attach(DetailsEncryption)
+        .attach(GraphQL)
+        .attach(WebSocket)
+        .attach(Grpc)
+        .attach(OpenApiDocs)
+        .attach(Templates)
+        .attach(StaticAssets)
+        .attach(AdHoc::config::<BulkConfig>())
+        .mount("/", mounted_routes())
+        .register("/ui", catchers![sign_in_required, other_workspace])
         .launch()
         .await
         .unwrap();
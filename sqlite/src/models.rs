@@ -1,57 +1,149 @@
-use crate::errors::AppError;
+use std::collections::HashSet;
+
+use crate::{
+    errors::AppError,
+    negotiate::{deserialize_body, read_body},
+    workspace::{Scoped, WorkspaceDb, WorkspaceTx},
+};
+use fairings::FromBody;
 use rocket::{
-    data::{FromData, Outcome, ToByteUnit},
+    data::{ByteUnit, FromData, ToByteUnit},
     http::Status,
     serde::{json::serde_json, Deserialize, Serialize},
 };
-use sqlx::{FromRow, SqlitePool};
-
-const FIND_BY_PATTERN: &'static str = include_str!("./../queries/find_by_pattern.sql");
-const FIND_ONGOING: &'static str = include_str!("./../queries/find_ongoing.sql");
-const FIND_ALL: &'static str = include_str!("./../queries/find_all.sql");
-const FIND_BY_ID: &'static str = include_str!("./../queries/find_by_id.sql");
-const INSERT: &'static str = include_str!("./../queries/insert.sql");
-const UPDATE: &'static str = include_str!("./../queries/update.sql");
-const DELETE: &'static str = include_str!("./../queries/delete.sql");
-
-const COMPLETED: &'static str = include_str!("./../queries/done.sql");
-const UNDO: &'static str = include_str!("./../queries/undo.sql");
-
-#[derive(Clone, Debug, Serialize, Deserialize, FromRow)]
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+const FIND_BY_PATTERN: Scoped = Scoped::new(include_str!("./../queries/find_by_pattern.sql"));
+const FIND_ONGOING: Scoped = Scoped::new(include_str!("./../queries/find_ongoing.sql"));
+const FIND_ALL: Scoped = Scoped::new(include_str!("./../queries/find_all.sql"));
+const FIND_BY_ID: Scoped = Scoped::new(include_str!("./../queries/find_by_id.sql"));
+const INSERT: Scoped = Scoped::new(include_str!("./../queries/insert.sql"));
+const UPDATE: Scoped = Scoped::new(include_str!("./../queries/update.sql"));
+const DELETE: Scoped = Scoped::new(include_str!("./../queries/delete.sql"));
+const REPLACE: Scoped = Scoped::new(include_str!("./../queries/replace.sql"));
+const SEAL_INSERTED_DETAILS: Scoped =
+    Scoped::new(include_str!("./../queries/seal_inserted_details.sql"));
+
+const COMPLETED: Scoped = Scoped::new(include_str!("./../queries/done.sql"));
+const UNDO: Scoped = Scoped::new(include_str!("./../queries/undo.sql"));
+const IS_DONE: Scoped = Scoped::new(include_str!("./../queries/is_done.sql"));
+const FIND_DONE_AMONG: Scoped = Scoped::new(include_str!("./../queries/find_done_among.sql"));
+const FIND_CHANGES_BY_TASKS: Scoped =
+    Scoped::new(include_str!("./../queries/find_changes_by_tasks.sql"));
+
+const TAG_TASK: Scoped = Scoped::new(include_str!("./../queries/tag_task.sql"));
+const UNTAG_TASK: Scoped = Scoped::new(include_str!("./../queries/untag_task.sql"));
+
+const INSERT_LIST: Scoped = Scoped::new(include_str!("./../queries/insert_list.sql"));
+const FIND_ALL_LISTS: Scoped = Scoped::new(include_str!("./../queries/find_all_lists.sql"));
+const FIND_LIST_BY_ID: Scoped = Scoped::new(include_str!("./../queries/find_list_by_id.sql"));
+const INSERT_TAG: Scoped = Scoped::new(include_str!("./../queries/insert_tag.sql"));
+const FIND_ALL_TAGS: Scoped = Scoped::new(include_str!("./../queries/find_all_tags.sql"));
+const FIND_TAGS_BY_TASK: Scoped = Scoped::new(include_str!("./../queries/find_tags_by_task.sql"));
+const FIND_TAGS_BY_TASKS: Scoped = Scoped::new(include_str!("./../queries/find_tags_by_tasks.sql"));
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
 pub(crate) struct Task {
     pub(crate) id: i64,
+    pub(crate) list_id: Option<i64>,
     pub(crate) title: String,
     pub(crate) details: String,
+    pub(crate) version: i64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub(crate) struct InsertTask {
     pub(crate) non_empty_title: String,
     pub(crate) details: String,
+    #[serde(default)]
+    pub(crate) list_id: Option<i64>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub(crate) struct UpdateTask {
     pub(crate) id: i64,
     pub(crate) new_title: String,
     pub(crate) details: String,
 }
 
+/// The fields of a `Task` clients can write, `PUT /tasks/<id>` replaces them all, and
+/// `PATCH /tasks/<id>` patches this document.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TaskDocument {
+    pub(crate) title: String,
+    pub(crate) details: String,
+    #[serde(default)]
+    pub(crate) list_id: Option<i64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+pub(crate) struct TaskList {
+    pub(crate) id: i64,
+    pub(crate) name: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+pub(crate) struct Tag {
+    pub(crate) id: i64,
+    pub(crate) name: String,
+}
+
+/// A row of `TaskChange`, what the triggers recorded of a creation, edit, completion or undo.
+#[derive(Clone, Debug, FromRow)]
+pub(crate) struct Change {
+    pub(crate) task_id: i64,
+    pub(crate) seq: i64,
+    pub(crate) kind: String,
+    pub(crate) title: String,
+    pub(crate) changed_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub(crate) struct InsertTaskList {
+    pub(crate) name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub(crate) struct InsertTag {
+    pub(crate) name: String,
+}
+
 impl InsertTask {
-    pub(crate) async fn insert(&self, db_pool: &SqlitePool) -> Result<Task, AppError> {
-        let mut connection = db_pool.acquire().await?;
-        let result = sqlx::query(INSERT)
+    /// NOTE(alex): Shared by every way of inserting a task (data guard, websocket).
+    pub(crate) fn validate(&self) -> Result<(), AppError> {
+        if self.non_empty_title.trim().is_empty() {
+            Err(AppError::EmptyTitle)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) async fn insert(&self, workspace_db: &WorkspaceDb<'_>) -> Result<Task, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let result = tx
+            .query(INSERT)
+            .bind(&self.list_id)
             .bind(&self.non_empty_title.to_string())
-            .bind(&self.details)
-            .execute(&mut connection)
+            .bind(Task::inserted_details(workspace_db, &self.details))
+            .execute()
             .await?;
 
+        if result.rows_affected() == 0 {
+            return Err(AppError::QuotaExceeded(workspace_db.workspace.max_tasks));
+        }
+
         let id = result.last_insert_rowid();
+        Task::seal_inserted(workspace_db, &mut tx, id, &self.details).await?;
+        tx.commit().await?;
 
         let new_task = Task {
             id,
+            list_id: self.list_id,
             title: self.non_empty_title.to_owned(),
             details: self.details.to_owned(),
+            version: 1,
         };
 
         Ok(new_task)
@@ -59,87 +151,441 @@ impl InsertTask {
 }
 
 impl UpdateTask {
-    pub(crate) async fn update(&self, db_pool: &SqlitePool) -> Result<u64, AppError> {
-        let mut connection = db_pool.acquire().await?;
-        let result = sqlx::query(UPDATE)
+    pub(crate) fn validate(&self) -> Result<(), AppError> {
+        if self.new_title.trim().is_empty() {
+            Err(AppError::EmptyTitle)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// NOTE(alex): `expected_version` makes the update conditional (`If-Match`), it only applies
+    /// if the task is still at that version.
+    pub(crate) async fn update(
+        &self,
+        workspace_db: &WorkspaceDb<'_>,
+        expected_version: Option<i64>,
+    ) -> Result<u64, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let result = tx
+            .query(UPDATE)
             .bind(&self.new_title)
-            .bind(&self.details)
+            .bind(&Task::sealed_details(workspace_db, self.id, &self.details)?)
             .bind(&self.id)
-            .execute(&mut connection)
+            .bind(expected_version)
+            .execute()
             .await?;
+        tx.commit().await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+impl TaskDocument {
+    pub(crate) async fn validate(&self, workspace_db: &WorkspaceDb<'_>) -> Result<(), AppError> {
+        if self.title.trim().is_empty() {
+            return Err(AppError::EmptyTitle);
+        }
+
+        if let Some(list_id) = self.list_id {
+            let mut tx = workspace_db.begin().await?;
+            let list: Option<TaskList> = tx
+                .query_as(FIND_LIST_BY_ID)
+                .bind(list_id)
+                .fetch_optional()
+                .await?;
+
+            if list.is_none() {
+                return Err(AppError::InvalidTask(format!(
+                    "list `{}` not found",
+                    list_id
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Overwrites every writable field of the task, conditional on `expected_version` like
+    /// `UpdateTask::update`.
+    pub(crate) async fn replace(
+        &self,
+        workspace_db: &WorkspaceDb<'_>,
+        task_id: i64,
+        expected_version: Option<i64>,
+    ) -> Result<u64, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let result = tx
+            .query(REPLACE)
+            .bind(&self.list_id)
+            .bind(&self.title)
+            .bind(&Task::sealed_details(workspace_db, task_id, &self.details)?)
+            .bind(task_id)
+            .bind(expected_version)
+            .execute()
+            .await?;
+        tx.commit().await?;
 
         Ok(result.rows_affected())
     }
 }
 
 impl Task {
-    pub(crate) async fn delete(db_pool: &SqlitePool, task_id: i64) -> Result<u64, AppError> {
-        let mut connection = db_pool.acquire().await?;
-        let result = sqlx::query(DELETE)
+    pub(crate) fn document(&self) -> TaskDocument {
+        TaskDocument {
+            title: self.title.clone(),
+            details: self.details.clone(),
+            list_id: self.list_id,
+        }
+    }
+
+    /// Encrypts `details` when `details_encryption` is enabled, otherwise stores them as they are.
+    pub(crate) fn sealed_details(
+        workspace_db: &WorkspaceDb<'_>,
+        task_id: i64,
+        details: &str,
+    ) -> Result<String, AppError> {
+        match workspace_db.cipher() {
+            Some(cipher) => cipher.seal(workspace_db.workspace.id, task_id, details),
+            None => Ok(details.to_string()),
+        }
+    }
+
+    /// The `details` an insert writes, `None` when they're encrypted: sealed details are bound to
+    /// the id of the task, so [`Task::seal_inserted`] writes them once the insert assigned it.
+    pub(crate) fn inserted_details<'d>(
+        workspace_db: &WorkspaceDb<'_>,
+        details: &'d str,
+    ) -> Option<&'d str> {
+        match workspace_db.cipher() {
+            Some(_) => None,
+            None => Some(details),
+        }
+    }
+
+    /// NOTE(alex): Runs in the transaction of the insert, nobody ever reads the `null` details.
+    pub(crate) async fn seal_inserted(
+        workspace_db: &WorkspaceDb<'_>,
+        tx: &mut WorkspaceTx,
+        task_id: i64,
+        details: &str,
+    ) -> Result<(), AppError> {
+        if workspace_db.cipher().is_some() {
+            tx.query(SEAL_INSERTED_DETAILS)
+                .bind(Task::sealed_details(workspace_db, task_id, details)?)
+                .bind(task_id)
+                .execute()
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn opened(mut self, workspace_db: &WorkspaceDb<'_>) -> Result<Self, AppError> {
+        if let Some(cipher) = workspace_db.cipher() {
+            self.details = cipher.open(workspace_db.workspace.id, self.id, &self.details)?;
+        }
+
+        Ok(self)
+    }
+
+    pub(crate) async fn delete(
+        workspace_db: &WorkspaceDb<'_>,
+        task_id: i64,
+        expected_version: Option<i64>,
+    ) -> Result<u64, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let result = tx
+            .query(DELETE)
             .bind(task_id)
-            .execute(&mut connection)
+            .bind(expected_version)
+            .execute()
             .await?;
+        tx.commit().await?;
 
         Ok(result.rows_affected())
     }
 
-    pub(crate) async fn done(pool: &SqlitePool, task_id: i64) -> Result<i64, AppError> {
-        let mut connection = pool.acquire().await?;
-        let result = sqlx::query(COMPLETED)
+    pub(crate) async fn done(
+        workspace_db: &WorkspaceDb<'_>,
+        task_id: i64,
+        expected_version: Option<i64>,
+    ) -> Result<i64, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let result = tx
+            .query(COMPLETED)
             .bind(task_id)
-            .execute(&mut connection)
+            .bind(expected_version)
+            .execute()
             .await?;
+        tx.commit().await?;
+
+        // NOTE(alex): `last_insert_rowid` is whatever the connection inserted last, which is stale
+        // when nothing was inserted.
+        if result.rows_affected() == 0 {
+            return Ok(0);
+        }
 
         Ok(result.last_insert_rowid())
     }
 
-    pub(crate) async fn undo(db_pool: &SqlitePool, task_id: i64) -> Result<u64, AppError> {
-        let mut connection = db_pool.acquire().await?;
-        let result = sqlx::query(UNDO)
+    pub(crate) async fn undo(
+        workspace_db: &WorkspaceDb<'_>,
+        task_id: i64,
+        expected_version: Option<i64>,
+    ) -> Result<u64, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let result = tx
+            .query(UNDO)
             .bind(task_id)
-            .execute(&mut connection)
+            .bind(expected_version)
+            .execute()
             .await?;
+        tx.commit().await?;
 
         Ok(result.rows_affected())
     }
 
-    pub(crate) async fn find_all(db_pool: &SqlitePool) -> Result<Vec<Self>, AppError> {
-        let result = sqlx::query_as(FIND_ALL).fetch_all(db_pool).await?;
+    pub(crate) async fn is_done(
+        workspace_db: &WorkspaceDb<'_>,
+        task_id: i64,
+    ) -> Result<bool, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let (done,): (bool,) = tx.query_as(IS_DONE).bind(task_id).fetch_one().await?;
 
-        Ok(result)
+        Ok(done)
     }
 
-    pub(crate) async fn find_ongoing(db_pool: &SqlitePool) -> Result<Vec<Self>, AppError> {
-        let result = sqlx::query_as(FIND_ONGOING).fetch_all(db_pool).await?;
+    /// The ones of `task_ids` that are done, in a single query.
+    pub(crate) async fn find_done_among(
+        workspace_db: &WorkspaceDb<'_>,
+        task_ids: &[i64],
+    ) -> Result<HashSet<i64>, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let done: Vec<(i64,)> = tx
+            .query_as(FIND_DONE_AMONG)
+            .bind(json_ids(task_ids)?)
+            .fetch_all()
+            .await?;
 
-        Ok(result)
+        Ok(done.into_iter().map(|(task_id,)| task_id).collect())
+    }
+
+    pub(crate) async fn find_all(workspace_db: &WorkspaceDb<'_>) -> Result<Vec<Self>, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let result: Vec<Self> = tx.query_as(FIND_ALL).fetch_all().await?;
+
+        result
+            .into_iter()
+            .map(|task| task.opened(workspace_db))
+            .collect()
+    }
+
+    pub(crate) async fn find_ongoing(
+        workspace_db: &WorkspaceDb<'_>,
+    ) -> Result<Vec<Self>, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let result: Vec<Self> = tx.query_as(FIND_ONGOING).fetch_all().await?;
+
+        result
+            .into_iter()
+            .map(|task| task.opened(workspace_db))
+            .collect()
     }
 
     pub(crate) async fn find_by_pattern(
-        db_pool: &SqlitePool,
+        workspace_db: &WorkspaceDb<'_>,
         search_pattern: &str,
     ) -> Result<Vec<Self>, AppError> {
-        let result = sqlx::query_as(FIND_BY_PATTERN)
+        let mut tx = workspace_db.begin().await?;
+        let result: Vec<Self> = tx
+            .query_as(FIND_BY_PATTERN)
             .bind(search_pattern)
-            .fetch_all(db_pool)
+            .fetch_all()
             .await?;
 
-        Ok(result)
+        result
+            .into_iter()
+            .map(|task| task.opened(workspace_db))
+            .collect()
     }
 
     pub(crate) async fn find_by_id(
-        db_pool: &SqlitePool,
+        workspace_db: &WorkspaceDb<'_>,
         task_id: i64,
     ) -> Result<Option<Self>, AppError> {
-        let result = sqlx::query_as(FIND_BY_ID)
+        let mut tx = workspace_db.begin().await?;
+        let result: Option<Self> = tx
+            .query_as(FIND_BY_ID)
+            .bind(task_id)
+            .fetch_optional()
+            .await?;
+
+        result.map(|task| task.opened(workspace_db)).transpose()
+    }
+
+    pub(crate) async fn tag(
+        workspace_db: &WorkspaceDb<'_>,
+        task_id: i64,
+        tag_id: i64,
+    ) -> Result<u64, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let result = tx
+            .query(TAG_TASK)
+            .bind(task_id)
+            .bind(tag_id)
+            .execute()
+            .await?;
+        tx.commit().await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub(crate) async fn untag(
+        workspace_db: &WorkspaceDb<'_>,
+        task_id: i64,
+        tag_id: i64,
+    ) -> Result<u64, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let result = tx
+            .query(UNTAG_TASK)
             .bind(task_id)
-            .fetch_optional(db_pool)
+            .bind(tag_id)
+            .execute()
             .await?;
+        tx.commit().await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+impl InsertTaskList {
+    pub(crate) async fn insert(
+        &self,
+        workspace_db: &WorkspaceDb<'_>,
+    ) -> Result<TaskList, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let result = tx.query(INSERT_LIST).bind(&self.name).execute().await?;
+        tx.commit().await?;
+
+        let list = TaskList {
+            id: result.last_insert_rowid(),
+            name: self.name.to_owned(),
+        };
+
+        Ok(list)
+    }
+}
+
+impl TaskList {
+    pub(crate) async fn find_all(workspace_db: &WorkspaceDb<'_>) -> Result<Vec<Self>, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let result = tx.query_as(FIND_ALL_LISTS).fetch_all().await?;
 
         Ok(result)
     }
 }
 
+impl InsertTag {
+    pub(crate) async fn insert(&self, workspace_db: &WorkspaceDb<'_>) -> Result<Tag, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let result = tx.query(INSERT_TAG).bind(&self.name).execute().await?;
+        tx.commit().await?;
+
+        let tag = Tag {
+            id: result.last_insert_rowid(),
+            name: self.name.to_owned(),
+        };
+
+        Ok(tag)
+    }
+}
+
+impl Tag {
+    pub(crate) async fn find_all(workspace_db: &WorkspaceDb<'_>) -> Result<Vec<Self>, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let result = tx.query_as(FIND_ALL_TAGS).fetch_all().await?;
+
+        Ok(result)
+    }
+
+    pub(crate) async fn find_by_task(
+        workspace_db: &WorkspaceDb<'_>,
+        task_id: i64,
+    ) -> Result<Vec<Self>, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let result = tx
+            .query_as(FIND_TAGS_BY_TASK)
+            .bind(task_id)
+            .fetch_all()
+            .await?;
+
+        Ok(result)
+    }
+
+    /// The tags of every one of `task_ids` (paired with the task id), in a single query.
+    pub(crate) async fn find_by_tasks(
+        workspace_db: &WorkspaceDb<'_>,
+        task_ids: &[i64],
+    ) -> Result<Vec<(i64, Self)>, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let rows: Vec<(i64, i64, String)> = tx
+            .query_as(FIND_TAGS_BY_TASKS)
+            .bind(json_ids(task_ids)?)
+            .fetch_all()
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(task_id, id, name)| (task_id, Tag { id, name }))
+            .collect())
+    }
+}
+
+impl Change {
+    /// The changes of every one of `task_ids`, oldest first, in a single query.
+    pub(crate) async fn find_by_tasks(
+        workspace_db: &WorkspaceDb<'_>,
+        task_ids: &[i64],
+    ) -> Result<Vec<Self>, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let result = tx
+            .query_as(FIND_CHANGES_BY_TASKS)
+            .bind(json_ids(task_ids)?)
+            .fetch_all()
+            .await?;
+
+        Ok(result)
+    }
+}
+
+/// NOTE(alex): The batch queries read their ids with `json_each`, sqlx can't bind a list.
+fn json_ids(ids: &[i64]) -> Result<String, AppError> {
+    serde_json::to_string(ids).map_err(|_| AppError::Internal)
+}
+
+impl<'r> FromBody<'r> for InsertTask {
+    type Error = AppError;
+
+    fn limit(req: &'r rocket::Request<'_>) -> ByteUnit {
+        req.limits().get("self").unwrap_or(512.bytes())
+    }
+
+    fn from_body(
+        req: &'r rocket::Request<'_>,
+        body: Vec<u8>,
+    ) -> Result<Self, (Status, Self::Error)> {
+        let insert: InsertTask = deserialize_body(req, &body)?;
+        if let Err(fail) = insert.validate() {
+            // TODO(alex) [high] 2021-06-22: This is where the error is returned from (the actual
+            // response), read the note in errors.rs.
+            return Err((Status::UnprocessableEntity, fail));
+        }
+
+        Ok(insert)
+    }
+}
+
 #[rocket::async_trait]
 impl<'r> FromData<'r> for InsertTask {
     type Error = AppError;
@@ -148,23 +594,30 @@ impl<'r> FromData<'r> for InsertTask {
         req: &'r rocket::Request<'_>,
         data: rocket::Data<'r>,
     ) -> rocket::data::Outcome<'r, Self> {
-        let limit = req.limits().get("self").unwrap_or(512.bytes());
-        let as_string = match data.open(limit).into_string().await {
-            Ok(string) if string.is_complete() => string.into_inner(),
-            Ok(_) => return Outcome::Failure((Status::PayloadTooLarge, AppError::Internal)),
-            Err(fail) => {
-                return Outcome::Failure((Status::InternalServerError, AppError::IO(fail)));
-            }
-        };
+        read_body(req, data).await
+    }
+}
+
+impl<'r> FromBody<'r> for UpdateTask {
+    type Error = AppError;
+
+    fn limit(req: &'r rocket::Request<'_>) -> ByteUnit {
+        req.limits().get("self").unwrap_or(512.bytes())
+    }
 
-        let insert: InsertTask = serde_json::from_str(&as_string).unwrap();
-        if insert.non_empty_title.trim().is_empty() {
+    fn from_body(
+        req: &'r rocket::Request<'_>,
+        body: Vec<u8>,
+    ) -> Result<Self, (Status, Self::Error)> {
+        let update: UpdateTask = deserialize_body(req, &body)?;
+
+        if let Err(fail) = update.validate() {
             // TODO(alex) [high] 2021-06-22: This is where the error is returned from (the actual
             // response), read the note in errors.rs.
-            return Outcome::Failure((Status::UnprocessableEntity, AppError::EmptyTitle));
+            return Err((Status::UnprocessableEntity, fail));
         }
 
-        Outcome::Success(insert)
+        Ok(update)
     }
 }
 
@@ -176,23 +629,6 @@ impl<'r> FromData<'r> for UpdateTask {
         req: &'r rocket::Request<'_>,
         data: rocket::Data<'r>,
     ) -> rocket::data::Outcome<'r, Self> {
-        let limit = req.limits().get("self").unwrap_or(512.bytes());
-        let as_string = match data.open(limit).into_string().await {
-            Ok(string) if string.is_complete() => string.into_inner(),
-            Ok(_) => return Outcome::Failure((Status::PayloadTooLarge, AppError::Internal)),
-            Err(fail) => {
-                return Outcome::Failure((Status::InternalServerError, AppError::IO(fail)))
-            }
-        };
-
-        let update: UpdateTask = serde_json::from_str(&as_string).unwrap();
-
-        if update.new_title.trim().is_empty() {
-            // TODO(alex) [high] 2021-06-22: This is where the error is returned from (the actual
-            // response), read the note in errors.rs.
-            return Outcome::Failure((Status::UnprocessableEntity, AppError::EmptyTitle));
-        }
-
-        Outcome::Success(update)
+        read_body(req, data).await
     }
 }
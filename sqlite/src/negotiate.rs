@@ -0,0 +1,228 @@
+use std::{
+    cmp::Ordering,
+    io::{self, Cursor},
+};
+
+use rocket::{
+    data::{Data, Outcome},
+    http::{ContentType, MediaType, Status},
+    response::{self, Responder},
+    serde::{de::DeserializeOwned, json::serde_json, Serialize},
+    Request, Response,
+};
+
+use fairings::FromBody;
+
+use crate::errors::AppError;
+
+/// Formats tasks can be read and written in, JSON when the client doesn't say.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    Json,
+    MessagePack,
+    Cbor,
+    Csv,
+    Yaml,
+}
+
+impl Format {
+    /// Every format, in the order a wildcard (`*/*`, `application/*`, `text/*`) picks them.
+    const OFFERED: [Format; 5] = [
+        Format::Json,
+        Format::MessagePack,
+        Format::Cbor,
+        Format::Csv,
+        Format::Yaml,
+    ];
+
+    /// Names of the `?format=` override.
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Format::Json),
+            "msgpack" => Some(Format::MessagePack),
+            "cbor" => Some(Format::Cbor),
+            "csv" => Some(Format::Csv),
+            "yaml" => Some(Format::Yaml),
+            _ => None,
+        }
+    }
+
+    /// NOTE(alex): MessagePack and YAML have no registered media type, so the usual aliases are
+    /// accepted too.
+    fn from_media_type(media_type: &MediaType) -> Option<Self> {
+        let top = media_type.top().as_str().to_ascii_lowercase();
+        let sub = media_type.sub().as_str().to_ascii_lowercase();
+
+        match (top.as_str(), sub.as_str()) {
+            ("application", "json") => Some(Format::Json),
+            ("application", "msgpack")
+            | ("application", "x-msgpack")
+            | ("application", "vnd.msgpack") => Some(Format::MessagePack),
+            ("application", "cbor") => Some(Format::Cbor),
+            ("text", "csv") => Some(Format::Csv),
+            ("application", "yaml")
+            | ("application", "x-yaml")
+            | ("text", "yaml")
+            | ("text", "x-yaml") => Some(Format::Yaml),
+            _ => None,
+        }
+    }
+
+    fn content_type(&self) -> ContentType {
+        match self {
+            Format::Json => ContentType::JSON,
+            Format::MessagePack => ContentType::MsgPack,
+            Format::Cbor => ContentType::new("application", "cbor"),
+            Format::Csv => ContentType::CSV,
+            Format::Yaml => ContentType::new("application", "yaml"),
+        }
+    }
+
+    /// The first offered format a media type of `Accept` matches, wildcards included.
+    fn from_accepted(media_type: &MediaType) -> Option<Self> {
+        match (media_type.top().as_str(), media_type.sub().as_str()) {
+            ("*", "*") => Some(Format::Json),
+            (_, "*") => Format::OFFERED
+                .iter()
+                .copied()
+                .find(|format| format.content_type().top() == media_type.top()),
+            _ => Format::from_media_type(media_type),
+        }
+    }
+
+    /// `?format=` wins over `Accept`, which is tried from the most preferred media type down
+    /// (`*/*`, or no `Accept` at all, is JSON, `text/*` is CSV).
+    pub(crate) fn negotiate(req: &Request<'_>) -> Result<Self, AppError> {
+        if let Some(name) = req.query_value::<&str>("format") {
+            return name
+                .ok()
+                .and_then(Format::from_name)
+                .ok_or(AppError::NotAcceptable);
+        }
+
+        let accept = match req.accept() {
+            Some(accept) => accept,
+            None => return Ok(Format::Json),
+        };
+
+        let mut preferred: Vec<_> = accept
+            .iter()
+            .filter(|media_type| media_type.weight_or(1.0) > 0.0)
+            .collect();
+        preferred.sort_by(|a, b| {
+            b.weight_or(1.0)
+                .partial_cmp(&a.weight_or(1.0))
+                .unwrap_or(Ordering::Equal)
+        });
+
+        preferred
+            .into_iter()
+            .find_map(|media_type| Format::from_accepted(media_type.media_type()))
+            .ok_or(AppError::NotAcceptable)
+    }
+
+    /// CSV gets a header row, then one row per value.
+    pub(crate) fn serialize<T: Serialize>(&self, values: &[T]) -> Result<Vec<u8>, AppError> {
+        match self {
+            Format::Json => serde_json::to_vec(values).map_err(|_| AppError::Internal),
+            Format::MessagePack => rmp_serde::to_vec_named(values).map_err(|_| AppError::Internal),
+            Format::Cbor => serde_cbor::to_vec(&values).map_err(|_| AppError::Internal),
+            Format::Yaml => serde_yaml::to_string(values)
+                .map(String::into_bytes)
+                .map_err(|_| AppError::Internal),
+            Format::Csv => {
+                let mut writer = csv::Writer::from_writer(Vec::new());
+                for value in values {
+                    writer.serialize(value).map_err(|_| AppError::Internal)?;
+                }
+
+                writer.into_inner().map_err(|_| AppError::Internal)
+            }
+        }
+    }
+
+    /// CSV bodies are a header row, and the row of the value.
+    pub(crate) fn deserialize<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, AppError> {
+        let result = match self {
+            Format::Json => serde_json::from_slice(bytes).map_err(|fail| fail.to_string()),
+            Format::MessagePack => rmp_serde::from_slice(bytes).map_err(|fail| fail.to_string()),
+            Format::Cbor => serde_cbor::from_slice(bytes).map_err(|fail| fail.to_string()),
+            Format::Yaml => serde_yaml::from_slice(bytes).map_err(|fail| fail.to_string()),
+            Format::Csv => match csv::Reader::from_reader(bytes).deserialize().next() {
+                Some(row) => row.map_err(|fail| fail.to_string()),
+                None => Err("missing the row after the header".to_string()),
+            },
+        };
+
+        result.map_err(AppError::InvalidTask)
+    }
+}
+
+/// Deserializes a body in the format of its `Content-Type` (JSON when it has none).
+pub(crate) fn deserialize_body<T: DeserializeOwned>(
+    req: &Request<'_>,
+    body: &[u8],
+) -> Result<T, (Status, AppError)> {
+    let format = match req.content_type() {
+        Some(content_type) => match Format::from_media_type(content_type.media_type()) {
+            Some(format) => format,
+            None => return Err((Status::UnsupportedMediaType, AppError::UnsupportedFormat)),
+        },
+        None => Format::Json,
+    };
+
+    format
+        .deserialize(body)
+        .map_err(|fail| (Status::UnprocessableEntity, fail))
+}
+
+/// Reads the whole body (within its limit) for the data guards, they're built from it like the
+/// `Idempotent` guard builds them.
+pub(crate) async fn read_body<'r, T: FromBody<'r, Error = AppError>>(
+    req: &'r Request<'_>,
+    data: Data<'r>,
+) -> Outcome<'r, T, AppError> {
+    let body = match data.open(T::limit(req)).into_bytes().await {
+        Ok(body) if body.is_complete() => body.into_inner(),
+        Ok(_) => return Outcome::Failure((Status::PayloadTooLarge, AppError::Internal)),
+        Err(fail) => return Outcome::Failure((Status::InternalServerError, AppError::IO(fail))),
+    };
+
+    match T::from_body(req, body) {
+        Ok(guard) => Outcome::Success(guard),
+        Err(failure) => Outcome::Failure(failure),
+    }
+}
+
+/// A body that must be UTF-8 text, failing like `DataStream::into_string` does.
+pub(crate) fn body_text(body: Vec<u8>) -> Result<String, (Status, AppError)> {
+    String::from_utf8(body).map_err(|fail| {
+        (
+            Status::InternalServerError,
+            AppError::IO(io::Error::new(io::ErrorKind::InvalidData, fail)),
+        )
+    })
+}
+
+/// A list in the format the client asked for, see [`Format::negotiate`].
+pub(crate) struct Negotiated<T>(pub(crate) Vec<T>);
+
+impl<'r, T: Serialize> Responder<'r, 'static> for Negotiated<T> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let format = match Format::negotiate(req) {
+            Ok(format) => format,
+            Err(fail) => return fail.respond_to(req),
+        };
+
+        let body = match format.serialize(&self.0) {
+            Ok(body) => body,
+            Err(fail) => return fail.respond_to(req),
+        };
+
+        Response::build()
+            .header(format.content_type())
+            .raw_header("Vary", "Accept")
+            .sized_body(body.len(), Cursor::new(body))
+            .ok()
+    }
+}
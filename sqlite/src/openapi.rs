@@ -0,0 +1,256 @@
+use log::warn;
+use rocket::{
+    fairing::{self, Fairing, Info, Kind},
+    get,
+    http::{Header, Method},
+    response::{self, content, Responder},
+    serde::json::Json,
+    Build, Request, Rocket, Route, State,
+};
+use utoipa::{
+    openapi::{
+        self,
+        security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    },
+    Modify, OpenApi,
+};
+
+use crate::{graphql, routes};
+
+const SWAGGER_UI: &'static str = include_str!("./../strings/swagger_ui.html");
+
+/// Swagger UI pulls its scripts and styles from unpkg.
+const SWAGGER_UI_CSP: &'static str = "default-src 'self'; \
+    script-src 'self' 'unsafe-inline' https://unpkg.com; \
+    style-src 'self' 'unsafe-inline' https://unpkg.com; \
+    img-src 'self' data:; \
+    frame-ancestors 'none'";
+
+/// Routes that are not part of the API, every other mounted route must be in [`ApiDoc`].
+const UNDOCUMENTED: &[(Method, &'static str)] = &[
+    (Method::Get, "/"),
+    (Method::Get, "/openapi.json"),
+    (Method::Get, "/docs"),
+    (Method::Get, "/static/<path..>"),
+    // NOTE(alex): GraphiQL, debug builds only.
+    (Method::Get, "/graphql"),
+    // NOTE(alex): Only reached by requests the `RateLimiter` fairing redirected.
+    (Method::Get, "/rate-limited"),
+    // NOTE(alex): Where the `Csrf` fairing sends the requests it rejects.
+    (Method::Get, "/csrf/rejected/<reason>"),
+];
+
+/// Prefix of the HTML pages (`ui.rs`), they're for browsers, not API clients.
+const UNDOCUMENTED_PREFIX: &'static str = "/ui";
+
+/// OpenAPI document of the HTTP routes, served on `/openapi.json`.
+///
+/// NOTE(alex): Paths and their parameters come from the route attributes (`rocket_extras`), and
+/// the schemas from the models, only the responses are written by hand (`#[utoipa::path]`).
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Todo",
+        description = "Every route belongs to the workspace of the API token (`Authorization: \
+            Bearer`). The `/w/{slug}` prefix or the `{slug}.localhost` subdomain only select it, \
+            and must name the workspace of the token. Errors are plain text."
+    ),
+    servers(
+        (url = "/", description = "Workspace of the API token"),
+        (url = "/w/{slug}", description = "Workspace of the API token, the slug must match it",
+            variables(("slug" = (default = "default")))
+        ),
+    ),
+    paths(
+        routes::insert,
+        routes::batch_tasks,
+        routes::bulk_tasks,
+        routes::update,
+        routes::replace_task,
+        routes::patch_task,
+        routes::delete,
+        routes::done,
+        routes::undo,
+        routes::find_all,
+        routes::find_ongoing,
+        routes::task_events,
+        routes::find_by_id,
+        routes::current_workspace,
+        routes::calendar_feed,
+        routes::rotate_calendar_feed,
+        routes::tasks_calendar,
+        routes::tasks_feed,
+        routes::import_calendar,
+        routes::export_tasks,
+        routes::import_tasks,
+        routes::export_csv,
+        routes::export_json_lines,
+        routes::import_csv,
+        routes::import_json_lines,
+        routes::import_source,
+        routes::insert_list,
+        routes::find_all_lists,
+        routes::insert_tag,
+        routes::find_all_tags,
+        routes::tag,
+        routes::untag,
+        routes::pull,
+        routes::push,
+        graphql::graphql_request,
+    ),
+    modifiers(&ApiToken),
+    security((), ("token" = [])),
+    tags(
+        (name = "tasks"),
+        (name = "lists"),
+        (name = "tags"),
+        (name = "sync", description = "Offline clients"),
+        (name = "workspace"),
+        (name = "calendar", description = "iCalendar and Atom feeds, and iCalendar import"),
+        (name = "checklists", description = "todo.txt and Markdown export and import"),
+        (name = "records", description = "CSV and JSON Lines export and import, streamed"),
+        (name = "sources", description = "Import from Taskwarrior and Trello"),
+        (name = "graphql"),
+    )
+)]
+pub(crate) struct ApiDoc;
+
+/// The `Authorization: Bearer {token}` of a workspace.
+struct ApiToken;
+
+impl Modify for ApiToken {
+    fn modify(&self, open_api: &mut openapi::OpenApi) {
+        let scheme = HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build();
+
+        open_api
+            .components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme("token", SecurityScheme::Http(scheme));
+    }
+}
+
+/// Whether the `(method, path)` of `route` is in the document, `/tasks/<id>` is documented as
+/// `/tasks/{id}`.
+fn is_documented(open_api: &openapi::OpenApi, route: &Route) -> bool {
+    let path = route
+        .uri
+        .path()
+        .split('/')
+        .map(|segment| match segment.strip_prefix('<') {
+            Some(name) => format!("{{{}}}", name.trim_end_matches('>').trim_end_matches("..")),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+
+    let item = match open_api.paths.paths.get(&path) {
+        Some(item) => item,
+        None => return false,
+    };
+
+    match route.method {
+        Method::Get => item.get.is_some(),
+        Method::Put => item.put.is_some(),
+        Method::Post => item.post.is_some(),
+        Method::Delete => item.delete.is_some(),
+        Method::Patch => item.patch.is_some(),
+        _ => false,
+    }
+}
+
+/// `METHOD /path` of the `routes` that are neither in the document, nor left out on purpose.
+fn undocumented<'r>(
+    open_api: &openapi::OpenApi,
+    routes: impl Iterator<Item = &'r Route>,
+) -> Vec<String> {
+    routes
+        .filter(|route| !route.uri.path().starts_with(UNDOCUMENTED_PREFIX))
+        .filter(|route| {
+            !UNDOCUMENTED
+                .iter()
+                .any(|(method, path)| route.method == *method && route.uri.path() == *path)
+        })
+        .filter(|route| !is_documented(open_api, route))
+        .map(|route| format!("{} {}", route.method, route.uri))
+        .collect()
+}
+
+/// Builds the [`ApiDoc`] once, and warns about the mounted routes missing from it.
+///
+/// NOTE(alex): Keeps the document from drifting like `welcome.txt` did, a new route must get its
+/// `#[utoipa::path]` (or be added to `UNDOCUMENTED`), `cargo test` fails until it does. At launch
+/// it's only a warning, a missing doc isn't worth refusing to serve.
+pub(crate) struct OpenApiDocs;
+
+#[rocket::async_trait]
+impl Fairing for OpenApiDocs {
+    fn info(&self) -> Info {
+        Info {
+            name: "OpenAPI",
+            kind: Kind::Ignite,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let open_api = ApiDoc::openapi();
+
+        let undocumented = undocumented(&open_api, rocket.routes());
+        if !undocumented.is_empty() {
+            warn!(
+                "Routes missing from the OpenAPI document: {}",
+                undocumented.join(", ")
+            );
+        }
+
+        Ok(rocket.manage(open_api))
+    }
+}
+
+/// The Swagger UI page, with its own `Content-Security-Policy` (the default one blocks it).
+pub(crate) struct SwaggerUi;
+
+impl<'r> Responder<'r, 'static> for SwaggerUi {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = content::Html(SWAGGER_UI).respond_to(req)?;
+        response.set_header(Header::new("Content-Security-Policy", SWAGGER_UI_CSP));
+
+        Ok(response)
+    }
+}
+
+#[get("/openapi.json")]
+pub(crate) async fn openapi_json(open_api: &State<openapi::OpenApi>) -> Json<&openapi::OpenApi> {
+    Json(open_api.inner())
+}
+
+#[get("/docs")]
+pub(crate) async fn docs() -> SwaggerUi {
+    SwaggerUi
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_mounted_route_is_documented() {
+        let open_api = ApiDoc::openapi();
+        let routes = crate::mounted_routes();
+
+        assert_eq!(undocumented(&open_api, routes.iter()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn route_parameters_match_path_templates() {
+        let open_api = ApiDoc::openapi();
+        let find_by_id = crate::mounted_routes()
+            .into_iter()
+            .find(|route| route.method == Method::Get && route.uri.path() == "/tasks/<id>")
+            .unwrap();
+        let mut options = find_by_id.clone();
+        options.method = Method::Options;
+
+        assert!(is_documented(&open_api, &find_by_id));
+        assert!(!is_documented(&open_api, &options));
+    }
+}
@@ -0,0 +1,42 @@
+use fairings::{Preconditions, TaskPatch};
+
+use crate::{errors::AppError, etag::expected_version, models::Task, workspace::WorkspaceDb};
+
+/// How many times a patch is re-applied when another write to the task gets in first.
+const MAX_ATTEMPTS: usize = 3;
+
+/// Applies the whole patch to one version of the task, and writes it only if the task is still at
+/// that version, so it never mixes with a concurrent write.
+///
+/// NOTE(alex): Without `If-Match` a lost race re-reads the task and tries again, with it the
+/// re-read fails the precondition.
+pub(crate) async fn apply_to(
+    task_patch: &TaskPatch,
+    workspace_db: &WorkspaceDb<'_>,
+    preconditions: &Preconditions,
+    task_id: i64,
+) -> Result<Task, AppError> {
+    for _ in 0..MAX_ATTEMPTS {
+        let current = Task::find_by_id(workspace_db, task_id)
+            .await?
+            .ok_or(AppError::IdNotFound(task_id))?;
+
+        let expected_version =
+            expected_version(preconditions, Some(&current))?.unwrap_or(current.version);
+
+        let document = task_patch.apply(current.document())?;
+        document.validate(workspace_db).await?;
+
+        if document
+            .replace(workspace_db, task_id, Some(expected_version))
+            .await?
+            > 0
+        {
+            return Task::find_by_id(workspace_db, task_id)
+                .await?
+                .ok_or(AppError::IdNotFound(task_id));
+        }
+    }
+
+    Err(AppError::PreconditionFailed)
+}
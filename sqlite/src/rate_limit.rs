@@ -0,0 +1,306 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use log::{error, info};
+use rocket::{
+    fairing::{self, Fairing, Info, Kind},
+    get,
+    http::{uri::Origin, Header, Method, Status},
+    response::status::Custom,
+    serde::Deserialize,
+    tokio, Build, Data, Orbit, Request, Response, Rocket,
+};
+use sqlx::SqlitePool;
+
+use crate::workspace::Workspace;
+
+/// A route group as configured in `Rocket.toml`, requests are matched against the groups in order,
+/// and the first match decides which bucket they take tokens from.
+#[derive(Debug, Clone, Deserialize)]
+struct RouteGroup {
+    name: String,
+    /// Path prefix, `/tasks` also matches `/tasks/1/done`.
+    path: String,
+    /// Empty means any method.
+    #[serde(default)]
+    methods: Vec<String>,
+    /// Only match when this query parameter is present (e.g. `pattern` for the search route).
+    query: Option<String>,
+    capacity: u32,
+    refill_per_second: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct RateLimitConfig {
+    /// How often (seconds) idle buckets are swept from memory.
+    #[serde(default = "default_eviction_interval")]
+    eviction_interval: u64,
+    /// Buckets untouched for this long (seconds) are dropped, they would be full anyway.
+    #[serde(default = "default_idle_timeout")]
+    idle_timeout: u64,
+    /// Most buckets kept in memory, the least recently used one makes room for a new client.
+    #[serde(default = "default_max_buckets")]
+    max_buckets: usize,
+    #[serde(default)]
+    groups: Vec<RouteGroup>,
+}
+
+fn default_eviction_interval() -> u64 {
+    60
+}
+
+fn default_idle_timeout() -> u64 {
+    600
+}
+
+fn default_max_buckets() -> usize {
+    10_000
+}
+
+impl RouteGroup {
+    fn matches(&self, method: &str, path: &str, has_query: impl Fn(&str) -> bool) -> bool {
+        let method_matches = self.methods.is_empty()
+            || self
+                .methods
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(method));
+
+        let query_matches = match &self.query {
+            Some(key) => has_query(key),
+            None => true,
+        };
+
+        method_matches && query_matches && path.starts_with(&self.path)
+    }
+
+    fn matches_request(&self, req: &Request<'_>) -> bool {
+        self.matches(req.method().as_str(), req.uri().path().as_str(), |key| {
+            req.query_value::<&str>(key).is_some()
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Outcome of taking a token, cached in the request so `on_response` can set the headers.
+#[derive(Debug, Clone, Copy)]
+struct Decision {
+    limit: u32,
+    remaining: u32,
+    reset: u64,
+    retry_after: Option<u64>,
+}
+
+type Buckets = Arc<Mutex<HashMap<(String, String), Bucket>>>;
+
+/// Token-bucket rate limiter, keyed by route group and client (workspace, or IP address).
+///
+/// NOTE(alex): Like the workspace prefix, a rejected request is rewritten into a `GET` to the
+/// `rate_limited` route, so the original handler never runs.
+#[derive(Clone, Default)]
+pub(crate) struct RateLimiter {
+    buckets: Buckets,
+}
+
+/// The config and buckets of the `RateLimiter`, managed so the connections Rocket doesn't serve
+/// (the websocket) take from the same buckets.
+#[derive(Clone)]
+pub(crate) struct RateLimits {
+    config: RateLimitConfig,
+    limiter: RateLimiter,
+}
+
+impl RateLimits {
+    /// Takes a token for a command that does what `method` on `path` does over HTTP, the seconds
+    /// to wait before retrying when none is left.
+    pub(crate) fn retry_after(&self, method: &str, path: &str, client: String) -> Option<u64> {
+        let group = self
+            .config
+            .groups
+            .iter()
+            .find(|group| group.matches(method, path, |_| false))?;
+
+        self.limiter.take(&self.config, group, client).retry_after
+    }
+}
+
+impl RateLimiter {
+    fn take(&self, config: &RateLimitConfig, group: &RouteGroup, client: String) -> Decision {
+        let capacity = group.capacity as f64;
+        let now = Instant::now();
+        let key = (group.name.clone(), client);
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= config.max_buckets && !buckets.contains_key(&key) {
+            let least_recent = buckets
+                .iter()
+                .min_by_key(|(_, bucket)| bucket.last_refill)
+                .map(|(key, _)| key.clone());
+
+            if let Some(least_recent) = least_recent {
+                buckets.remove(&least_recent);
+            }
+        }
+
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: capacity,
+            last_refill: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * group.refill_per_second).min(capacity);
+        bucket.last_refill = now;
+
+        let retry_after = if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            None
+        } else {
+            Some(((1.0 - bucket.tokens) / group.refill_per_second).ceil() as u64)
+        };
+
+        Decision {
+            limit: group.capacity,
+            remaining: bucket.tokens.floor() as u32,
+            reset: ((capacity - bucket.tokens) / group.refill_per_second).ceil() as u64,
+            retry_after,
+        }
+    }
+}
+
+/// The client a workspace's requests are counted under, whichever way they come in.
+pub(crate) fn workspace_key(workspace_id: i64) -> String {
+    format!("workspace:{}", workspace_id)
+}
+
+/// NOTE(alex): Only a token that resolves to a workspace keys its own bucket, anything else
+/// (a made up token included) shares the bucket of its IP address, so new tokens don't get new
+/// buckets.
+async fn client_key(req: &Request<'_>) -> String {
+    let token = req
+        .headers()
+        .get_one("Authorization")
+        .and_then(|header| header.strip_prefix("Bearer "));
+
+    if let (Some(token), Some(db_pool)) = (token, req.rocket().state::<SqlitePool>()) {
+        if let Ok(Some(workspace)) = Workspace::find_by_token(db_pool, token).await {
+            return workspace_key(workspace.id);
+        }
+    }
+
+    match req.client_ip() {
+        Some(ip) => format!("ip:{}", ip),
+        None => "unknown".to_string(),
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for RateLimiter {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate limiter",
+            kind: Kind::Ignite | Kind::Liftoff | Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        if rocket.figment().find_value("rate_limit").is_err() {
+            return Ok(rocket);
+        }
+
+        match rocket
+            .figment()
+            .extract_inner::<RateLimitConfig>("rate_limit")
+        {
+            Ok(config) => Ok(rocket.manage(RateLimits {
+                config,
+                limiter: self.clone(),
+            })),
+            Err(fail) => {
+                error!("Invalid `rate_limit` configuration: {}", fail);
+                Err(rocket)
+            }
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let config = match rocket.state::<RateLimits>() {
+            Some(limits) => limits.config.clone(),
+            None => return,
+        };
+
+        let buckets = self.buckets.clone();
+        tokio::spawn(async move {
+            let idle_timeout = Duration::from_secs(config.idle_timeout);
+            let mut interval = tokio::time::interval(Duration::from_secs(config.eviction_interval));
+
+            loop {
+                interval.tick().await;
+
+                let mut buckets = buckets.lock().unwrap();
+                let before = buckets.len();
+                buckets.retain(|_, bucket| bucket.last_refill.elapsed() < idle_timeout);
+
+                if before != buckets.len() {
+                    info!(
+                        "Evicted {} idle rate limit buckets.",
+                        before - buckets.len()
+                    );
+                }
+            }
+        });
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _data: &mut Data<'_>) {
+        let config = match req.rocket().state::<RateLimits>() {
+            Some(limits) => &limits.config,
+            None => return,
+        };
+
+        let group = match config
+            .groups
+            .iter()
+            .find(|group| group.matches_request(req))
+        {
+            Some(group) => group,
+            None => return,
+        };
+
+        let decision = self.take(config, group, client_key(req).await);
+        req.local_cache(|| Some(decision));
+
+        if decision.retry_after.is_some() {
+            req.set_method(Method::Get);
+            req.set_uri(Origin::parse("/rate-limited").unwrap());
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let decision = match req.local_cache(|| None::<Decision>) {
+            Some(decision) => decision,
+            None => return,
+        };
+
+        res.set_header(Header::new("RateLimit-Limit", decision.limit.to_string()));
+        res.set_header(Header::new(
+            "RateLimit-Remaining",
+            decision.remaining.to_string(),
+        ));
+        res.set_header(Header::new("RateLimit-Reset", decision.reset.to_string()));
+
+        if let Some(retry_after) = decision.retry_after {
+            res.set_header(Header::new("Retry-After", retry_after.to_string()));
+        }
+    }
+}
+
+#[get("/rate-limited")]
+pub(crate) async fn rate_limited() -> Custom<&'static str> {
+    Custom(Status::TooManyRequests, "Too many requests, slow down!")
+}
@@ -0,0 +1,630 @@
+use log::error;
+use rocket::{
+    data::{ByteUnit, DataStream, FromData, Outcome, ToByteUnit},
+    futures::{Stream, StreamExt},
+    response::stream::TextStream,
+    serde::{
+        json::{serde_json, Value},
+        Serialize,
+    },
+    tokio::io::{AsyncBufReadExt, BufReader},
+    FromForm,
+};
+use utoipa::ToSchema;
+
+use crate::{
+    errors::AppError,
+    events::TaskEvents,
+    exchange::{is_date, is_due, ExportedTask, ImportedTask},
+    workspace::WorkspaceDb,
+};
+
+/// Columns of `GET /tasks/export.csv`, and keys of `GET /tasks/export.jsonl`, in order.
+const COLUMNS: [&str; 11] = [
+    "id",
+    "title",
+    "details",
+    "list",
+    "tags",
+    "due",
+    "priority",
+    "created_on",
+    "done",
+    "completed_at",
+    "version",
+];
+
+/// Tags are a single CSV cell, a JSON line has them as an array. A `;` (or `\`) in a tag is
+/// escaped with a `\`.
+const TAG_SEPARATOR: char = ';';
+
+/// An import stops reading after this many failed rows, nothing is committed anyway.
+const MAX_ERRORS: usize = 100;
+
+/// Every row is kept until the body is read, and inserted in one transaction, this keeps both
+/// bounded, like `MAX_ITEMS` of a checklist.
+const MAX_ROWS: usize = 10_000;
+
+/// A task as a CSV row or a JSON line.
+#[derive(Debug, Serialize)]
+struct TaskRecord {
+    id: i64,
+    title: String,
+    details: String,
+    list: Option<String>,
+    tags: Vec<String>,
+    due: Option<String>,
+    priority: Option<String>,
+    created_on: Option<String>,
+    done: bool,
+    completed_at: Option<String>,
+    version: i64,
+}
+
+impl From<ExportedTask> for TaskRecord {
+    fn from(task: ExportedTask) -> Self {
+        Self {
+            tags: task.tags().into_iter().map(str::to_string).collect(),
+            id: task.id,
+            title: task.title,
+            details: task.details,
+            list: task.list,
+            due: task.due,
+            priority: task.priority,
+            created_on: task.created_on,
+            done: task.completed_at.is_some(),
+            completed_at: task.completed_at,
+            version: task.version,
+        }
+    }
+}
+
+impl TaskRecord {
+    fn csv_row(&self) -> Result<String, csv::Error> {
+        let tags = join_tags(&self.tags);
+        let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+
+        csv_row(&[
+            self.id.to_string(),
+            self.title.clone(),
+            self.details.clone(),
+            optional(&self.list),
+            tags,
+            optional(&self.due),
+            optional(&self.priority),
+            optional(&self.created_on),
+            self.done.to_string(),
+            optional(&self.completed_at),
+            self.version.to_string(),
+        ])
+    }
+}
+
+/// The tags as a single cell, see [`TAG_SEPARATOR`].
+fn join_tags(tags: &[String]) -> String {
+    let escaped: Vec<String> = tags
+        .iter()
+        .map(|tag| {
+            tag.replace('\\', "\\\\")
+                .replace(TAG_SEPARATOR, &format!("\\{}", TAG_SEPARATOR))
+        })
+        .collect();
+
+    escaped.join(&TAG_SEPARATOR.to_string())
+}
+
+/// The reverse of [`join_tags`], a `\` keeps the character after it (a lone one at the end is
+/// dropped).
+fn split_tags(cell: &str) -> Vec<String> {
+    let mut tags = vec![String::new()];
+    let mut chars = cell.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => tags.last_mut().unwrap().extend(chars.next()),
+            TAG_SEPARATOR => tags.push(String::new()),
+            c => tags.last_mut().unwrap().push(c),
+        }
+    }
+
+    tags
+}
+
+fn csv_row<T: AsRef<[u8]>>(fields: &[T]) -> Result<String, csv::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(fields)?;
+
+    let bytes = writer
+        .into_inner()
+        .map_err(|fail| csv::Error::from(fail.into_error()))?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Every task of the workspace as CSV, a header, then a row per task, read from the database as
+/// they're sent.
+///
+/// NOTE(alex): The status is sent before the first row is read, a failure halfway through can
+/// only be logged, and the client gets a truncated body.
+pub(crate) fn csv_stream<'r>(
+    workspace_db: &WorkspaceDb<'r>,
+) -> TextStream<impl Stream<Item = String> + 'r> {
+    let workspace_db = workspace_db.clone();
+
+    TextStream! {
+        match csv_row(&COLUMNS) {
+            Ok(header) => yield header,
+            Err(fail) => {
+                error!("Failed to write the CSV header: {}", fail);
+                return;
+            }
+        }
+
+        let mut tx = match workspace_db.begin().await {
+            Ok(tx) => tx,
+            Err(fail) => {
+                error!("CSV export stopped: {}", fail);
+                return;
+            }
+        };
+        let mut tasks = ExportedTask::fetch(&workspace_db, &mut tx);
+
+        while let Some(task) = tasks.next().await {
+            match task.map(TaskRecord::from).and_then(|record| {
+                record.csv_row().map_err(|fail| AppError::InvalidTask(fail.to_string()))
+            }) {
+                Ok(row) => yield row,
+                Err(fail) => {
+                    error!("CSV export stopped: {}", fail);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Every task of the workspace as JSON Lines, an object per task, see [`csv_stream`].
+pub(crate) fn json_lines_stream<'r>(
+    workspace_db: &WorkspaceDb<'r>,
+) -> TextStream<impl Stream<Item = String> + 'r> {
+    let workspace_db = workspace_db.clone();
+
+    TextStream! {
+        let mut tx = match workspace_db.begin().await {
+            Ok(tx) => tx,
+            Err(fail) => {
+                error!("JSON Lines export stopped: {}", fail);
+                return;
+            }
+        };
+        let mut tasks = ExportedTask::fetch(&workspace_db, &mut tx);
+
+        while let Some(task) = tasks.next().await {
+            match task.map(TaskRecord::from).and_then(|record| {
+                serde_json::to_string(&record).map_err(|_| AppError::Internal)
+            }) {
+                Ok(line) => yield format!("{}\n", line),
+                Err(fail) => {
+                    error!("JSON Lines export stopped: {}", fail);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// `?columns.<field>=<column>` of the imports, the column (or key) each field is read from,
+/// defaults to the name of the field.
+#[derive(Debug, Default, FromForm)]
+pub(crate) struct Columns {
+    title: Option<String>,
+    details: Option<String>,
+    list: Option<String>,
+    tags: Option<String>,
+    due: Option<String>,
+    priority: Option<String>,
+    created_on: Option<String>,
+    done: Option<String>,
+    completed_at: Option<String>,
+}
+
+/// The cell of `column` as text, an empty one (or a JSON `null`) is missing.
+fn text(record: &serde_json::Map<String, Value>, column: &str) -> Result<Option<String>, String> {
+    match record.get(column) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(text)) if text.trim().is_empty() => Ok(None),
+        Some(Value::String(text)) => Ok(Some(text.clone())),
+        Some(Value::Number(number)) => Ok(Some(number.to_string())),
+        Some(Value::Bool(boolean)) => Ok(Some(boolean.to_string())),
+        Some(_) => Err(format!("`{}` must be text", column)),
+    }
+}
+
+impl Columns {
+    fn column<'c>(&'c self, column: &'c Option<String>, field: &'c str) -> &'c str {
+        column.as_deref().unwrap_or(field)
+    }
+
+    /// Reads the task of `record` (a CSV row keyed by its header, or a JSON line).
+    fn read(&self, record: &serde_json::Map<String, Value>) -> Result<ImportedTask, String> {
+        let text = |column: &Option<String>, field: &str| text(record, self.column(column, field));
+
+        let tags_column = self.column(&self.tags, "tags");
+        let tags = match record.get(tags_column) {
+            Some(Value::Array(tags)) => tags
+                .iter()
+                .map(|tag| match tag {
+                    Value::String(tag) => Ok(tag.trim().to_string()),
+                    _ => Err(format!("`{}` must be an array of text", tags_column)),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            _ => text(&self.tags, "tags")?
+                .map(|tags| {
+                    split_tags(&tags)
+                        .iter()
+                        .map(|tag| tag.trim().to_string())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        };
+
+        let due = text(&self.due, "due")?;
+        if let Some(due) = due.as_deref().filter(|due| !is_due(due)) {
+            return Err(format!("`{}` is not a date or date-time", due));
+        }
+
+        let priority = text(&self.priority, "priority")?;
+        let is_letter =
+            |letter: &str| letter.len() == 1 && letter.chars().all(|c| c.is_ascii_uppercase());
+        if let Some(priority) = priority.as_deref().filter(|priority| !is_letter(priority)) {
+            return Err(format!("`{}` is not a priority from `A` to `Z`", priority));
+        }
+
+        let created_on = text(&self.created_on, "created_on")?;
+        if let Some(created_on) = created_on
+            .as_deref()
+            .filter(|created_on| !is_date(created_on))
+        {
+            return Err(format!("`{}` is not a date", created_on));
+        }
+
+        // NOTE(alex): A date alone is completed at midnight, and the time must be in UTC.
+        let completed_at = match text(&self.completed_at, "completed_at")? {
+            Some(date) if is_date(&date) => Some(format!("{}T00:00:00Z", date)),
+            Some(completed_at) if is_due(&completed_at) && completed_at.ends_with('Z') => {
+                Some(completed_at)
+            }
+            Some(completed_at) => {
+                return Err(format!("`{}` is not a date or UTC date-time", completed_at))
+            }
+            None => None,
+        };
+
+        let done = match text(&self.done, "done")?.map(|done| done.to_ascii_lowercase()) {
+            Some(done) if ["true", "1", "yes", "x"].contains(&done.as_str()) => true,
+            Some(done) if ["false", "0", "no"].contains(&done.as_str()) => false,
+            Some(done) => return Err(format!("`{}` is not a boolean", done)),
+            None => completed_at.is_some(),
+        };
+
+        Ok(ImportedTask {
+            title: text(&self.title, "title")?.unwrap_or_default(),
+            details: text(&self.details, "details")?.unwrap_or_default(),
+            list: text(&self.list, "list")?,
+            tags: tags.into_iter().filter(|tag| !tag.is_empty()).collect(),
+            due,
+            priority,
+            created_on,
+            done,
+            completed_at,
+        })
+    }
+}
+
+/// Body of `POST /tasks/import.csv` and `POST /tasks/import.jsonl`, read a line at a time.
+pub(crate) struct Records<'r> {
+    reader: BufReader<DataStream<'r>>,
+    limit: ByteUnit,
+    read: u64,
+    line: usize,
+}
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for Records<'r> {
+    type Error = AppError;
+
+    async fn from_data(
+        req: &'r rocket::Request<'_>,
+        data: rocket::Data<'r>,
+    ) -> rocket::data::Outcome<'r, Self> {
+        let limit = req.limits().get("records").unwrap_or(64.mebibytes());
+
+        // NOTE(alex): One byte over the limit is read, a body of exactly `limit` bytes would be
+        // indistinguishable from a truncated one otherwise.
+        Outcome::Success(Records {
+            reader: BufReader::new(data.open(limit + 1.bytes())),
+            limit,
+            read: 0,
+            line: 0,
+        })
+    }
+}
+
+impl<'r> Records<'r> {
+    /// The next line, without its line ending, fails on invalid UTF-8 and past the limit.
+    async fn next_line(&mut self) -> Result<Option<String>, String> {
+        let mut line = String::new();
+        let read = self
+            .reader
+            .read_line(&mut line)
+            .await
+            .map_err(|fail| fail.to_string())?;
+
+        if read == 0 {
+            return Ok(None);
+        }
+
+        self.read += read as u64;
+        self.line += 1;
+        if self.read > self.limit.as_u64() {
+            return Err(format!("body is larger than `{}`", self.limit));
+        }
+
+        let trimmed = line.trim_end_matches('\n').trim_end_matches('\r').len();
+        line.truncate(trimmed);
+
+        Ok(Some(line))
+    }
+}
+
+/// The failed row of an import, `line` is where it starts (from 1).
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct RecordError {
+    line: usize,
+    error: String,
+}
+
+/// How an import went, every row is committed in one transaction, or none is.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub(crate) enum ImportRecordsResult {
+    Committed {
+        imported: usize,
+    },
+    /// The first failed rows (up to 100), the rest wasn't read.
+    RolledBack {
+        errors: Vec<RecordError>,
+    },
+}
+
+/// Reads and validates rows as they arrive, and inserts them once the whole body was read.
+///
+/// NOTE(alex): Nothing is written while the body is read, a slow client can't hold the write lock
+/// of the workspace, and `MAX_ROWS` bounds how long the transaction holds it.
+struct Importer<'a> {
+    columns: &'a Columns,
+    rows: usize,
+    items: Vec<(usize, ImportedTask)>,
+    errors: Vec<RecordError>,
+}
+
+impl<'a> Importer<'a> {
+    fn new(columns: &'a Columns) -> Self {
+        Importer {
+            columns,
+            rows: 0,
+            items: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    fn row(&mut self, line: usize, record: Result<serde_json::Map<String, Value>, String>) {
+        self.rows += 1;
+        if self.rows > MAX_ROWS {
+            self.errors.push(RecordError {
+                line,
+                error: format!("an import can't have more than `{}` rows", MAX_ROWS),
+            });
+            return;
+        }
+
+        let item = record
+            .and_then(|record| self.columns.read(&record))
+            .and_then(|item| {
+                item.validate()
+                    .map(|_| item)
+                    .map_err(|fail| fail.to_string())
+            });
+
+        // NOTE(alex): Once a row failed nothing will be inserted, the others are only validated.
+        match item {
+            Ok(item) if self.errors.is_empty() => self.items.push((line, item)),
+            Ok(_) => {}
+            Err(error) => self.errors.push(RecordError { line, error }),
+        }
+    }
+
+    /// Past `MAX_ERRORS` failed rows, or `MAX_ROWS` rows, the rest isn't read.
+    fn is_full(&self) -> bool {
+        self.errors.len() >= MAX_ERRORS || self.rows > MAX_ROWS
+    }
+
+    async fn finish(
+        self,
+        workspace_db: &WorkspaceDb<'_>,
+        task_events: &TaskEvents,
+    ) -> Result<ImportRecordsResult, AppError> {
+        if !self.errors.is_empty() {
+            return Ok(ImportRecordsResult::RolledBack {
+                errors: self.errors,
+            });
+        }
+
+        let mut tx = workspace_db.begin().await?;
+        let mut events = Vec::new();
+        for (line, item) in &self.items {
+            match item.apply(workspace_db, &mut tx).await {
+                Ok((_, applied)) => events.extend(applied),
+                Err(fail) => {
+                    tx.rollback().await?;
+                    return Ok(ImportRecordsResult::RolledBack {
+                        errors: vec![RecordError {
+                            line: *line,
+                            error: fail.to_string(),
+                        }],
+                    });
+                }
+            }
+        }
+        tx.commit().await?;
+
+        for (kind, task) in events {
+            task_events.publish(workspace_db.workspace.id, kind, task);
+        }
+
+        Ok(ImportRecordsResult::Committed {
+            imported: self.items.len(),
+        })
+    }
+}
+
+/// Every row of a CSV body, keyed by the columns of its header (the first row).
+///
+/// NOTE(alex): A quoted cell may span lines, so lines are joined until the quotes are balanced,
+/// before the `csv` crate reads the row.
+pub(crate) async fn import_csv(
+    workspace_db: &WorkspaceDb<'_>,
+    task_events: &TaskEvents,
+    columns: &Columns,
+    mut records: Records<'_>,
+) -> Result<ImportRecordsResult, AppError> {
+    let mut importer = Importer::new(columns);
+
+    let mut header: Option<Vec<String>> = None;
+    let mut row = String::new();
+    let mut row_line = 0;
+    let mut quotes = 0;
+
+    while !importer.is_full() {
+        let line = match records.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(error) => {
+                importer.errors.push(RecordError {
+                    line: records.line,
+                    error,
+                });
+                break;
+            }
+        };
+
+        if row.is_empty() {
+            row_line = records.line;
+        } else {
+            row.push('\n');
+        }
+        quotes += line.matches('"').count();
+        row.push_str(&line);
+
+        if quotes % 2 == 1 {
+            continue;
+        }
+        quotes = 0;
+        let text = std::mem::take(&mut row);
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        let cells = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(text.as_bytes())
+            .records()
+            .next()
+            .unwrap_or_else(|| Ok(csv::StringRecord::new()))
+            .map_err(|fail| fail.to_string());
+
+        match (&header, cells) {
+            (None, Ok(cells)) => {
+                header = Some(
+                    cells
+                        .iter()
+                        .map(|cell| cell.trim_start_matches('\u{feff}').trim().to_string())
+                        .collect(),
+                )
+            }
+            (Some(header), Ok(cells)) => {
+                let record = header
+                    .iter()
+                    .cloned()
+                    .zip(cells.iter().map(|cell| Value::String(cell.to_string())))
+                    .collect();
+                importer.row(row_line, Ok(record));
+            }
+            (_, Err(error)) => importer.row(row_line, Err(error)),
+        }
+    }
+
+    if !row.is_empty() && !importer.is_full() {
+        importer.errors.push(RecordError {
+            line: row_line,
+            error: "quoted cell is never closed".to_string(),
+        });
+    }
+
+    importer.finish(workspace_db, task_events).await
+}
+
+/// Every line of a JSON Lines body is an object, blank lines are skipped.
+pub(crate) async fn import_json_lines(
+    workspace_db: &WorkspaceDb<'_>,
+    task_events: &TaskEvents,
+    columns: &Columns,
+    mut records: Records<'_>,
+) -> Result<ImportRecordsResult, AppError> {
+    let mut importer = Importer::new(columns);
+
+    while !importer.is_full() {
+        let line = match records.next_line().await {
+            Ok(Some(line)) if line.trim().is_empty() => continue,
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(error) => {
+                importer.errors.push(RecordError {
+                    line: records.line,
+                    error,
+                });
+                break;
+            }
+        };
+
+        let record = match serde_json::from_str::<Value>(&line) {
+            Ok(Value::Object(record)) => Ok(record),
+            Ok(_) => Err("line is not a JSON object".to_string()),
+            Err(fail) => Err(fail.to_string()),
+        };
+        importer.row(records.line, record);
+    }
+
+    importer.finish(workspace_db, task_events).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_with_separators_round_trip() {
+        let tags: Vec<String> = ["plain", "a;b", "back\\slash", "both\\;", ""]
+            .iter()
+            .map(|tag| tag.to_string())
+            .collect();
+
+        let cell = join_tags(&tags);
+        assert_eq!(cell, "plain;a\\;b;back\\\\slash;both\\\\\\;;");
+        assert_eq!(split_tags(&cell), tags);
+    }
+
+    #[test]
+    fn unescaped_cells_split_as_before() {
+        assert_eq!(split_tags("home; work ;"), ["home", " work ", ""]);
+    }
+}
@@ -1,99 +1,1022 @@
+use fairings::{ETagged, IdempotencyKey, Idempotent, Preconditions, Tagged, TaskPatch};
 use rocket::{
-    delete, get,
-    http::Status,
-    post, put,
-    response::status::{Accepted, Created, Custom},
+    delete,
+    futures::Stream,
+    get,
+    http::{ContentType, Status},
+    patch, post, put,
+    response::{
+        status::{Accepted, Created, Custom},
+        stream::{Event, EventStream, TextStream},
+    },
     serde::json::Json,
-    State,
+    tokio::{select, sync::broadcast::error::RecvError},
+    Shutdown, State,
 };
 use sqlx::SqlitePool;
 
 use crate::{
+    atom::{self, AtomFeed},
+    batch::{Batch, BatchResult},
+    bulk::{Bulk, BulkConfig, BulkResult},
+    checklist::{self, Checklist, ChecklistFormat, ChecklistResult},
+    encryption::DetailsCipher,
     errors::AppError,
-    models::{InsertTask, Task, UpdateTask},
+    etag::{check_unchanged, expected_version},
+    events::{LastEventId, TaskEventKind, TaskEvents},
+    ics::{self, Calendar, ImportResult},
+    models::{
+        InsertTag, InsertTask, InsertTaskList, Tag, Task, TaskDocument, TaskList, UpdateTask,
+    },
+    negotiate::Negotiated,
+    patch,
+    records::{self, Columns, ImportRecordsResult, Records},
+    sources::{self, Export, Source, SourceResult},
+    sync::{ChangeResult, Delta, Push},
+    workspace::{BearerToken, CalendarFeed, Workspace, WorkspaceDb},
 };
 
+#[utoipa::path(
+    tag = "tasks",
+    summary = "Insert a new task",
+    request_body(content(
+        (InsertTask = "application/json"),
+        (InsertTask = "application/msgpack"),
+        (InsertTask = "application/cbor"),
+        (InsertTask = "text/csv"),
+        (InsertTask = "application/yaml"),
+    )),
+    responses(
+        (status = 201, description = "The inserted task", body = Task),
+        (status = 403, description = "The workspace reached its quota of tasks"),
+        (status = 415, description = "Not one of the supported formats"),
+        (status = 422, description = "Empty title, or unknown list"),
+    )
+)]
 #[post("/tasks", data = "<insert_task>")]
 pub(crate) async fn insert(
-    db_pool: &State<SqlitePool>,
-    insert_task: InsertTask,
+    workspace_db: WorkspaceDb<'_>,
+    task_events: &State<TaskEvents>,
+    insert_task: Idempotent<InsertTask>,
 ) -> Result<Created<Json<Task>>, AppError> {
-    let task = insert_task.insert(db_pool).await?;
+    let task = insert_task.insert(&workspace_db).await?;
+    task_events.publish(
+        workspace_db.workspace.id,
+        TaskEventKind::Created,
+        task.clone(),
+    );
 
     Ok(Created::new("/tasks").body(Json(task)))
 }
 
+/// NOTE(alex): A batch that wasn't committed (`all_or_nothing` mode) is a `422`, the body still
+/// says which operation failed.
+#[utoipa::path(
+    tag = "tasks",
+    summary = "Run many task operations in one transaction",
+    request_body = Batch,
+    responses(
+        (status = 200, description = "The batch was committed", body = BatchResult),
+        (status = 422, description = "The batch was rolled back, or has too many operations",
+            body = BatchResult),
+    )
+)]
+#[post("/tasks/batch", data = "<batch>")]
+pub(crate) async fn batch_tasks(
+    workspace_db: WorkspaceDb<'_>,
+    task_events: &State<TaskEvents>,
+    batch: Idempotent<Json<Batch>>,
+) -> Result<Custom<Json<BatchResult>>, AppError> {
+    let batch = batch.into_inner().into_inner();
+    batch.validate()?;
+
+    let result = batch.execute(&workspace_db, task_events).await?;
+    let status = if result.committed {
+        Status::Ok
+    } else {
+        Status::UnprocessableEntity
+    };
+
+    Ok(Custom(status, Json(result)))
+}
+
+/// NOTE(alex): Send `dry_run: true` first to see which tasks would be affected, actions over the
+/// `bulk_confirm_threshold` are a `428` until they're sent again with `confirm: true`.
+#[utoipa::path(
+    tag = "tasks",
+    summary = "Apply an action to every task matching a filter",
+    description = "Send `dry_run: true` first to see which tasks would be affected.",
+    request_body = Bulk,
+    responses(
+        (status = 200, description = "The affected tasks", body = BulkResult),
+        (status = 428,
+            description = "More tasks than `bulk_confirm_threshold`, send `confirm: true`"),
+    )
+)]
+#[post("/tasks/bulk", data = "<bulk>")]
+pub(crate) async fn bulk_tasks(
+    workspace_db: WorkspaceDb<'_>,
+    task_events: &State<TaskEvents>,
+    bulk_config: &State<BulkConfig>,
+    bulk: Idempotent<Json<Bulk>>,
+) -> Result<Json<BulkResult>, AppError> {
+    let result = bulk
+        .into_inner()
+        .into_inner()
+        .execute(&workspace_db, task_events, bulk_config)
+        .await?;
+
+    Ok(Json(result))
+}
+
+#[utoipa::path(
+    tag = "tasks",
+    summary = "Update a task",
+    request_body(content(
+        (UpdateTask = "application/json"),
+        (UpdateTask = "application/msgpack"),
+        (UpdateTask = "application/cbor"),
+        (UpdateTask = "text/csv"),
+        (UpdateTask = "application/yaml"),
+    )),
+    params(("If-Match" = Option<String>, Header, description = "`ETag` the task must still have")),
+    responses(
+        (status = 201, description = "Number of tasks updated", body = String,
+            headers(("ETag" = String))),
+        (status = 412, description = "The task changed since `If-Match`"),
+        (status = 415, description = "Not one of the supported formats"),
+        (status = 422, description = "Empty title"),
+    )
+)]
 #[put("/tasks", data = "<update_task>")]
 pub(crate) async fn update(
-    db_pool: &State<SqlitePool>,
+    workspace_db: WorkspaceDb<'_>,
+    task_events: &State<TaskEvents>,
+    preconditions: Preconditions,
     update_task: UpdateTask,
-) -> Result<Created<String>, AppError> {
-    let rows_affected = update_task.update(db_pool).await?;
+) -> Result<Tagged<Created<String>>, AppError> {
+    let current = Task::find_by_id(&workspace_db, update_task.id).await?;
+    let expected_version = expected_version(&preconditions, current.as_ref())?;
+
+    let rows_affected = update_task.update(&workspace_db, expected_version).await?;
+    let task = Task::find_by_id(&workspace_db, update_task.id).await?;
+
+    if rows_affected == 0 {
+        check_unchanged(expected_version, task.as_ref())?;
+    } else if let Some(task) = &task {
+        task_events.publish(
+            workspace_db.workspace.id,
+            TaskEventKind::Updated,
+            task.clone(),
+        );
+    }
 
-    Ok(Created::new("/tasks").body(rows_affected.to_string()))
+    Ok(Tagged::new(
+        task.as_ref().map(ETagged::etag),
+        Created::new("/tasks").body(rows_affected.to_string()),
+    ))
 }
 
+/// NOTE(alex): Unlike `PUT /tasks`, every writable field must be sent, a missing `list_id` takes
+/// the task out of its list.
+#[utoipa::path(
+    tag = "tasks",
+    summary = "Replace every field of a task",
+    description = "A missing `list_id` takes the task out of its list.",
+    request_body = TaskDocument,
+    params(("If-Match" = Option<String>, Header, description = "`ETag` the task must still have")),
+    responses(
+        (status = 200, description = "The replaced task", body = Task, headers(("ETag" = String))),
+        (status = 404, description = "Task not found"),
+        (status = 412, description = "The task changed since `If-Match`"),
+        (status = 422, description = "Empty title, or unknown list"),
+    )
+)]
+#[put("/tasks/<id>", data = "<document>")]
+pub(crate) async fn replace_task(
+    workspace_db: WorkspaceDb<'_>,
+    task_events: &State<TaskEvents>,
+    preconditions: Preconditions,
+    id: i64,
+    document: Json<TaskDocument>,
+) -> Result<Tagged<Json<Task>>, AppError> {
+    let current = Task::find_by_id(&workspace_db, id).await?;
+    let expected_version = expected_version(&preconditions, current.as_ref())?;
+
+    if current.is_none() {
+        return Err(AppError::IdNotFound(id));
+    }
+
+    document.validate(&workspace_db).await?;
+    let rows_affected = document
+        .replace(&workspace_db, id, expected_version)
+        .await?;
+
+    let task = Task::find_by_id(&workspace_db, id).await?;
+    if rows_affected == 0 {
+        check_unchanged(expected_version, task.as_ref())?;
+    }
+
+    let task = task.ok_or(AppError::IdNotFound(id))?;
+    task_events.publish(
+        workspace_db.workspace.id,
+        TaskEventKind::Updated,
+        task.clone(),
+    );
+
+    Ok(Tagged::new(Some(task.etag()), Json(task)))
+}
+
+#[utoipa::path(
+    tag = "tasks",
+    summary = "Patch a task",
+    request_body(
+        description = "A merge patch, or a JSON patch, of the task's `TaskDocument`",
+        content(
+            (Object = "application/merge-patch+json"),
+            (Vec<Object> = "application/json-patch+json"),
+        )
+    ),
+    params(("If-Match" = Option<String>, Header, description = "`ETag` the task must still have")),
+    responses(
+        (status = 200, description = "The patched task", body = Task, headers(("ETag" = String))),
+        (status = 404, description = "Task not found"),
+        (status = 412, description = "The task changed since `If-Match`"),
+        (status = 415, description = "Not a merge patch, or a JSON patch"),
+        (status = 422, description = "The patched task isn't valid"),
+    )
+)]
+#[patch("/tasks/<id>", data = "<task_patch>")]
+pub(crate) async fn patch_task(
+    workspace_db: WorkspaceDb<'_>,
+    task_events: &State<TaskEvents>,
+    preconditions: Preconditions,
+    id: i64,
+    task_patch: Idempotent<TaskPatch>,
+) -> Result<Tagged<Json<Task>>, AppError> {
+    let task = patch::apply_to(&task_patch, &workspace_db, &preconditions, id).await?;
+
+    task_events.publish(
+        workspace_db.workspace.id,
+        TaskEventKind::Updated,
+        task.clone(),
+    );
+
+    Ok(Tagged::new(Some(task.etag()), Json(task)))
+}
+
+#[utoipa::path(
+    tag = "tasks",
+    summary = "Delete a task",
+    params(("If-Match" = Option<String>, Header, description = "`ETag` the task must still have")),
+    responses(
+        (status = 202, description = "Number of tasks deleted", body = String),
+        (status = 412, description = "The task changed since `If-Match`"),
+    )
+)]
 #[delete("/tasks/<id>")]
 pub(crate) async fn delete(
-    db_pool: &State<SqlitePool>,
+    workspace_db: WorkspaceDb<'_>,
+    task_events: &State<TaskEvents>,
+    preconditions: Preconditions,
     id: i64,
 ) -> Result<Accepted<String>, AppError> {
-    let rows_affected = Task::delete(db_pool, id).await?;
+    let task = Task::find_by_id(&workspace_db, id).await?;
+    let expected_version = expected_version(&preconditions, task.as_ref())?;
+
+    let rows_affected = Task::delete(&workspace_db, id, expected_version).await?;
+
+    if rows_affected == 0 {
+        let current = Task::find_by_id(&workspace_db, id).await?;
+        check_unchanged(expected_version, current.as_ref())?;
+    } else if let Some(task) = task {
+        task_events.publish(workspace_db.workspace.id, TaskEventKind::Deleted, task);
+    }
+
     Ok(Accepted(Some(rows_affected.to_string())))
 }
 
+#[utoipa::path(
+    tag = "tasks",
+    summary = "Mark a task as done",
+    params(("If-Match" = Option<String>, Header, description = "`ETag` the task must still have")),
+    responses(
+        (status = 201, description = "Id of the done entry", body = String,
+            headers(("ETag" = String))),
+        (status = 304, description = "The task was already done"),
+        (status = 412, description = "The task changed since `If-Match`"),
+    )
+)]
 #[post("/tasks/<id>/done")]
-pub(crate) async fn done(db_pool: &State<SqlitePool>, id: i64) -> Result<Custom<String>, AppError> {
-    let created_id = Task::done(db_pool, id).await?;
+pub(crate) async fn done(
+    workspace_db: WorkspaceDb<'_>,
+    task_events: &State<TaskEvents>,
+    preconditions: Preconditions,
+    id: i64,
+    _idempotency_key: IdempotencyKey,
+) -> Result<Tagged<Custom<String>>, AppError> {
+    let current = Task::find_by_id(&workspace_db, id).await?;
+    let expected_version = expected_version(&preconditions, current.as_ref())?;
+
+    let created_id = Task::done(&workspace_db, id, expected_version).await?;
+    let task = Task::find_by_id(&workspace_db, id).await?;
 
     if created_id == 0 {
-        Ok(Custom(Status::NotModified, "".to_string()))
+        check_unchanged(expected_version, task.as_ref())?;
+
+        Ok(Tagged::new(
+            task.as_ref().map(ETagged::etag),
+            Custom(Status::NotModified, "".to_string()),
+        ))
     } else {
-        Ok(Custom(Status::Created, created_id.to_string()))
+        if let Some(task) = &task {
+            task_events.publish(workspace_db.workspace.id, TaskEventKind::Done, task.clone());
+        }
+
+        Ok(Tagged::new(
+            task.as_ref().map(ETagged::etag),
+            Custom(Status::Created, created_id.to_string()),
+        ))
     }
 }
 
+#[utoipa::path(
+    tag = "tasks",
+    summary = "Mark a task as not done",
+    params(("If-Match" = Option<String>, Header, description = "`ETag` the task must still have")),
+    responses(
+        (status = 201, description = "Number of tasks undone", body = String,
+            headers(("ETag" = String))),
+        (status = 304, description = "The task wasn't done"),
+        (status = 412, description = "The task changed since `If-Match`"),
+    )
+)]
 #[post("/tasks/<id>/undo")]
-pub(crate) async fn undo(db_pool: &State<SqlitePool>, id: i64) -> Result<Custom<String>, AppError> {
-    let num_modified = Task::undo(db_pool, id).await?;
+pub(crate) async fn undo(
+    workspace_db: WorkspaceDb<'_>,
+    task_events: &State<TaskEvents>,
+    preconditions: Preconditions,
+    id: i64,
+    _idempotency_key: IdempotencyKey,
+) -> Result<Tagged<Custom<String>>, AppError> {
+    let current = Task::find_by_id(&workspace_db, id).await?;
+    let expected_version = expected_version(&preconditions, current.as_ref())?;
+
+    let num_modified = Task::undo(&workspace_db, id, expected_version).await?;
+    let task = Task::find_by_id(&workspace_db, id).await?;
 
     if num_modified == 0 {
-        Ok(Custom(Status::NotModified, "".to_string()))
+        check_unchanged(expected_version, task.as_ref())?;
+
+        Ok(Tagged::new(
+            task.as_ref().map(ETagged::etag),
+            Custom(Status::NotModified, "".to_string()),
+        ))
     } else {
-        Ok(Custom(Status::Created, num_modified.to_string()))
+        if let Some(task) = &task {
+            task_events.publish(
+                workspace_db.workspace.id,
+                TaskEventKind::Undone,
+                task.clone(),
+            );
+        }
+
+        Ok(Tagged::new(
+            task.as_ref().map(ETagged::etag),
+            Custom(Status::Created, num_modified.to_string()),
+        ))
     }
 }
 
+#[utoipa::path(
+    tag = "tasks",
+    summary = "Get all tasks, or the ones with a title like `pattern`",
+    params(
+        ("pattern" = Option<String>, Query, description = "SQL `like` pattern, e.g. `%milk%`"),
+        ("format" = Option<String>, Query,
+            description = "`json`, `msgpack`, `cbor`, `csv` or `yaml`, overrides `Accept`"),
+    ),
+    responses(
+        (status = 200, description = "The tasks", content(
+            (Vec<Task> = "application/json"),
+            (Vec<Task> = "application/msgpack"),
+            (Vec<Task> = "application/cbor"),
+            (Vec<Task> = "text/csv"),
+            (Vec<Task> = "application/yaml"),
+        )),
+        (status = 406, description = "None of the `Accept`ed formats is supported"),
+    )
+)]
 #[get("/tasks")]
-pub(crate) async fn find_all(db_pool: &State<SqlitePool>) -> Result<Json<Vec<Task>>, AppError> {
-    let tasks = Task::find_all(db_pool).await?;
+pub(crate) async fn find_all(workspace_db: WorkspaceDb<'_>) -> Result<Negotiated<Task>, AppError> {
+    let tasks = Task::find_all(&workspace_db).await?;
 
-    Ok(Json(tasks))
+    Ok(Negotiated(tasks))
 }
 
+#[utoipa::path(
+    tag = "tasks",
+    summary = "Get the tasks that are not done",
+    params(
+        ("format" = Option<String>, Query,
+            description = "`json`, `msgpack`, `cbor`, `csv` or `yaml`, overrides `Accept`"),
+    ),
+    responses(
+        (status = 200, description = "The ongoing tasks", content(
+            (Vec<Task> = "application/json"),
+            (Vec<Task> = "application/msgpack"),
+            (Vec<Task> = "application/cbor"),
+            (Vec<Task> = "text/csv"),
+            (Vec<Task> = "application/yaml"),
+        )),
+        (status = 406, description = "None of the `Accept`ed formats is supported"),
+    )
+)]
 #[get("/tasks/ongoing")]
-pub(crate) async fn find_ongoing(db_pool: &State<SqlitePool>) -> Result<Json<Vec<Task>>, AppError> {
-    let tasks = Task::find_ongoing(db_pool).await?;
+pub(crate) async fn find_ongoing(
+    workspace_db: WorkspaceDb<'_>,
+) -> Result<Negotiated<Task>, AppError> {
+    let tasks = Task::find_ongoing(&workspace_db).await?;
 
-    Ok(Json(tasks))
+    Ok(Negotiated(tasks))
 }
 
 #[get("/tasks?<pattern>")]
 pub(crate) async fn find_by_pattern(
-    db_pool: &State<SqlitePool>,
+    workspace_db: WorkspaceDb<'_>,
     pattern: &str,
-) -> Result<Json<Vec<Task>>, AppError> {
-    let tasks = Task::find_by_pattern(db_pool, pattern).await?;
+) -> Result<Negotiated<Task>, AppError> {
+    let tasks = Task::find_by_pattern(&workspace_db, pattern).await?;
 
-    Ok(Json(tasks))
+    Ok(Negotiated(tasks))
 }
 
+/// NOTE(alex): Reconnecting clients send `Last-Event-ID`, and get whatever they missed that is
+/// still in the replay buffer before the live events.
+#[utoipa::path(
+    tag = "tasks",
+    summary = "Stream of task changes",
+    description = "Server-sent events named after the change (`created`, `updated`, `deleted`, \
+        `done`, `undone`), with the task as data.",
+    params(("Last-Event-ID" = Option<String>, Header, description = "Replays the events after it")),
+    responses(
+        (status = 200, description = "The events", body = Task, content_type = "text/event-stream"),
+    )
+)]
+#[get("/tasks/events")]
+pub(crate) async fn task_events(
+    workspace_db: WorkspaceDb<'_>,
+    task_events: &State<TaskEvents>,
+    last_event_id: LastEventId,
+    mut shutdown: Shutdown,
+) -> EventStream![] {
+    let workspace_id = workspace_db.workspace.id;
+    let (missed, mut last_sent, mut receiver) =
+        task_events.subscribe(workspace_id, last_event_id.0);
+
+    EventStream! {
+        for event in missed {
+            yield Event::json(&event.task).event(event.kind.as_str()).id(event.id.to_string());
+        }
+
+        loop {
+            let event = select! {
+                event = receiver.recv() => match event {
+                    Ok(event) => event,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => continue,
+                },
+                _ = &mut shutdown => break,
+            };
+
+            if event.workspace_id != workspace_id || Some(event.id) <= last_sent {
+                continue;
+            }
+
+            last_sent = Some(event.id);
+            yield Event::json(&event.task).event(event.kind.as_str()).id(event.id.to_string());
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "tasks",
+    summary = "Get a task",
+    params(
+        ("If-None-Match" = Option<String>, Header, description = "`ETag` the client already has"),
+    ),
+    responses(
+        (status = 200, description = "The task (`null` when not found)", body = Option<Task>,
+            headers(("ETag" = String))),
+        (status = 304, description = "The task still matches `If-None-Match`"),
+    )
+)]
 #[get("/tasks/<id>")]
 pub(crate) async fn find_by_id(
+    workspace_db: WorkspaceDb<'_>,
+    preconditions: Preconditions,
+    id: i64,
+) -> Result<Tagged<Json<Option<Task>>>, AppError> {
+    let task = Task::find_by_id(&workspace_db, id).await?;
+
+    match task {
+        Some(task) if preconditions.is_not_modified(&task) => Ok(Tagged::not_modified(&task)),
+        task => Ok(Tagged::new(task.as_ref().map(ETagged::etag), Json(task))),
+    }
+}
+
+#[utoipa::path(
+    tag = "workspace",
+    summary = "Get the current workspace",
+    responses((status = 200, description = "The workspace", body = Workspace))
+)]
+#[get("/workspace")]
+pub(crate) async fn current_workspace(workspace_db: WorkspaceDb<'_>) -> Json<Workspace> {
+    Json(workspace_db.workspace)
+}
+
+#[utoipa::path(
+    tag = "calendar",
+    summary = "Get the calendar feed of the workspace",
+    description = "Only with the API token in the `Authorization` header.",
+    responses(
+        (status = 200, description = "Where calendar apps subscribe", body = CalendarFeed),
+        (status = 401, description = "No `Authorization` header, or an unknown token"),
+    )
+)]
+#[get("/workspace/feed")]
+pub(crate) async fn calendar_feed(
+    _bearer: BearerToken,
+    workspace_db: WorkspaceDb<'_>,
+) -> Result<Json<CalendarFeed>, AppError> {
+    let feed = workspace_db.calendar_feed().await?;
+
+    Ok(Json(feed))
+}
+
+#[utoipa::path(
+    tag = "calendar",
+    summary = "Rotate the feed token",
+    description = "The previous feed URL stops working. Only with the API token in the \
+        `Authorization` header.",
+    responses(
+        (status = 200, description = "The new feed", body = CalendarFeed),
+        (status = 401, description = "No `Authorization` header, or an unknown token"),
+    )
+)]
+#[post("/workspace/feed")]
+pub(crate) async fn rotate_calendar_feed(
+    _bearer: BearerToken,
+    workspace_db: WorkspaceDb<'_>,
+    _idempotency_key: IdempotencyKey,
+) -> Result<Json<CalendarFeed>, AppError> {
+    let feed = workspace_db.rotate_feed_token().await?;
+
+    Ok(Json(feed))
+}
+
+/// NOTE(alex): Calendar apps can't send headers, so the feed token in the query is all it takes,
+/// the API token would give away far more than the tasks.
+#[utoipa::path(
+    tag = "calendar",
+    summary = "Get every task as iCalendar",
+    description = "Ongoing and done tasks as `VTODO`s, with their tags as `CATEGORIES`.",
+    params(("token" = String, Query, description = "Feed token, see `GET /workspace/feed`")),
+    security(()),
+    responses(
+        (status = 200, description = "The calendar", body = String, content_type = "text/calendar"),
+        (status = 401, description = "Missing, unknown or rotated feed token"),
+    )
+)]
+#[get("/tasks.ics?<token>")]
+pub(crate) async fn tasks_calendar(
     db_pool: &State<SqlitePool>,
+    cipher: Option<&DetailsCipher>,
+    token: Option<&str>,
+) -> Result<(ContentType, String), AppError> {
+    let token = token.ok_or(AppError::InvalidToken)?;
+    let workspace = Workspace::find_by_feed_token(db_pool, token)
+        .await?
+        .ok_or(AppError::InvalidToken)?;
+
+    let workspace_db = WorkspaceDb::new(workspace, db_pool, cipher);
+
+    Ok((ContentType::Calendar, ics::render(&workspace_db).await?))
+}
+
+/// NOTE(alex): Authenticated by the feed token like `GET /tasks.ics`, feed readers can't send
+/// headers either.
+#[utoipa::path(
+    tag = "calendar",
+    summary = "Get an Atom feed of the latest task changes",
+    description = "The latest 50 creations, edits, completions and undos, newest first, each with \
+        a stable `id` and the time of the change as `updated`. Supports `If-None-Match` and \
+        `If-Modified-Since`.",
+    params(
+        ("token" = String, Query, description = "Feed token, see `GET /workspace/feed`"),
+        ("list" = Option<i64>, Query, description = "Only the tasks of this list"),
+        ("tag" = Option<i64>, Query, description = "Only the tasks with this tag"),
+        ("If-None-Match" = Option<String>, Header, description = "`ETag` the client already has"),
+        ("If-Modified-Since" = Option<String>, Header, description = "`Last-Modified` the client \
+            already has"),
+    ),
+    security(()),
+    responses(
+        (status = 200, description = "The feed", body = String,
+            content_type = "application/atom+xml"),
+        (status = 304, description = "The feed didn't change"),
+        (status = 401, description = "Missing, unknown or rotated feed token"),
+    )
+)]
+#[get("/tasks/feed.atom?<token>&<list>&<tag>")]
+pub(crate) async fn tasks_feed(
+    db_pool: &State<SqlitePool>,
+    cipher: Option<&DetailsCipher>,
+    preconditions: Preconditions,
+    token: Option<&str>,
+    list: Option<i64>,
+    tag: Option<i64>,
+) -> Result<AtomFeed, AppError> {
+    let token = token.ok_or(AppError::InvalidToken)?;
+    let workspace = Workspace::find_by_feed_token(db_pool, token)
+        .await?
+        .ok_or(AppError::InvalidToken)?;
+
+    let workspace_db = WorkspaceDb::new(workspace, db_pool, cipher);
+
+    atom::render(&workspace_db, list, tag, &preconditions).await
+}
+
+#[utoipa::path(
+    tag = "calendar",
+    summary = "Import the to-dos of a calendar",
+    description = "Every `VTODO` becomes a task (`SUMMARY`, `DESCRIPTION`, `DUE`), done when \
+        `COMPLETED`, tagged with its `CATEGORIES` (missing tags are created). Each one is \
+        imported on its own, a failure doesn't stop the others.",
+    request_body(content = String, content_type = "text/calendar"),
+    responses(
+        (status = 200, description = "A result per `VTODO`", body = [ImportResult]),
+        (status = 415, description = "Not `text/calendar`"),
+        (status = 422, description = "Not an iCalendar, or too many to-dos"),
+    )
+)]
+#[post("/import/ics", data = "<calendar>")]
+pub(crate) async fn import_calendar(
+    workspace_db: WorkspaceDb<'_>,
+    task_events: &State<TaskEvents>,
+    calendar: Idempotent<Calendar>,
+) -> Result<Json<Vec<ImportResult>>, AppError> {
+    let results = ics::import(&workspace_db, task_events, calendar.into_inner()).await?;
+
+    Ok(Json(results))
+}
+
+#[utoipa::path(
+    tag = "checklists",
+    summary = "Get every task as a todo.txt or Markdown checklist",
+    description = "Ongoing and done tasks, with their priority, creation and completion dates, \
+        list as `+project`, tags as `@context`s, and `due:` date. Only Markdown keeps the \
+        details, indented below each item.",
+    params(("format" = Option<String>, Query,
+        description = "`todotxt` (the default) or `markdown`")),
+    responses(
+        (status = 200, description = "The checklist", body = String, content_type = "text/plain"),
+        (status = 415, description = "Unknown `format`"),
+    )
+)]
+#[get("/tasks/export?<format>")]
+pub(crate) async fn export_tasks(
+    workspace_db: WorkspaceDb<'_>,
+    format: Option<&str>,
+) -> Result<(ContentType, String), AppError> {
+    let format = ChecklistFormat::from_name(format.unwrap_or("todotxt"))
+        .ok_or(AppError::UnsupportedChecklist)?;
+
+    Ok((
+        format.content_type(),
+        checklist::render(&workspace_db, format).await?,
+    ))
+}
+
+#[utoipa::path(
+    tag = "checklists",
+    summary = "Import a todo.txt or Markdown checklist",
+    description = "Every item becomes a task, with the `+project` as its list and the \
+        `@context`s as tags (both created when missing), done when checked (`x`). Each one is \
+        imported on its own, a failure doesn't stop the others. A `dry_run` imports nothing, and \
+        previews what each item would become.",
+    params(
+        ("format" = Option<String>, Query, description = "`todotxt` or `markdown`, instead of the \
+            `Content-Type`"),
+        ("dry_run" = Option<bool>, Query, description = "Only preview the import"),
+    ),
+    request_body(content(
+        (String = "text/plain"),
+        (String = "text/markdown"),
+    )),
+    responses(
+        (status = 200, description = "A result per item", body = [ChecklistResult]),
+        (status = 415, description = "Neither todo.txt nor Markdown"),
+        (status = 422, description = "Too many items"),
+    )
+)]
+#[post("/tasks/import?<dry_run>", data = "<checklist>")]
+pub(crate) async fn import_tasks(
+    workspace_db: WorkspaceDb<'_>,
+    task_events: &State<TaskEvents>,
+    dry_run: Option<bool>,
+    checklist: Idempotent<Checklist>,
+) -> Result<Json<Vec<ChecklistResult>>, AppError> {
+    let dry_run = dry_run.unwrap_or(false);
+    let results =
+        checklist::import(&workspace_db, task_events, checklist.into_inner(), dry_run).await?;
+
+    Ok(Json(results))
+}
+
+/// NOTE(alex): Streamed straight from the database cursor, so even a huge workspace is never
+/// held in memory.
+#[utoipa::path(
+    tag = "records",
+    summary = "Get every task as CSV",
+    description = "A header, then a row per task (`id`, `title`, `details`, `list`, `tags` \
+        separated by `;`, `due`, `priority`, `created_on`, `done`, `completed_at`, `version`).",
+    responses((status = 200, description = "The rows", body = String, content_type = "text/csv"))
+)]
+#[get("/tasks/export.csv")]
+pub(crate) async fn export_csv<'r>(
+    workspace_db: WorkspaceDb<'r>,
+) -> (ContentType, TextStream<impl Stream<Item = String> + 'r>) {
+    (ContentType::CSV, records::csv_stream(&workspace_db))
+}
+
+#[utoipa::path(
+    tag = "records",
+    summary = "Get every task as JSON Lines",
+    description = "An object per line, with the columns of `GET /tasks/export.csv` (`tags` is an \
+        array).",
+    responses((
+        status = 200,
+        description = "The lines",
+        body = String,
+        content_type = "application/jsonl"
+    ))
+)]
+#[get("/tasks/export.jsonl")]
+pub(crate) async fn export_json_lines<'r>(
+    workspace_db: WorkspaceDb<'r>,
+) -> (ContentType, TextStream<impl Stream<Item = String> + 'r>) {
+    (
+        ContentType::new("application", "jsonl"),
+        records::json_lines_stream(&workspace_db),
+    )
+}
+
+#[utoipa::path(
+    tag = "records",
+    summary = "Import the rows of a CSV",
+    description = "The first row names the columns, `?columns.{field}={column}` maps a field \
+        (`title`, `details`, `list`, `tags`, `due`, `priority`, `created_on`, `done`, \
+        `completed_at`) to another column, other columns are ignored. `tags` are separated by `;` \
+        (a `\\` escapes a `;` or `\\` in a tag). Rows are validated as the body arrives, then \
+        inserted and committed together (up to 10000 rows), a single failed row rolls the whole \
+        import back.",
+    params(
+        ("columns.title" = Option<String>, Query, description = "Column of the title, and so on \
+            for every field"),
+    ),
+    request_body(content = String, content_type = "text/csv"),
+    responses(
+        (status = 200, description = "Every row was imported", body = ImportRecordsResult),
+        (status = 422, description = "Nothing was imported, the failed rows",
+            body = ImportRecordsResult),
+    )
+)]
+#[post("/tasks/import.csv?<columns>", data = "<records>")]
+pub(crate) async fn import_csv(
+    workspace_db: WorkspaceDb<'_>,
+    task_events: &State<TaskEvents>,
+    columns: Columns,
+    records: Records<'_>,
+) -> Result<Custom<Json<ImportRecordsResult>>, AppError> {
+    let result = records::import_csv(&workspace_db, task_events, &columns, records).await?;
+
+    Ok(records_status(result))
+}
+
+#[utoipa::path(
+    tag = "records",
+    summary = "Import JSON Lines",
+    description = "An object per line, read like the rows of `POST /tasks/import.csv` (`tags` may \
+        be an array, `done` a boolean).",
+    params(
+        ("columns.title" = Option<String>, Query, description = "Key of the title, and so on for \
+            every field"),
+    ),
+    request_body(content = String, content_type = "application/jsonl"),
+    responses(
+        (status = 200, description = "Every line was imported", body = ImportRecordsResult),
+        (status = 422, description = "Nothing was imported, the failed lines",
+            body = ImportRecordsResult),
+    )
+)]
+#[post("/tasks/import.jsonl?<columns>", data = "<records>")]
+pub(crate) async fn import_json_lines(
+    workspace_db: WorkspaceDb<'_>,
+    task_events: &State<TaskEvents>,
+    columns: Columns,
+    records: Records<'_>,
+) -> Result<Custom<Json<ImportRecordsResult>>, AppError> {
+    let result = records::import_json_lines(&workspace_db, task_events, &columns, records).await?;
+
+    Ok(records_status(result))
+}
+
+fn records_status(result: ImportRecordsResult) -> Custom<Json<ImportRecordsResult>> {
+    match result {
+        ImportRecordsResult::Committed { .. } => Custom(Status::Ok, Json(result)),
+        ImportRecordsResult::RolledBack { .. } => Custom(Status::UnprocessableEntity, Json(result)),
+    }
+}
+
+#[utoipa::path(
+    tag = "sources",
+    summary = "Import the export of another task manager",
+    description = "`taskwarrior` reads the array of `task export` (`project` as the list, \
+        `annotations` below the details, `H`/`M`/`L` as the priorities `A`/`B`/`C`), `trello` the \
+        JSON of a board (its list as the list, labels as tags, comments below the description). \
+        Each item is imported on its own, a failure doesn't stop the others. Importing the same \
+        source again matches the items on their ids (Taskwarrior `uuid`, Trello card `id`), and \
+        only updates what changed.",
+    params(("source" = String, Path, description = "`taskwarrior` or `trello`")),
+    request_body(content = String, content_type = "application/json"),
+    responses(
+        (status = 200, description = "A result per item", body = [SourceResult]),
+        (status = 404, description = "Unknown `source`"),
+        (status = 415, description = "Not JSON"),
+        (status = 422, description = "Not an export of `source`, or too many items"),
+    )
+)]
+#[post("/import/<source>", data = "<export>")]
+pub(crate) async fn import_source(
+    workspace_db: WorkspaceDb<'_>,
+    task_events: &State<TaskEvents>,
+    source: &str,
+    export: Idempotent<Export>,
+) -> Result<Option<Json<Vec<SourceResult>>>, AppError> {
+    let source = match Source::from_name(source) {
+        Some(source) => source,
+        None => return Ok(None),
+    };
+
+    let results = sources::import(
+        &workspace_db,
+        task_events,
+        source,
+        &export.into_inner().0,
+        Some(sources::MAX_ITEMS),
+    )
+    .await?;
+
+    Ok(Some(Json(results)))
+}
+
+#[utoipa::path(
+    tag = "lists",
+    summary = "Insert a new list",
+    request_body = InsertTaskList,
+    responses((status = 201, description = "The inserted list", body = TaskList))
+)]
+#[post("/lists", data = "<insert_list>")]
+pub(crate) async fn insert_list(
+    workspace_db: WorkspaceDb<'_>,
+    insert_list: Idempotent<Json<InsertTaskList>>,
+) -> Result<Created<Json<TaskList>>, AppError> {
+    let list = insert_list.insert(&workspace_db).await?;
+
+    Ok(Created::new("/lists").body(Json(list)))
+}
+
+#[utoipa::path(
+    tag = "lists",
+    summary = "Get all lists",
+    responses((status = 200, description = "The lists", body = Vec<TaskList>))
+)]
+#[get("/lists")]
+pub(crate) async fn find_all_lists(
+    workspace_db: WorkspaceDb<'_>,
+) -> Result<Json<Vec<TaskList>>, AppError> {
+    let lists = TaskList::find_all(&workspace_db).await?;
+
+    Ok(Json(lists))
+}
+
+#[utoipa::path(
+    tag = "tags",
+    summary = "Insert a new tag",
+    request_body = InsertTag,
+    responses((status = 201, description = "The inserted tag", body = Tag))
+)]
+#[post("/tags", data = "<insert_tag>")]
+pub(crate) async fn insert_tag(
+    workspace_db: WorkspaceDb<'_>,
+    insert_tag: Idempotent<Json<InsertTag>>,
+) -> Result<Created<Json<Tag>>, AppError> {
+    let tag = insert_tag.insert(&workspace_db).await?;
+
+    Ok(Created::new("/tags").body(Json(tag)))
+}
+
+#[utoipa::path(
+    tag = "tags",
+    summary = "Get all tags",
+    responses((status = 200, description = "The tags", body = Vec<Tag>))
+)]
+#[get("/tags")]
+pub(crate) async fn find_all_tags(
+    workspace_db: WorkspaceDb<'_>,
+) -> Result<Json<Vec<Tag>>, AppError> {
+    let tags = Tag::find_all(&workspace_db).await?;
+
+    Ok(Json(tags))
+}
+
+#[utoipa::path(
+    tag = "tags",
+    summary = "Tag a task",
+    responses(
+        (status = 201, description = "Number of tags added", body = String),
+        (status = 304, description = "The task already had the tag"),
+    )
+)]
+#[put("/tasks/<id>/tags/<tag_id>")]
+pub(crate) async fn tag(
+    workspace_db: WorkspaceDb<'_>,
+    id: i64,
+    tag_id: i64,
+) -> Result<Custom<String>, AppError> {
+    let num_modified = Task::tag(&workspace_db, id, tag_id).await?;
+
+    if num_modified == 0 {
+        Ok(Custom(Status::NotModified, "".to_string()))
+    } else {
+        Ok(Custom(Status::Created, num_modified.to_string()))
+    }
+}
+
+#[utoipa::path(
+    tag = "tags",
+    summary = "Remove a tag from a task",
+    responses((status = 202, description = "Number of tags removed", body = String))
+)]
+#[delete("/tasks/<id>/tags/<tag_id>")]
+pub(crate) async fn untag(
+    workspace_db: WorkspaceDb<'_>,
     id: i64,
-) -> Result<Json<Option<Task>>, AppError> {
-    let task = Task::find_by_id(db_pool, id).await?;
+    tag_id: i64,
+) -> Result<Accepted<String>, AppError> {
+    let rows_affected = Task::untag(&workspace_db, id, tag_id).await?;
+
+    Ok(Accepted(Some(rows_affected.to_string())))
+}
+
+/// NOTE(alex): `since` is the `token` of the previous sync, leave it out (or `0`) for a full sync.
+#[utoipa::path(
+    tag = "sync",
+    summary = "Tasks changed and deleted after a sync token",
+    description = "`since` is the `token` of the previous sync, leave it out for a full sync.",
+    responses((status = 200, description = "The changes, and the next token", body = Delta))
+)]
+#[get("/sync?<since>")]
+pub(crate) async fn pull(
+    workspace_db: WorkspaceDb<'_>,
+    since: Option<i64>,
+) -> Result<Json<Delta>, AppError> {
+    let delta = Delta::since(&workspace_db, since.unwrap_or_default()).await?;
+
+    Ok(Json(delta))
+}
+
+/// NOTE(alex): Doesn't hand out a new `token`, changes made by other clients in the meantime
+/// would be skipped, pull again after pushing.
+#[utoipa::path(
+    tag = "sync",
+    summary = "Apply a batch of offline changes",
+    description = "Doesn't hand out a new `token`, pull again after pushing.",
+    request_body = Push,
+    responses((status = 200, description = "The result of each change", body = Vec<ChangeResult>))
+)]
+#[post("/sync", data = "<push>")]
+pub(crate) async fn push(
+    workspace_db: WorkspaceDb<'_>,
+    task_events: &State<TaskEvents>,
+    push: Idempotent<Json<Push>>,
+) -> Json<Vec<ChangeResult>> {
+    let mut results = Vec::with_capacity(push.changes.len());
+
+    for change in push.into_inner().into_inner().changes {
+        results.push(change.apply(&workspace_db, task_events).await);
+    }
 
-    Ok(Json(task))
+    Json(results)
 }
@@ -0,0 +1,183 @@
+use sqlx::SqlitePool;
+
+const CREATE_DATABASE: &'static str = include_str!("./../queries/create_database.sql");
+const FIND_TASK_COLUMNS: &'static str = include_str!("./../queries/find_task_columns.sql");
+const MIGRATE_BASELINE: &'static str = include_str!("./../queries/migrate_baseline.sql");
+const COPY_BASELINE: &'static str = include_str!("./../queries/copy_baseline.sql");
+
+/// The version of `create_database.sql`, kept in `pragma user_version`. Bump it (and add a step
+/// to [`create_database`]) whenever an existing database can't pick a change up by itself, the
+/// `if not exists` tables are never altered.
+const SCHEMA_VERSION: i64 = 1;
+
+const BASELINE_COLUMNS: &[&str] = &["id", "title", "details"];
+const TASK_COLUMNS: &[&str] = &[
+    "id",
+    "workspace_id",
+    "list_id",
+    "title",
+    "details",
+    "due",
+    "priority",
+    "created_on",
+    "seq",
+    "version",
+];
+
+/// What an unversioned database (`user_version` 0) holds.
+enum Unversioned {
+    Empty,
+    /// The tasks of the first version, without workspaces, copied into the `default` one.
+    Baseline,
+    /// Created by this schema before it had a version.
+    Current,
+}
+
+/// Creates the schema, or brings an older one up to [`SCHEMA_VERSION`], all or nothing. An error
+/// means the server must not start on this database.
+///
+/// NOTE(alex): This should be part of some setup script, it's here for convenience.
+pub(crate) async fn create_database(db_pool: &SqlitePool) -> Result<(), String> {
+    let mut tx = db_pool.begin().await.map_err(database_error)?;
+
+    let (version,): (i64,) = sqlx::query_as("pragma user_version")
+        .fetch_one(&mut tx)
+        .await
+        .map_err(database_error)?;
+
+    let unversioned = match version {
+        SCHEMA_VERSION => None,
+        0 => {
+            let columns: Vec<(String,)> = sqlx::query_as(FIND_TASK_COLUMNS)
+                .fetch_all(&mut tx)
+                .await
+                .map_err(database_error)?;
+            let columns: Vec<&str> = columns.iter().map(|(name,)| name.as_str()).collect();
+
+            Some(unversioned(&columns)?)
+        }
+        newer => {
+            return Err(format!(
+                "The database has schema version `{}`, newer than this build's `{}`!",
+                newer, SCHEMA_VERSION
+            ))
+        }
+    };
+
+    if let Some(Unversioned::Baseline) = unversioned {
+        sqlx::query(MIGRATE_BASELINE)
+            .execute(&mut tx)
+            .await
+            .map_err(database_error)?;
+    }
+
+    sqlx::query(CREATE_DATABASE)
+        .execute(&mut tx)
+        .await
+        .map_err(database_error)?;
+
+    if let Some(Unversioned::Baseline) = unversioned {
+        sqlx::query(COPY_BASELINE)
+            .execute(&mut tx)
+            .await
+            .map_err(database_error)?;
+    }
+
+    sqlx::query(&format!("pragma user_version = {}", SCHEMA_VERSION))
+        .execute(&mut tx)
+        .await
+        .map_err(database_error)?;
+
+    tx.commit().await.map_err(database_error)
+}
+
+fn unversioned(columns: &[&str]) -> Result<Unversioned, String> {
+    if columns.is_empty() {
+        Ok(Unversioned::Empty)
+    } else if columns == BASELINE_COLUMNS {
+        Ok(Unversioned::Baseline)
+    } else if TASK_COLUMNS.iter().all(|column| columns.contains(column)) {
+        Ok(Unversioned::Current)
+    } else {
+        // NOTE(alex): The versions in between dropped their tables on every start, so there's
+        // nothing in there worth migrating.
+        Err(format!(
+            "The database has an unversioned `Task` table ({}) that can't be migrated, move the \
+            database file away to start over!",
+            columns.join(", ")
+        ))
+    }
+}
+
+fn database_error(fail: sqlx::Error) -> String {
+    format!("Failed to create the database schema: {}", fail)
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::*;
+
+    async fn memory_pool() -> SqlitePool {
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn migrates_baseline() {
+        let db_pool = memory_pool().await;
+        sqlx::query(
+            "create table Task (id integer primary key, title text not null, details text);
+            create table Done (task_id int not null);
+            insert into Task (id, title, details) values (1, 'ongoing', null), (2, 'done', 'd');
+            insert into Done (task_id) values (2), (2);",
+        )
+        .execute(&db_pool)
+        .await
+        .unwrap();
+
+        create_database(&db_pool).await.unwrap();
+        // NOTE(alex): Starting again is a no-op.
+        create_database(&db_pool).await.unwrap();
+
+        let tasks: Vec<(i64, String, String, String, bool)> = sqlx::query_as(
+            "select Task.id, Workspace.slug, Task.title, Task.details, Done.task_id is not null
+            from Task
+                join Workspace on Workspace.id = Task.workspace_id
+                left join Done on Done.task_id = Task.id
+            order by Task.id",
+        )
+        .fetch_all(&db_pool)
+        .await
+        .unwrap();
+
+        assert_eq!(
+            tasks,
+            vec![
+                (1, "default".into(), "ongoing".into(), "".into(), false),
+                (2, "default".into(), "done".into(), "d".into(), true),
+            ]
+        );
+    }
+
+    #[rocket::async_test]
+    async fn refuses_unknown_schemas() {
+        let db_pool = memory_pool().await;
+        sqlx::query("create table Task (id integer primary key, workspace_id int, title text)")
+            .execute(&db_pool)
+            .await
+            .unwrap();
+        assert!(create_database(&db_pool).await.is_err());
+
+        let db_pool = memory_pool().await;
+        sqlx::query("pragma user_version = 99")
+            .execute(&db_pool)
+            .await
+            .unwrap();
+        assert!(create_database(&db_pool).await.is_err());
+    }
+}
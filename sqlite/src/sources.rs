@@ -0,0 +1,472 @@
+use std::collections::HashMap;
+
+use fairings::FromBody;
+use rocket::{
+    data::{ByteUnit, FromData, ToByteUnit},
+    http::Status,
+    serde::{
+        json::{self, Value},
+        Deserialize, Serialize,
+    },
+};
+use utoipa::ToSchema;
+
+use crate::{
+    errors::AppError,
+    events::TaskEvents,
+    exchange::{from_basic, is_due, ImportedTask, Upserted},
+    models::Task,
+    negotiate::{body_text, read_body},
+    workspace::WorkspaceDb,
+};
+
+/// Same reasoning as `MAX_TODOS` of a calendar, every item is a transaction of its own. Only for
+/// the route, `sqlite import` takes an export of any size.
+pub(crate) const MAX_ITEMS: usize = 1000;
+
+/// The task managers `POST /import/<source>` reads the JSON exports of.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Source {
+    /// The array of `task export`.
+    Taskwarrior,
+    /// A board, from "Print, export, and share" > "Export as JSON".
+    Trello,
+}
+
+impl Source {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "taskwarrior" => Some(Self::Taskwarrior),
+            "trello" => Some(Self::Trello),
+            _ => None,
+        }
+    }
+
+    /// What `ImportedSource.source` holds.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Taskwarrior => "taskwarrior",
+            Self::Trello => "trello",
+        }
+    }
+}
+
+/// The JSON body of `POST /import/<source>`, read as it is, the source decides how to parse it.
+pub(crate) struct Export(pub(crate) String);
+
+impl<'r> FromBody<'r> for Export {
+    type Error = AppError;
+
+    // NOTE(alex): Trello boards carry their whole history of `actions`, so they get a bigger limit
+    // than the `json` one.
+    fn limit(req: &'r rocket::Request<'_>) -> ByteUnit {
+        req.limits().get("import").unwrap_or(16.mebibytes())
+    }
+
+    fn from_body(
+        req: &'r rocket::Request<'_>,
+        body: Vec<u8>,
+    ) -> Result<Self, (Status, Self::Error)> {
+        let is_json = req
+            .content_type()
+            .is_none_or(|content_type| content_type.is_json());
+        if !is_json {
+            return Err((Status::UnsupportedMediaType, AppError::UnsupportedFormat));
+        }
+
+        body_text(body).map(Export)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for Export {
+    type Error = AppError;
+
+    async fn from_data(
+        req: &'r rocket::Request<'_>,
+        data: rocket::Data<'r>,
+    ) -> rocket::data::Outcome<'r, Self> {
+        read_body(req, data).await
+    }
+}
+
+/// What happened to one item of an export, in the order of the export, `source_id` is the
+/// Taskwarrior `uuid` or the Trello card `id`.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub(crate) enum SourceResult {
+    /// First import of the item.
+    Imported { source_id: String, task: Task },
+    /// Imported before, and changed since.
+    Updated { source_id: String, task: Task },
+    /// Imported before, as it is.
+    Unchanged { source_id: String, task: Task },
+    /// Deleted, archived or recurring, nothing a task here can stand for.
+    Skipped { source_id: String, reason: String },
+    Failed {
+        source_id: Option<String>,
+        error: String,
+    },
+}
+
+/// An item of an export, read into a task, or the reason it's skipped.
+type Parsed = Result<ImportedTask, String>;
+
+/// The id of an item (when it has one), and what it was read into.
+type Item = (Option<String>, Result<Parsed, AppError>);
+
+/// `YYYY-MM-DD` of an annotation or comment, prefixed to its line of the details.
+fn dated(date: Option<String>, text: &str) -> String {
+    match date.as_deref().and_then(|date| date.get(..10)) {
+        Some(day) => format!("{} {}", day, text.trim()),
+        None => text.trim().to_string(),
+    }
+}
+
+/// Appends `lines` to `details`, after a blank line.
+fn with_notes(details: &str, lines: Vec<String>) -> String {
+    let mut details = details.trim_end().to_string();
+    if !lines.is_empty() {
+        if !details.is_empty() {
+            details.push_str("\n\n");
+        }
+        details.push_str(&lines.join("\n"));
+    }
+
+    details
+}
+
+#[derive(Debug, Deserialize)]
+struct TaskwarriorAnnotation {
+    entry: Option<String>,
+    description: String,
+}
+
+/// An item of `task export` (its `uuid` is read before), every date is in the basic format, in
+/// UTC.
+#[derive(Debug, Deserialize)]
+struct TaskwarriorTask {
+    description: String,
+    status: String,
+    #[serde(default)]
+    project: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    priority: Option<String>,
+    #[serde(default)]
+    entry: Option<String>,
+    #[serde(default)]
+    due: Option<String>,
+    #[serde(default)]
+    end: Option<String>,
+    #[serde(default)]
+    annotations: Vec<TaskwarriorAnnotation>,
+}
+
+fn taskwarrior_date(name: &str, value: &str) -> Result<String, AppError> {
+    from_basic(value).ok_or_else(|| {
+        AppError::InvalidTask(format!("`{}` value `{}` is not a date-time", name, value))
+    })
+}
+
+impl TaskwarriorTask {
+    /// `pending` and `waiting` tasks are ongoing, `completed` ones done. A `recurring` task is
+    /// only the template of its instances (which are exported too), so it's skipped along with
+    /// the `deleted` ones.
+    fn parse(self) -> Result<Parsed, AppError> {
+        let done = match self.status.as_str() {
+            "pending" | "waiting" => false,
+            "completed" => true,
+            "deleted" | "recurring" => return Ok(Err(format!("task is `{}`", self.status))),
+            status => {
+                return Err(AppError::InvalidTask(format!(
+                    "unknown `status` `{}`",
+                    status
+                )))
+            }
+        };
+
+        let annotations = self
+            .annotations
+            .iter()
+            .map(|annotation| {
+                let entry = annotation
+                    .entry
+                    .as_deref()
+                    .and_then(from_basic)
+                    .filter(|entry| entry.len() >= 10);
+                dated(entry, &annotation.description)
+            })
+            .collect();
+
+        // NOTE(alex): `H`, `M` and `L` are the todo.txt priorities `A`, `B` and `C`.
+        let priority = match self.priority.as_deref() {
+            Some("H") => Some("A".to_string()),
+            Some("M") => Some("B".to_string()),
+            Some("L") => Some("C".to_string()),
+            _ => None,
+        };
+
+        let created_on = match &self.entry {
+            Some(entry) => Some(taskwarrior_date("entry", entry)?[..10].to_string()),
+            None => None,
+        };
+        let due = self
+            .due
+            .as_deref()
+            .map(|due| taskwarrior_date("due", due))
+            .transpose()?;
+        let completed_at = match (&self.end, done) {
+            (Some(end), true) => Some(taskwarrior_date("end", end)?),
+            _ => None,
+        };
+
+        Ok(Ok(ImportedTask {
+            title: self.description,
+            details: with_notes("", annotations),
+            list: self.project,
+            tags: self.tags,
+            due,
+            priority,
+            created_on,
+            done,
+            completed_at,
+        }))
+    }
+}
+
+/// The items of a `task export`, by `uuid`.
+fn parse_taskwarrior(export: &str) -> Result<Vec<Item>, AppError> {
+    let items: Vec<Value> = json::from_str(export).map_err(|fail| {
+        AppError::InvalidTask(format!("body is not a `task export` array: {}", fail))
+    })?;
+
+    Ok(items
+        .into_iter()
+        .map(|item| {
+            let uuid = item["uuid"].as_str().map(str::to_string);
+            let parsed = json::from_value::<TaskwarriorTask>(item)
+                .map_err(|fail| AppError::InvalidTask(fail.to_string()))
+                .and_then(TaskwarriorTask::parse);
+
+            (uuid, parsed)
+        })
+        .collect())
+}
+
+#[derive(Debug, Deserialize)]
+struct TrelloList {
+    id: String,
+    name: String,
+    #[serde(default)]
+    closed: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct TrelloLabel {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    color: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TrelloCard {
+    id: String,
+    name: String,
+    #[serde(default)]
+    desc: String,
+    #[serde(default, rename = "idList")]
+    id_list: Option<String>,
+    #[serde(default)]
+    labels: Vec<TrelloLabel>,
+    #[serde(default)]
+    due: Option<String>,
+    #[serde(default, rename = "dueComplete")]
+    due_complete: bool,
+    #[serde(default)]
+    closed: bool,
+}
+
+/// Only the `commentCard` ones are read, the rest of the history of the board is dropped.
+#[derive(Debug, Deserialize)]
+struct TrelloAction {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    date: Option<String>,
+    #[serde(default)]
+    data: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct TrelloBoard {
+    #[serde(default)]
+    lists: Vec<TrelloList>,
+    cards: Vec<Value>,
+    #[serde(default)]
+    actions: Vec<TrelloAction>,
+}
+
+/// Trello dates are `2026-10-18T12:00:00.000Z`, the milliseconds are dropped.
+fn trello_date(value: &str) -> Result<String, AppError> {
+    let due = match value.split_once('.') {
+        Some((date_time, _)) => format!("{}Z", date_time),
+        None => value.to_string(),
+    };
+
+    if is_due(&due) {
+        Ok(due)
+    } else {
+        Err(AppError::InvalidTask(format!(
+            "`due` value `{}` is not a date-time",
+            value
+        )))
+    }
+}
+
+impl TrelloCard {
+    /// The list of the card is its list, its labels are tags (by color when they have no name),
+    /// and its comments go below its description. Archived cards, and the ones of archived lists,
+    /// are skipped.
+    fn parse(
+        self,
+        lists: &HashMap<&str, &TrelloList>,
+        comments: &mut HashMap<String, Vec<String>>,
+    ) -> Result<Parsed, AppError> {
+        let list = self
+            .id_list
+            .as_deref()
+            .and_then(|id_list| lists.get(id_list));
+
+        if self.closed {
+            return Ok(Err("card is archived".to_string()));
+        }
+        if list.is_some_and(|list| list.closed) {
+            return Ok(Err("list of the card is archived".to_string()));
+        }
+
+        let tags = self
+            .labels
+            .into_iter()
+            .filter_map(|label| match label.name.trim() {
+                "" => label.color,
+                name => Some(name.to_string()),
+            })
+            .collect();
+
+        Ok(Ok(ImportedTask {
+            title: self.name,
+            details: with_notes(&self.desc, comments.remove(&self.id).unwrap_or_default()),
+            list: list.map(|list| list.name.clone()),
+            tags,
+            due: self.due.as_deref().map(trello_date).transpose()?,
+            done: self.due_complete,
+            ..ImportedTask::default()
+        }))
+    }
+}
+
+/// The cards of a board, by `id`.
+fn parse_trello(export: &str) -> Result<Vec<Item>, AppError> {
+    let board: TrelloBoard = json::from_str(export)
+        .map_err(|fail| AppError::InvalidTask(format!("body is not a Trello board: {}", fail)))?;
+
+    let lists = board
+        .lists
+        .iter()
+        .map(|list| (list.id.as_str(), list))
+        .collect::<HashMap<_, _>>();
+
+    // NOTE(alex): `actions` are newest first, comments read better oldest first.
+    let mut comments: HashMap<String, Vec<String>> = HashMap::new();
+    for action in board.actions.iter().rev() {
+        if action.kind != "commentCard" {
+            continue;
+        }
+
+        if let (Some(card), Some(text)) = (
+            action.data["card"]["id"].as_str(),
+            action.data["text"].as_str(),
+        ) {
+            let date = action.date.clone().filter(|date| date.len() >= 10);
+            comments
+                .entry(card.to_string())
+                .or_default()
+                .push(dated(date, text));
+        }
+    }
+
+    Ok(board
+        .cards
+        .into_iter()
+        .map(|card| {
+            let id = card["id"].as_str().map(str::to_string);
+            let parsed = json::from_value::<TrelloCard>(card)
+                .map_err(|fail| AppError::InvalidTask(fail.to_string()))
+                .and_then(|card| card.parse(&lists, &mut comments));
+
+            (id, parsed)
+        })
+        .collect())
+}
+
+/// Imports every item of `export`, each one on its own, a failure doesn't stop the others.
+/// Importing the same export again only updates what changed at the source, see
+/// [`ImportedTask::upsert`]. An export with more than `max_items` is refused as a whole.
+pub(crate) async fn import(
+    workspace_db: &WorkspaceDb<'_>,
+    task_events: &TaskEvents,
+    source: Source,
+    export: &str,
+    max_items: Option<usize>,
+) -> Result<Vec<SourceResult>, AppError> {
+    let items = match source {
+        Source::Taskwarrior => parse_taskwarrior(export)?,
+        Source::Trello => parse_trello(export)?,
+    };
+
+    if let Some(max_items) = max_items.filter(|max_items| items.len() > *max_items) {
+        return Err(AppError::InvalidTask(format!(
+            "an export can't have more than `{}` items",
+            max_items
+        )));
+    }
+
+    let mut results = Vec::with_capacity(items.len());
+    for (source_id, parsed) in items {
+        let result = match (source_id, parsed) {
+            (None, parsed) => SourceResult::Failed {
+                source_id: None,
+                error: match parsed {
+                    Err(fail) => fail.to_string(),
+                    Ok(_) => "item has no id".to_string(),
+                },
+            },
+            (Some(source_id), Err(fail)) => SourceResult::Failed {
+                source_id: Some(source_id),
+                error: fail.to_string(),
+            },
+            (Some(source_id), Ok(Err(reason))) => SourceResult::Skipped { source_id, reason },
+            (Some(source_id), Ok(Ok(imported_task))) => {
+                match imported_task
+                    .upsert(workspace_db, task_events, source.name(), &source_id)
+                    .await
+                {
+                    Ok((Upserted::Inserted, task)) => SourceResult::Imported { source_id, task },
+                    Ok((Upserted::Updated, task)) => SourceResult::Updated { source_id, task },
+                    Ok((Upserted::Unchanged, task)) => SourceResult::Unchanged { source_id, task },
+                    Err(fail) => SourceResult::Failed {
+                        source_id: Some(source_id),
+                        error: fail.to_string(),
+                    },
+                }
+            }
+        };
+
+        results.push(result);
+    }
+
+    Ok(results)
+}
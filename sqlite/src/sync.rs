@@ -0,0 +1,365 @@
+use rocket::serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use crate::{
+    errors::AppError,
+    events::{TaskEventKind, TaskEvents},
+    models::{InsertTask, Task},
+    workspace::{Scoped, WorkspaceDb, WorkspaceTx},
+};
+
+const CURRENT_SEQUENCE: Scoped = Scoped::new(include_str!("./../queries/current_sequence.sql"));
+const FIND_CHANGED: Scoped = Scoped::new(include_str!("./../queries/find_changed.sql"));
+const FIND_TOMBSTONES: Scoped = Scoped::new(include_str!("./../queries/find_tombstones.sql"));
+const FIND_TOMBSTONE_BY_ID: Scoped =
+    Scoped::new(include_str!("./../queries/find_tombstone_by_id.sql"));
+const FIND_SYNCED_BY_ID: Scoped = Scoped::new(include_str!("./../queries/find_synced_by_id.sql"));
+const INSERT: Scoped = Scoped::new(include_str!("./../queries/insert.sql"));
+const UPDATE_IF_UNCHANGED: Scoped =
+    Scoped::new(include_str!("./../queries/update_if_unchanged.sql"));
+const DELETE_IF_UNCHANGED: Scoped =
+    Scoped::new(include_str!("./../queries/delete_if_unchanged.sql"));
+
+const COMPLETED: Scoped = Scoped::new(include_str!("./../queries/done.sql"));
+const UNDO: Scoped = Scoped::new(include_str!("./../queries/undo.sql"));
+
+/// A `Task` as seen by sync clients, with its done state and the `seq` of its last change.
+#[derive(Clone, Debug, Serialize, FromRow, ToSchema)]
+pub(crate) struct SyncedTask {
+    pub(crate) id: i64,
+    pub(crate) list_id: Option<i64>,
+    pub(crate) title: String,
+    pub(crate) details: String,
+    pub(crate) done: bool,
+    pub(crate) seq: i64,
+    pub(crate) version: i64,
+}
+
+#[derive(Clone, Debug, Serialize, FromRow, ToSchema)]
+pub(crate) struct Tombstone {
+    pub(crate) id: i64,
+    pub(crate) seq: i64,
+}
+
+/// Everything that changed after `since`, resume from `token` next time.
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct Delta {
+    pub(crate) token: i64,
+    pub(crate) changed: Vec<SyncedTask>,
+    pub(crate) deleted: Vec<Tombstone>,
+}
+
+/// A change made while offline, `base_seq` is the `seq` of the task the client changed.
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub(crate) enum ClientChange {
+    Create {
+        #[serde(flatten)]
+        insert_task: InsertTask,
+        #[serde(default)]
+        done: bool,
+    },
+    Update {
+        id: i64,
+        base_seq: i64,
+        new_title: String,
+        details: String,
+        done: bool,
+    },
+    Delete {
+        id: i64,
+        base_seq: i64,
+    },
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub(crate) struct Push {
+    pub(crate) changes: Vec<ClientChange>,
+}
+
+/// Result of each `ClientChange`, in the same order they were pushed.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub(crate) enum ChangeResult {
+    /// `task` is `None` for deletes.
+    Applied {
+        task: Option<SyncedTask>,
+    },
+    /// Someone else changed the task first, `current` is `None` when it was deleted.
+    Conflict {
+        current: Option<SyncedTask>,
+    },
+    Rejected {
+        error: String,
+    },
+}
+
+impl SyncedTask {
+    fn opened(mut self, workspace_db: &WorkspaceDb<'_>) -> Result<Self, AppError> {
+        if let Some(cipher) = workspace_db.cipher() {
+            self.details = cipher.open(workspace_db.workspace.id, self.id, &self.details)?;
+        }
+
+        Ok(self)
+    }
+
+    fn task(&self) -> Task {
+        Task {
+            id: self.id,
+            list_id: self.list_id,
+            title: self.title.clone(),
+            details: self.details.clone(),
+            version: self.version,
+        }
+    }
+
+    async fn find_by_id(
+        workspace_db: &WorkspaceDb<'_>,
+        tx: &mut WorkspaceTx,
+        task_id: i64,
+    ) -> Result<Option<Self>, AppError> {
+        let result: Option<Self> = tx
+            .query_as(FIND_SYNCED_BY_ID)
+            .bind(task_id)
+            .fetch_optional()
+            .await?;
+
+        result.map(|task| task.opened(workspace_db)).transpose()
+    }
+}
+
+impl Delta {
+    pub(crate) async fn since(
+        workspace_db: &WorkspaceDb<'_>,
+        since: i64,
+    ) -> Result<Self, AppError> {
+        // NOTE(alex): The sequence is shared by every workspace. It's read first, and bounds the
+        // other queries, so a change committed in between shows up in the next delta instead of
+        // being skipped.
+        let mut tx = workspace_db.begin().await?;
+        let (token,): (i64,) = tx.query_as(CURRENT_SEQUENCE).fetch_one().await?;
+
+        let changed: Vec<SyncedTask> = tx
+            .query_as(FIND_CHANGED)
+            .bind(since)
+            .bind(token)
+            .fetch_all()
+            .await?;
+
+        let deleted = tx
+            .query_as(FIND_TOMBSTONES)
+            .bind(since)
+            .bind(token)
+            .fetch_all()
+            .await?;
+
+        Ok(Self {
+            token,
+            changed: changed
+                .into_iter()
+                .map(|task| task.opened(workspace_db))
+                .collect::<Result<_, _>>()?,
+            deleted,
+        })
+    }
+}
+
+impl ClientChange {
+    /// Applies the change in its own transaction, a conflict or an error leaves the task as it
+    /// was.
+    pub(crate) async fn apply(
+        self,
+        workspace_db: &WorkspaceDb<'_>,
+        task_events: &TaskEvents,
+    ) -> ChangeResult {
+        let result = match self {
+            ClientChange::Create { insert_task, done } => {
+                Self::create(workspace_db, task_events, insert_task, done).await
+            }
+            ClientChange::Update {
+                id,
+                base_seq,
+                new_title,
+                details,
+                done,
+            } => {
+                Self::update(
+                    workspace_db,
+                    task_events,
+                    id,
+                    base_seq,
+                    new_title,
+                    details,
+                    done,
+                )
+                .await
+            }
+            ClientChange::Delete { id, base_seq } => {
+                Self::delete(workspace_db, task_events, id, base_seq).await
+            }
+        };
+
+        result.unwrap_or_else(|fail| ChangeResult::Rejected {
+            error: fail.to_string(),
+        })
+    }
+
+    async fn create(
+        workspace_db: &WorkspaceDb<'_>,
+        task_events: &TaskEvents,
+        insert_task: InsertTask,
+        done: bool,
+    ) -> Result<ChangeResult, AppError> {
+        insert_task.validate()?;
+
+        let mut tx = workspace_db.begin().await?;
+        let result = tx
+            .query(INSERT)
+            .bind(&insert_task.list_id)
+            .bind(&insert_task.non_empty_title)
+            .bind(Task::inserted_details(workspace_db, &insert_task.details))
+            .execute()
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::QuotaExceeded(workspace_db.workspace.max_tasks));
+        }
+
+        let id = result.last_insert_rowid();
+        Task::seal_inserted(workspace_db, &mut tx, id, &insert_task.details).await?;
+        if done {
+            tx.query(COMPLETED)
+                .bind(id)
+                .bind(None::<i64>)
+                .execute()
+                .await?;
+        }
+
+        let task = SyncedTask::find_by_id(workspace_db, &mut tx, id).await?;
+        tx.commit().await?;
+
+        if let Some(task) = &task {
+            let workspace_id = workspace_db.workspace.id;
+            task_events.publish(workspace_id, TaskEventKind::Created, task.task());
+
+            if task.done {
+                task_events.publish(workspace_id, TaskEventKind::Done, task.task());
+            }
+        }
+
+        Ok(ChangeResult::Applied { task })
+    }
+
+    async fn update(
+        workspace_db: &WorkspaceDb<'_>,
+        task_events: &TaskEvents,
+        id: i64,
+        base_seq: i64,
+        new_title: String,
+        details: String,
+        done: bool,
+    ) -> Result<ChangeResult, AppError> {
+        if new_title.trim().is_empty() {
+            return Err(AppError::EmptyTitle);
+        }
+
+        // NOTE(alex): Writes first, so the transaction holds the write lock before it reads
+        // anything back.
+        let mut tx = workspace_db.begin().await?;
+        let result = tx
+            .query(UPDATE_IF_UNCHANGED)
+            .bind(&new_title)
+            .bind(&Task::sealed_details(workspace_db, id, &details)?)
+            .bind(id)
+            .bind(base_seq)
+            .execute()
+            .await?;
+
+        if result.rows_affected() == 0 {
+            let current = SyncedTask::find_by_id(workspace_db, &mut tx, id).await?;
+            tx.rollback().await?;
+
+            return Ok(ChangeResult::Conflict { current });
+        }
+
+        let was_done = SyncedTask::find_by_id(workspace_db, &mut tx, id)
+            .await?
+            .map_or(false, |task| task.done);
+
+        if done != was_done {
+            let query = if done { COMPLETED } else { UNDO };
+            tx.query(query).bind(id).bind(None::<i64>).execute().await?;
+        }
+
+        let task = SyncedTask::find_by_id(workspace_db, &mut tx, id).await?;
+        tx.commit().await?;
+
+        if let Some(task) = &task {
+            let workspace_id = workspace_db.workspace.id;
+            task_events.publish(workspace_id, TaskEventKind::Updated, task.task());
+
+            if done != was_done {
+                let kind = if done {
+                    TaskEventKind::Done
+                } else {
+                    TaskEventKind::Undone
+                };
+                task_events.publish(workspace_id, kind, task.task());
+            }
+        }
+
+        Ok(ChangeResult::Applied { task })
+    }
+
+    async fn delete(
+        workspace_db: &WorkspaceDb<'_>,
+        task_events: &TaskEvents,
+        id: i64,
+        base_seq: i64,
+    ) -> Result<ChangeResult, AppError> {
+        let mut tx = workspace_db.begin().await?;
+        let current = SyncedTask::find_by_id(workspace_db, &mut tx, id).await?;
+
+        let result = tx
+            .query(DELETE_IF_UNCHANGED)
+            .bind(id)
+            .bind(base_seq)
+            .execute()
+            .await?;
+
+        if result.rows_affected() > 0 {
+            tx.commit().await?;
+
+            if let Some(task) = current {
+                task_events.publish(
+                    workspace_db.workspace.id,
+                    TaskEventKind::Deleted,
+                    task.task(),
+                );
+            }
+
+            return Ok(ChangeResult::Applied { task: None });
+        }
+
+        match SyncedTask::find_by_id(workspace_db, &mut tx, id).await? {
+            Some(current) => Ok(ChangeResult::Conflict {
+                current: Some(current),
+            }),
+            None => {
+                // NOTE(alex): Deleting a task that is already gone is fine, unless it never
+                // existed in this workspace.
+                let tombstone: Option<(i64,)> = tx
+                    .query_as(FIND_TOMBSTONE_BY_ID)
+                    .bind(id)
+                    .fetch_optional()
+                    .await?;
+
+                match tombstone {
+                    Some(_) => Ok(ChangeResult::Applied { task: None }),
+                    None => Err(AppError::IdNotFound(id)),
+                }
+            }
+        }
+    }
+}
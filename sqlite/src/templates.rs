@@ -0,0 +1,97 @@
+use log::error;
+use rocket::{
+    fairing::{self, Fairing, Info, Kind},
+    http::Status,
+    response::{self, content, Responder},
+    serde::{Deserialize, Serialize},
+    Build, Request, Rocket,
+};
+use tera::{Context, Tera};
+
+/// `template_dir` in `Rocket.toml`, relative to the crate.
+#[derive(Debug, Clone, Deserialize)]
+struct TemplateConfig {
+    #[serde(default = "default_template_dir")]
+    template_dir: String,
+}
+
+fn default_template_dir() -> String {
+    "templates".to_string()
+}
+
+/// Every `*.html.tera` of the `template_dir`, loaded once by [`Templates`].
+pub(crate) struct TemplateEngine(Tera);
+
+/// Loads the templates, and refuses to launch when one of them doesn't parse.
+///
+/// NOTE(alex): Does what `rocket_dyn_templates` does (minus the reloading in debug builds), with
+/// `tera` used directly.
+pub(crate) struct Templates;
+
+#[rocket::async_trait]
+impl Fairing for Templates {
+    fn info(&self) -> Info {
+        Info {
+            name: "Templates",
+            kind: Kind::Ignite,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let config = rocket
+            .figment()
+            .extract::<TemplateConfig>()
+            .unwrap_or_else(|_| TemplateConfig {
+                template_dir: default_template_dir(),
+            });
+
+        let glob = format!("{}/**/*.html.tera", config.template_dir);
+        let mut tera = match Tera::new(&glob) {
+            Ok(tera) => tera,
+            Err(fail) => {
+                error!("Failed to load the templates `{}`: {:?}", glob, fail);
+                return Err(rocket);
+            }
+        };
+
+        // NOTE(alex): Tera only escapes templates ending in `.html` by default.
+        tera.autoescape_on(vec![".html.tera"]);
+
+        Ok(rocket.manage(TemplateEngine(tera)))
+    }
+}
+
+/// An HTML page, `name` is the template file without its `.html.tera`.
+pub(crate) struct Template {
+    name: &'static str,
+    context: tera::Result<Context>,
+}
+
+impl Template {
+    pub(crate) fn render(name: &'static str, context: impl Serialize) -> Self {
+        Self {
+            name,
+            context: Context::from_serialize(context),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Template {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let engine = req.rocket().state::<TemplateEngine>().ok_or_else(|| {
+            error!("Templates require the `Templates` fairing to be attached!");
+            Status::InternalServerError
+        })?;
+
+        let name = format!("{}.html.tera", self.name);
+        let html = self
+            .context
+            .and_then(|context| engine.0.render(&name, &context))
+            .map_err(|fail| {
+                error!("Failed to render `{}`: {:?}", name, fail);
+                Status::InternalServerError
+            })?;
+
+        content::Html(html).respond_to(req)
+    }
+}
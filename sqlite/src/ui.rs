@@ -0,0 +1,378 @@
+use std::collections::HashSet;
+
+use fairings::CsrfToken;
+use rocket::{
+    catch,
+    form::{Form, Strict},
+    get,
+    http::{Cookie, CookieJar, SameSite},
+    post,
+    request::{FlashMessage, FromRequest, Outcome},
+    response::{Flash, Redirect},
+    serde::Serialize,
+    FromForm, Request, State,
+};
+use sqlx::SqlitePool;
+
+use crate::{
+    batch::Operation,
+    errors::AppError,
+    events::TaskEvents,
+    models::{InsertTask, Task, UpdateTask},
+    templates::Template,
+    workspace::{path_prefix, selected_slug, Workspace, WorkspaceDb, PATH_PREFIX, TOKEN_COOKIE},
+};
+
+// NOTE(alex): Every form starts with the `_csrf` field the `Csrf` fairing checks (it only peeks
+// at the start of the body), the handlers never read it.
+
+/// `POST /ui/sign-in`, `token` is the API token of the workspace.
+#[derive(Debug, FromForm)]
+pub(crate) struct SignInForm {
+    _csrf: String,
+    token: String,
+}
+
+/// `POST /ui/tasks`, the form at the top of the list.
+#[derive(Debug, FromForm)]
+pub(crate) struct TaskForm {
+    _csrf: String,
+    title: String,
+    details: String,
+}
+
+/// `POST /ui/tasks/<id>`, `version` is the one the edit page was rendered with.
+#[derive(Debug, FromForm)]
+pub(crate) struct EditForm {
+    _csrf: String,
+    title: String,
+    details: String,
+    version: i64,
+}
+
+/// The `done`, `undo` and `delete` buttons of a task.
+#[derive(Debug, FromForm)]
+pub(crate) struct VersionForm {
+    _csrf: String,
+    version: i64,
+}
+
+/// The flash message of the previous action, `kind` is `success` or `error`.
+#[derive(Debug, Serialize)]
+struct Message {
+    kind: String,
+    text: String,
+}
+
+impl Message {
+    fn from_flash(flash: Option<FlashMessage<'_>>) -> Option<Self> {
+        flash.map(|flash| Message {
+            kind: flash.kind().to_string(),
+            text: flash.message().to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct SignInPage {
+    base: String,
+    workspace: Option<String>,
+    flash: Option<Message>,
+    csrf: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ListPage {
+    base: String,
+    workspace: String,
+    flash: Option<Message>,
+    csrf: String,
+    ongoing: Vec<Task>,
+    done: Vec<Task>,
+}
+
+#[derive(Debug, Serialize)]
+struct EditPage {
+    base: String,
+    workspace: String,
+    flash: Option<Message>,
+    csrf: String,
+    task: Task,
+    done: bool,
+}
+
+/// Links and redirects always go through the `/w/<slug>` prefix, whatever host the browser is on.
+fn base(workspace_db: &WorkspaceDb<'_>) -> String {
+    format!("{}{}/ui", PATH_PREFIX, workspace_db.workspace.slug)
+}
+
+/// Where the sign-in page is for the browser, which may not be signed in to any workspace yet:
+/// `base` keeps the `/w/<slug>` prefix it came through, if any.
+pub(crate) struct SignInBase {
+    base: String,
+    slug: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SignInBase {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(SignInBase {
+            base: format!("{}/ui", path_prefix(req)),
+            slug: selected_slug(req),
+        })
+    }
+}
+
+fn sign_in_redirect(req: &Request<'_>, message: &str) -> Flash<Redirect> {
+    Flash::error(
+        Redirect::to(format!("{}/ui/sign-in", path_prefix(req))),
+        message.to_string(),
+    )
+}
+
+/// Pages and forms of a browser that isn't signed in (`401`) go to the sign-in page instead.
+#[catch(401)]
+pub(crate) fn sign_in_required(req: &Request<'_>) -> Flash<Redirect> {
+    sign_in_redirect(req, "Sign in with the API token of the workspace.")
+}
+
+/// Same for a browser signed in to another workspace (`403`).
+#[catch(403)]
+pub(crate) fn other_workspace(req: &Request<'_>) -> Flash<Redirect> {
+    sign_in_redirect(req, "Signed in to another workspace, sign in to this one.")
+}
+
+/// Runs `operation` like a batch of one (same checks and events as the API), and goes back to the
+/// list with a flash message of how it went.
+///
+/// NOTE(alex): Post/redirect/get, every form posts here and the browser ends on a `GET`, so
+/// reloading the page never submits a form again.
+async fn apply(
+    workspace_db: &WorkspaceDb<'_>,
+    task_events: &TaskEvents,
+    operation: Operation,
+    done: &str,
+) -> Flash<Redirect> {
+    let redirect = Redirect::to(base(workspace_db));
+
+    let applied = async {
+        let mut tx = workspace_db.begin().await?;
+        let applied = operation.apply(workspace_db, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok::<_, AppError>(applied)
+    };
+
+    match applied.await {
+        Ok(applied) => {
+            let title = applied
+                .task
+                .as_ref()
+                .or(applied.event.as_ref().map(|(_, task)| task))
+                .map(|task| task.title.clone())
+                .unwrap_or_default();
+
+            if let Some((kind, task)) = applied.event {
+                task_events.publish(workspace_db.workspace.id, kind, task);
+            }
+
+            Flash::success(redirect, format!("`{}` {}.", title, done))
+        }
+        Err(AppError::PreconditionFailed) => Flash::error(
+            redirect,
+            "The task was changed by someone else in the meantime, nothing was changed.",
+        ),
+        Err(fail) => Flash::error(redirect, fail.to_string()),
+    }
+}
+
+#[get("/ui/sign-in")]
+pub(crate) async fn sign_in_page(
+    sign_in_base: SignInBase,
+    csrf: CsrfToken,
+    flash: Option<FlashMessage<'_>>,
+) -> Template {
+    Template::render(
+        "sign_in",
+        SignInPage {
+            base: sign_in_base.base,
+            workspace: sign_in_base.slug,
+            flash: Message::from_flash(flash),
+            csrf: csrf.as_str().to_string(),
+        },
+    )
+}
+
+/// Keeps the token in an `HttpOnly` cookie when it opens the workspace (the one of the slug, when
+/// there's one), the UI then works like the API does with the header.
+#[post("/ui/sign-in", data = "<form>")]
+pub(crate) async fn sign_in(
+    db_pool: &State<SqlitePool>,
+    sign_in_base: SignInBase,
+    cookies: &CookieJar<'_>,
+    form: Form<Strict<SignInForm>>,
+) -> Flash<Redirect> {
+    let token = form.into_inner().into_inner().token;
+
+    match Workspace::resolve(db_pool, Some(&token), sign_in_base.slug).await {
+        Ok(workspace) => {
+            let mut cookie = Cookie::new(TOKEN_COOKIE, token);
+            cookie.set_path("/");
+            cookie.set_http_only(true);
+            cookie.set_same_site(SameSite::Strict);
+            cookies.add(cookie);
+
+            Flash::success(
+                Redirect::to(format!("{}{}/ui", PATH_PREFIX, workspace.slug)),
+                format!("Signed in to `{}`.", workspace.slug),
+            )
+        }
+        Err(fail) => Flash::error(
+            Redirect::to(format!("{}/sign-in", sign_in_base.base)),
+            fail.to_string(),
+        ),
+    }
+}
+
+/// The form only carries the `_csrf` field, nothing to read.
+#[post("/ui/sign-out")]
+pub(crate) async fn sign_out(sign_in_base: SignInBase, cookies: &CookieJar<'_>) -> Flash<Redirect> {
+    cookies.remove(Cookie::build(TOKEN_COOKIE, "").path("/").finish());
+
+    Flash::success(
+        Redirect::to(format!("{}/sign-in", sign_in_base.base)),
+        "Signed out.",
+    )
+}
+
+/// Ongoing tasks first, then the done ones.
+#[get("/ui")]
+pub(crate) async fn list_page(
+    workspace_db: WorkspaceDb<'_>,
+    csrf: CsrfToken,
+    flash: Option<FlashMessage<'_>>,
+) -> Result<Template, AppError> {
+    let ongoing = Task::find_ongoing(&workspace_db).await?;
+    let ongoing_ids = ongoing.iter().map(|task| task.id).collect::<HashSet<_>>();
+
+    let done = Task::find_all(&workspace_db)
+        .await?
+        .into_iter()
+        .filter(|task| !ongoing_ids.contains(&task.id))
+        .collect();
+
+    Ok(Template::render(
+        "tasks",
+        ListPage {
+            base: base(&workspace_db),
+            workspace: workspace_db.workspace.slug.clone(),
+            flash: Message::from_flash(flash),
+            csrf: csrf.as_str().to_string(),
+            ongoing,
+            done,
+        },
+    ))
+}
+
+#[get("/ui/tasks/<id>")]
+pub(crate) async fn edit_page(
+    workspace_db: WorkspaceDb<'_>,
+    csrf: CsrfToken,
+    flash: Option<FlashMessage<'_>>,
+    id: i64,
+) -> Result<Option<Template>, AppError> {
+    let task = match Task::find_by_id(&workspace_db, id).await? {
+        Some(task) => task,
+        None => return Ok(None),
+    };
+    let done = Task::is_done(&workspace_db, id).await?;
+
+    Ok(Some(Template::render(
+        "task",
+        EditPage {
+            base: base(&workspace_db),
+            workspace: workspace_db.workspace.slug.clone(),
+            flash: Message::from_flash(flash),
+            csrf: csrf.as_str().to_string(),
+            task,
+            done,
+        },
+    )))
+}
+
+#[post("/ui/tasks", data = "<form>")]
+pub(crate) async fn create_task(
+    workspace_db: WorkspaceDb<'_>,
+    task_events: &State<TaskEvents>,
+    form: Form<Strict<TaskForm>>,
+) -> Flash<Redirect> {
+    let form = form.into_inner().into_inner();
+    let operation = Operation::Insert(InsertTask {
+        non_empty_title: form.title,
+        details: form.details,
+        list_id: None,
+    });
+
+    apply(&workspace_db, task_events, operation, "created").await
+}
+
+#[post("/ui/tasks/<id>", data = "<form>")]
+pub(crate) async fn edit_task(
+    workspace_db: WorkspaceDb<'_>,
+    task_events: &State<TaskEvents>,
+    id: i64,
+    form: Form<Strict<EditForm>>,
+) -> Flash<Redirect> {
+    let form = form.into_inner().into_inner();
+    let operation = Operation::Update {
+        update_task: UpdateTask {
+            id,
+            new_title: form.title,
+            details: form.details,
+        },
+        version: Some(form.version),
+    };
+
+    apply(&workspace_db, task_events, operation, "updated").await
+}
+
+#[post("/ui/tasks/<id>/done", data = "<form>")]
+pub(crate) async fn complete_task(
+    workspace_db: WorkspaceDb<'_>,
+    task_events: &State<TaskEvents>,
+    id: i64,
+    form: Form<Strict<VersionForm>>,
+) -> Flash<Redirect> {
+    let version = Some(form.into_inner().into_inner().version);
+    let operation = Operation::Done { id, version };
+
+    apply(&workspace_db, task_events, operation, "done").await
+}
+
+#[post("/ui/tasks/<id>/undo", data = "<form>")]
+pub(crate) async fn undo_task(
+    workspace_db: WorkspaceDb<'_>,
+    task_events: &State<TaskEvents>,
+    id: i64,
+    form: Form<Strict<VersionForm>>,
+) -> Flash<Redirect> {
+    let version = Some(form.into_inner().into_inner().version);
+    let operation = Operation::Undo { id, version };
+
+    apply(&workspace_db, task_events, operation, "is ongoing again").await
+}
+
+#[post("/ui/tasks/<id>/delete", data = "<form>")]
+pub(crate) async fn delete_task(
+    workspace_db: WorkspaceDb<'_>,
+    task_events: &State<TaskEvents>,
+    id: i64,
+    form: Form<Strict<VersionForm>>,
+) -> Flash<Redirect> {
+    let version = Some(form.into_inner().into_inner().version);
+    let operation = Operation::Delete { id, version };
+
+    apply(&workspace_db, task_events, operation, "deleted").await
+}
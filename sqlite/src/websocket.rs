@@ -0,0 +1,522 @@
+use std::net::SocketAddr;
+
+use async_graphql::{
+    http::{WebSocket as GraphQLWebSocket, WebSocketProtocols as Protocols, WsMessage},
+    Data,
+};
+use fairings::{AllowedOrigins, Cors};
+use log::{error, info, warn};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    futures::{future, SinkExt, StreamExt},
+    serde::{json::serde_json, Deserialize, Serialize},
+    tokio::{
+        self,
+        net::{TcpListener, TcpStream},
+        select,
+        sync::broadcast::error::RecvError,
+    },
+    Orbit, Rocket,
+};
+use sqlx::SqlitePool;
+use tokio_tungstenite::{
+    tungstenite::{
+        handshake::server::{ErrorResponse, Request, Response},
+        http::{HeaderValue, StatusCode},
+        protocol::CloseFrame,
+        Message,
+    },
+    WebSocketStream,
+};
+
+use crate::{
+    encryption::DetailsCipher,
+    errors::AppError,
+    events::{TaskEvent, TaskEventKind, TaskEvents},
+    graphql::{GraphQLContext, TaskSchema},
+    models::{InsertTask, Task, UpdateTask},
+    rate_limit::{self, RateLimits},
+    workspace::{self, Workspace, WorkspaceConfig, WorkspaceDb, PATH_PREFIX, TOKEN_COOKIE},
+};
+
+/// Bumped whenever the envelope changes in a way old clients can't handle.
+const PROTOCOL_VERSION: u32 = 1;
+
+/// A command sent by the client, `request_id` is echoed back in the reply.
+///
+/// ```json
+/// { "version": 1, "request_id": "42", "command": { "type": "done", "id": 1 } }
+/// ```
+#[derive(Debug, Deserialize)]
+struct ClientEnvelope {
+    version: u32,
+    request_id: String,
+    command: Command,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Command {
+    Insert(InsertTask),
+    Update(UpdateTask),
+    Delete { id: i64 },
+    Done { id: i64 },
+    Undo { id: i64 },
+}
+
+impl Command {
+    /// The method and path of the route doing the same, for the `rate_limit` groups.
+    fn route(&self) -> (&'static str, String) {
+        match self {
+            Command::Insert(_) => ("POST", "/tasks".to_string()),
+            Command::Update(_) => ("PUT", "/tasks".to_string()),
+            Command::Delete { id } => ("DELETE", format!("/tasks/{}", id)),
+            Command::Done { id } => ("POST", format!("/tasks/{}/done", id)),
+            Command::Undo { id } => ("POST", format!("/tasks/{}/undo", id)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerEnvelope<'a> {
+    Response {
+        version: u32,
+        request_id: String,
+        task: Option<Task>,
+    },
+    Error {
+        version: u32,
+        request_id: Option<String>,
+        error: String,
+    },
+    Event {
+        version: u32,
+        event: &'a TaskEvent,
+    },
+}
+
+impl ServerEnvelope<'_> {
+    fn error(request_id: Option<String>, error: impl ToString) -> Self {
+        ServerEnvelope::Error {
+            version: PROTOCOL_VERSION,
+            request_id,
+            error: error.to_string(),
+        }
+    }
+
+    fn to_message(&self) -> Message {
+        Message::Text(serde_json::to_string(self).unwrap().into())
+    }
+}
+
+/// Everything a connection needs, cloned out of Rocket's managed state on liftoff.
+#[derive(Clone)]
+struct Shared {
+    db_pool: SqlitePool,
+    task_events: TaskEvents,
+    cipher: Option<DetailsCipher>,
+    workspace_config: WorkspaceConfig,
+    schema: TaskSchema,
+    allowed_origins: AllowedOrigins,
+    /// The port Rocket serves its pages on.
+    http_port: u16,
+    rate_limits: Option<RateLimits>,
+}
+
+/// Runs the commands through the same model methods (and publishes the same events) as the REST
+/// routes.
+async fn execute(
+    workspace_db: &WorkspaceDb<'_>,
+    task_events: &TaskEvents,
+    command: Command,
+) -> Result<Option<Task>, AppError> {
+    let workspace_id = workspace_db.workspace.id;
+
+    match command {
+        Command::Insert(insert_task) => {
+            insert_task.validate()?;
+            let task = insert_task.insert(workspace_db).await?;
+            task_events.publish(workspace_id, TaskEventKind::Created, task.clone());
+
+            Ok(Some(task))
+        }
+        Command::Update(update_task) => {
+            update_task.validate()?;
+            if update_task.update(workspace_db, None).await? == 0 {
+                return Err(AppError::IdNotFound(update_task.id));
+            }
+
+            let task = Task::find_by_id(workspace_db, update_task.id).await?;
+            if let Some(task) = &task {
+                task_events.publish(workspace_id, TaskEventKind::Updated, task.clone());
+            }
+
+            Ok(task)
+        }
+        Command::Delete { id } => {
+            let task = Task::find_by_id(workspace_db, id)
+                .await?
+                .ok_or(AppError::IdNotFound(id))?;
+
+            Task::delete(workspace_db, id, None).await?;
+            task_events.publish(workspace_id, TaskEventKind::Deleted, task.clone());
+
+            Ok(Some(task))
+        }
+        Command::Done { id } => {
+            let created_id = Task::done(workspace_db, id, None).await?;
+            let task = Task::find_by_id(workspace_db, id)
+                .await?
+                .ok_or(AppError::IdNotFound(id))?;
+
+            if created_id != 0 {
+                task_events.publish(workspace_id, TaskEventKind::Done, task.clone());
+            }
+
+            Ok(Some(task))
+        }
+        Command::Undo { id } => {
+            let num_modified = Task::undo(workspace_db, id, None).await?;
+            let task = Task::find_by_id(workspace_db, id)
+                .await?
+                .ok_or(AppError::IdNotFound(id))?;
+
+            if num_modified != 0 {
+                task_events.publish(workspace_id, TaskEventKind::Undone, task.clone());
+            }
+
+            Ok(Some(task))
+        }
+    }
+}
+
+async fn reply(
+    workspace_db: &WorkspaceDb<'_>,
+    task_events: &TaskEvents,
+    rate_limits: Option<&RateLimits>,
+    text: &str,
+) -> Message {
+    let value: serde_json::Value = match serde_json::from_str(text) {
+        Ok(value) => value,
+        Err(fail) => return ServerEnvelope::error(None, fail).to_message(),
+    };
+
+    // NOTE(alex): Grabbed before decoding, so a malformed command still gets its `request_id` back.
+    let request_id = value
+        .get("request_id")
+        .and_then(|request_id| request_id.as_str())
+        .map(|request_id| request_id.to_string());
+
+    let envelope: ClientEnvelope = match serde_json::from_value(value) {
+        Ok(envelope) => envelope,
+        Err(fail) => return ServerEnvelope::error(request_id, fail).to_message(),
+    };
+
+    if envelope.version != PROTOCOL_VERSION {
+        let error = format!("Unsupported protocol version `{}`!", envelope.version);
+        return ServerEnvelope::error(Some(envelope.request_id), error).to_message();
+    }
+
+    // NOTE(alex): Commands take from the same buckets as the routes doing the same, this listener
+    // never goes through the `RateLimiter` fairing.
+    if let Some(rate_limits) = rate_limits {
+        let (method, path) = envelope.command.route();
+        let client = rate_limit::workspace_key(workspace_db.workspace.id);
+
+        if let Some(retry_after) = rate_limits.retry_after(method, &path, client) {
+            let error = AppError::RateLimited(retry_after);
+            return ServerEnvelope::error(Some(envelope.request_id), error).to_message();
+        }
+    }
+
+    match execute(workspace_db, task_events, envelope.command).await {
+        Ok(task) => ServerEnvelope::Response {
+            version: PROTOCOL_VERSION,
+            request_id: envelope.request_id,
+            task,
+        }
+        .to_message(),
+        Err(fail) => ServerEnvelope::error(Some(envelope.request_id), fail).to_message(),
+    }
+}
+
+/// Picks the workspace like `WorkspaceDb` does for HTTP requests, but from the handshake, with
+/// a `?token=` query fallback as browsers can't set headers on a websocket, then the cookie of a
+/// signed in browser (GraphiQL), which it sends to every port of the host.
+fn handshake_credentials(
+    request: &Request,
+    workspace_config: &WorkspaceConfig,
+) -> (Option<String>, Option<String>) {
+    let header = |name: &str| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+
+    let token = header("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.to_string())
+        .or_else(|| {
+            request
+                .uri()
+                .query()?
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("token=").map(|token| token.to_string()))
+        })
+        .or_else(|| {
+            header("Cookie")?.split(';').find_map(|pair| {
+                let (name, value) = pair.trim().split_once('=')?;
+                (name == TOKEN_COOKIE).then(|| value.to_string())
+            })
+        });
+
+    let slug = request
+        .uri()
+        .path()
+        .strip_prefix(PATH_PREFIX)
+        .and_then(|rest| rest.split('/').next())
+        .filter(|slug| !slug.is_empty())
+        .map(|slug| slug.to_string())
+        .or_else(|| workspace::subdomain(header("Host")?, workspace_config));
+
+    (token, slug)
+}
+
+/// Browsers send the `Origin` of the page opening the websocket, and don't apply CORS to it, so
+/// the handshake is refused unless `[global.cors]` allows that origin, or it's one of the pages
+/// of Rocket itself (same host, `http_port`). Other clients don't send one.
+///
+/// NOTE(alex): This is also what keeps other sites from riding on the cookie.
+fn allows_origin(request: &Request, allowed_origins: &AllowedOrigins, http_port: u16) -> bool {
+    let origin = match request.headers().get("Origin") {
+        Some(origin) => origin.to_str().unwrap_or_default(),
+        None => return true,
+    };
+
+    let own_origin = request
+        .headers()
+        .get("Host")
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| host.split(':').next())
+        .map(|host| {
+            ["http", "https"]
+                .iter()
+                .any(|scheme| origin == format!("{}://{}:{}", scheme, host, http_port))
+        })
+        .unwrap_or(false);
+
+    own_origin || allowed_origins.allows(origin)
+}
+
+/// Connections to `/graphql` (or `/w/{slug}/graphql`) speak GraphQL over websocket instead, the
+/// protocol is picked from `Sec-WebSocket-Protocol` (`graphql-transport-ws` when missing).
+fn graphql_protocol(request: &Request) -> Option<(Protocols, bool)> {
+    if !request.uri().path().ends_with("/graphql") {
+        return None;
+    }
+
+    let requested = request
+        .headers()
+        .get("Sec-WebSocket-Protocol")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| {
+            value
+                .split(',')
+                .find_map(|protocol| protocol.trim().parse::<Protocols>().ok())
+        });
+
+    match requested {
+        Some(protocol) => Some((protocol, true)),
+        None => Some((Protocols::GraphQLWS, false)),
+    }
+}
+
+/// Runs `async-graphql`'s protocol handler over the connection, until either side closes it.
+async fn serve_graphql(
+    ws: WebSocketStream<TcpStream>,
+    context: GraphQLContext,
+    schema: TaskSchema,
+    protocol: Protocols,
+) -> Result<(), AppError> {
+    let (mut sink, stream) = ws.split();
+    let stream = stream
+        .take_while(|message| future::ready(message.is_ok()))
+        .filter_map(|message| {
+            future::ready(match message {
+                Ok(Message::Text(text)) => Some(text.to_string()),
+                _ => None,
+            })
+        });
+
+    let mut data = Data::default();
+    data.insert(context);
+
+    let mut connection = GraphQLWebSocket::new(schema, stream, protocol).connection_data(data);
+    while let Some(message) = connection.next().await {
+        let message = match message {
+            WsMessage::Text(text) => Message::Text(text.into()),
+            WsMessage::Close(code, reason) => Message::Close(Some(CloseFrame {
+                code: code.into(),
+                reason: reason.into(),
+            })),
+        };
+
+        if sink.send(message).await.is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+async fn serve(stream: TcpStream, shared: Shared) -> Result<(), AppError> {
+    let mut credentials = (None, None);
+    let mut graphql = None;
+    let mut ws = tokio_tungstenite::accept_hdr_async(stream, |request: &Request, response| {
+        let mut response: Response = response;
+        if !allows_origin(request, &shared.allowed_origins, shared.http_port) {
+            let mut forbidden = ErrorResponse::new(Some("Origin not allowed!".to_string()));
+            *forbidden.status_mut() = StatusCode::FORBIDDEN;
+            return Err(forbidden);
+        }
+
+        credentials = handshake_credentials(request, &shared.workspace_config);
+        graphql = graphql_protocol(request);
+
+        if let Some((protocol, true)) = graphql {
+            response.headers_mut().insert(
+                "Sec-WebSocket-Protocol",
+                HeaderValue::from_static(protocol.sec_websocket_protocol()),
+            );
+        }
+
+        Ok(response)
+    })
+    .await
+    .map_err(|_| AppError::Internal)?;
+
+    let (token, slug) = credentials;
+    let workspace = match Workspace::resolve(&shared.db_pool, token.as_deref(), slug).await {
+        Ok(workspace) => workspace,
+        Err(fail) => {
+            let _ = ws
+                .send(ServerEnvelope::error(None, &fail).to_message())
+                .await;
+            let _ = ws.close(None).await;
+            return Err(fail);
+        }
+    };
+
+    if let Some((protocol, _)) = graphql {
+        let context = GraphQLContext::new(
+            workspace,
+            shared.db_pool.clone(),
+            shared.cipher.clone(),
+            shared.task_events.clone(),
+        );
+        return serve_graphql(ws, context, shared.schema.clone(), protocol).await;
+    }
+
+    let workspace_id = workspace.id;
+    let workspace_db = WorkspaceDb::new(workspace, &shared.db_pool, shared.cipher.as_ref());
+    let (_, _, mut receiver) = shared.task_events.subscribe(workspace_id, None);
+
+    loop {
+        let outgoing = select! {
+            incoming = ws.next() => match incoming {
+                Some(Ok(Message::Text(text))) => {
+                    let rate_limits = shared.rate_limits.as_ref();
+                    reply(&workspace_db, &shared.task_events, rate_limits, &text).await
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            event = receiver.recv() => match event {
+                Ok(event) if event.workspace_id == workspace_id => ServerEnvelope::Event {
+                    version: PROTOCOL_VERSION,
+                    event: &event,
+                }
+                .to_message(),
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            },
+        };
+
+        if ws.send(outgoing).await.is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Websocket server for bidirectional task sync (and GraphQL subscriptions), listens on
+/// `websocket_port` next to Rocket.
+///
+/// NOTE(alex): Rocket (0.5.0-rc.1) can't upgrade connections, so this runs its own listener in
+/// the same process, sharing the pool, the event feed and the model layer.
+pub(crate) struct WebSocket;
+
+#[rocket::async_trait]
+impl Fairing for WebSocket {
+    fn info(&self) -> Info {
+        Info {
+            name: "WebSocket",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let port = match rocket.figment().extract_inner::<u16>("websocket_port") {
+            Ok(port) => port,
+            Err(_) => return,
+        };
+
+        let shared = match (
+            rocket.state::<SqlitePool>(),
+            rocket.state::<TaskEvents>(),
+            rocket.state::<WorkspaceConfig>(),
+            rocket.state::<TaskSchema>(),
+        ) {
+            (Some(db_pool), Some(task_events), Some(workspace_config), Some(schema)) => Shared {
+                db_pool: db_pool.clone(),
+                task_events: task_events.clone(),
+                cipher: rocket.state::<DetailsCipher>().cloned(),
+                workspace_config: workspace_config.clone(),
+                schema: schema.clone(),
+                allowed_origins: Cors::allowed_origins(rocket),
+                http_port: rocket.config().port,
+                rate_limits: rocket.state::<RateLimits>().cloned(),
+            },
+            _ => {
+                error!(
+                    "WebSocket requires the pool, task events, workspaces and GraphQL to be managed!"
+                );
+                return;
+            }
+        };
+
+        let address = SocketAddr::new(rocket.config().address, port);
+        let listener = match TcpListener::bind(address).await {
+            Ok(listener) => listener,
+            Err(fail) => {
+                error!("WebSocket failed to bind `{}`: {}", address, fail);
+                return;
+            }
+        };
+
+        info!("WebSocket listening on ws://{}", address);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let shared = shared.clone();
+                tokio::spawn(async move {
+                    if let Err(fail) = serve(stream, shared).await {
+                        warn!("WebSocket connection closed: {}", fail);
+                    }
+                });
+            }
+        });
+    }
+}
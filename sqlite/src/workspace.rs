@@ -0,0 +1,488 @@
+use rocket::futures::stream::BoxStream;
+use rocket::{
+    fairing::{self, Fairing, Info, Kind},
+    http::{uri::Origin, Status},
+    request::{FromRequest, Outcome},
+    serde::{Deserialize, Serialize},
+    Build, Data, Request, Rocket, State,
+};
+use sqlx::{
+    query::{Query, QueryAs},
+    sqlite::{SqliteArguments, SqliteQueryResult, SqliteRow},
+    Encode, FromRow, Sqlite, SqlitePool, Transaction, Type,
+};
+use utoipa::ToSchema;
+
+use crate::{encryption::DetailsCipher, errors::AppError};
+
+const FIND_BY_SLUG: &'static str = include_str!("./../queries/find_workspace_by_slug.sql");
+const FIND_BY_TOKEN: &'static str = include_str!("./../queries/find_workspace_by_token.sql");
+const FIND_BY_FEED_TOKEN: &'static str =
+    include_str!("./../queries/find_workspace_by_feed_token.sql");
+const FIND_TOKEN: &'static str = include_str!("./../queries/find_workspace_token.sql");
+const FIND_FEED_TOKEN: Scoped = Scoped::new(include_str!("./../queries/find_feed_token.sql"));
+const ROTATE_FEED_TOKEN: Scoped = Scoped::new(include_str!("./../queries/rotate_feed_token.sql"));
+
+/// Prefix used to select a workspace by path, `/w/<slug>/tasks` is served by `/tasks`.
+pub(crate) const PATH_PREFIX: &'static str = "/w/";
+
+/// Cookie holding the API token of a browser signed in to the UI (`ui.rs`), sent like the
+/// `Authorization` header would be.
+pub(crate) const TOKEN_COOKIE: &'static str = "workspace_token";
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+pub(crate) struct Workspace {
+    pub(crate) id: i64,
+    pub(crate) slug: String,
+    pub(crate) max_tasks: i64,
+}
+
+/// Slug taken from the `/w/<slug>` path prefix, cached in the request by [`Workspaces`].
+#[derive(Debug, Default)]
+struct PrefixSlug(Option<String>);
+
+/// Where calendar apps subscribe to the tasks of a workspace, `path` needs no other credentials.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub(crate) struct CalendarFeed {
+    pub(crate) token: String,
+    /// `/tasks.ics?token={token}`
+    pub(crate) path: String,
+}
+
+impl CalendarFeed {
+    fn new(token: String) -> Self {
+        Self {
+            path: format!("/tasks.ics?token={}", token),
+            token,
+        }
+    }
+}
+
+/// Domain that workspace subdomains hang from, `team.localhost` resolves to the `team` workspace
+/// when `workspace_domain = "localhost"` is set in `Rocket.toml`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub(crate) struct WorkspaceConfig {
+    workspace_domain: Option<String>,
+}
+
+impl Workspace {
+    pub(crate) async fn find_by_slug(
+        db_pool: &SqlitePool,
+        slug: &str,
+    ) -> Result<Option<Self>, AppError> {
+        let result = sqlx::query_as(FIND_BY_SLUG)
+            .bind(slug)
+            .fetch_optional(db_pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    pub(crate) async fn find_by_token(
+        db_pool: &SqlitePool,
+        token: &str,
+    ) -> Result<Option<Self>, AppError> {
+        let result = sqlx::query_as(FIND_BY_TOKEN)
+            .bind(token)
+            .fetch_optional(db_pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    /// NOTE(alex): Only the calendar feed is opened by `feed_token`, it's never accepted as the
+    /// bearer token.
+    pub(crate) async fn find_by_feed_token(
+        db_pool: &SqlitePool,
+        feed_token: &str,
+    ) -> Result<Option<Self>, AppError> {
+        let result = sqlx::query_as(FIND_BY_FEED_TOKEN)
+            .bind(feed_token)
+            .fetch_optional(db_pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    /// The API token, for the `token` command of `cli`, routes never hand it out.
+    pub(crate) async fn token(&self, db_pool: &SqlitePool) -> Result<String, AppError> {
+        let (token,): (String,) = sqlx::query_as(FIND_TOKEN)
+            .bind(self.id)
+            .fetch_one(db_pool)
+            .await?;
+
+        Ok(token)
+    }
+
+    /// Resolves the workspace from the `Authorization: Bearer <token>` header (or the
+    /// `workspace_token` cookie of a signed in browser), checked against the `/w/<slug>` path
+    /// prefix, or the subdomain, when there's one.
+    ///
+    /// NOTE(alex): The cookie makes browsers send the token on their own, the `Csrf` fairing is
+    /// what keeps other sites from using that.
+    async fn from_request(req: &Request<'_>, db_pool: &SqlitePool) -> Result<Self, AppError> {
+        let token = match req.headers().get_one("Authorization") {
+            Some(header) => header
+                .strip_prefix("Bearer ")
+                .map(|token| token.to_string()),
+            None => req
+                .cookies()
+                .get(TOKEN_COOKIE)
+                .map(|crumb| crumb.value().to_string()),
+        };
+
+        Workspace::resolve(db_pool, token.as_deref(), selected_slug(req)).await
+    }
+
+    /// The token is what authorizes, the slug only selects the workspace, so when both are
+    /// present they must point to the same one.
+    ///
+    /// NOTE(alex): A slug alone is never enough, it's in every URL (and subdomain) of the
+    /// workspace, anyone who saw one of them would get in.
+    pub(crate) async fn resolve(
+        db_pool: &SqlitePool,
+        token: Option<&str>,
+        slug: Option<String>,
+    ) -> Result<Self, AppError> {
+        let token = token.ok_or(AppError::InvalidToken)?;
+        let workspace = Workspace::find_by_token(db_pool, token)
+            .await?
+            .ok_or(AppError::InvalidToken)?;
+
+        match slug {
+            Some(slug) if slug != workspace.slug => Err(AppError::WorkspaceMismatch(slug)),
+            _ => Ok(workspace),
+        }
+    }
+}
+
+/// The slug of the `/w/<slug>` path prefix, or of the subdomain.
+pub(crate) fn selected_slug(req: &Request<'_>) -> Option<String> {
+    req.local_cache(|| PrefixSlug::default())
+        .0
+        .clone()
+        .or_else(|| {
+            let config = req.rocket().state::<WorkspaceConfig>()?;
+            subdomain(req.headers().get_one("Host")?, config)
+        })
+}
+
+/// `/w/<slug>` when the request came through the path prefix, empty otherwise, for links that
+/// must stay in the same workspace.
+pub(crate) fn path_prefix(req: &Request<'_>) -> String {
+    match &req.local_cache(|| PrefixSlug::default()).0 {
+        Some(slug) => format!("{}{}", PATH_PREFIX, slug),
+        None => String::new(),
+    }
+}
+
+pub(crate) fn subdomain(host: &str, config: &WorkspaceConfig) -> Option<String> {
+    let base = config.workspace_domain.as_ref()?;
+    let host = host.split(':').next()?;
+
+    host.strip_suffix(base.as_str())
+        .and_then(|rest| rest.strip_suffix('.'))
+        .filter(|slug| !slug.is_empty() && !slug.contains('.'))
+        .map(|slug| slug.to_string())
+}
+
+/// SQL that only reads or writes the rows of the workspace bound as `$1`: it filters by
+/// `workspace_id = $1` (or `Workspace.id = $1`), or inserts `$1` as the `workspace_id`.
+///
+/// NOTE(alex): `Scoped::new` runs when the constant is compiled, a query that forgets the filter
+/// doesn't build. Only [`WorkspaceTx`] runs them.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Scoped(&'static str);
+
+impl Scoped {
+    pub(crate) const fn new(sql: &'static str) -> Self {
+        if !is_scoped(sql) {
+            panic!("query is not scoped by `workspace_id = $1`");
+        }
+
+        Scoped(sql)
+    }
+}
+
+const fn is_scoped(sql: &str) -> bool {
+    contains(sql, "workspace_id = $1")
+        || contains(sql, "Workspace.id = $1")
+        || (contains(sql, "(workspace_id,")
+            && (contains(sql, "values ($1,") || contains(sql, "select $1,")))
+}
+
+const fn contains(haystack: &str, needle: &str) -> bool {
+    let (haystack, needle) = (haystack.as_bytes(), needle.as_bytes());
+    let mut start = 0;
+
+    while start + needle.len() <= haystack.len() {
+        let mut index = 0;
+        while index < needle.len() && haystack[start + index] == needle[index] {
+            index += 1;
+        }
+
+        if index == needle.len() {
+            return true;
+        }
+        start += 1;
+    }
+
+    false
+}
+
+/// Database handle scoped to a single [`Workspace`].
+///
+/// NOTE(alex): Route handlers never see the `SqlitePool` directly, they get this guard instead,
+/// and it only hands out [`WorkspaceTx`]s, which only run [`Scoped`] queries, with the workspace
+/// id already bound as `$1`.
+#[derive(Clone)]
+pub(crate) struct WorkspaceDb<'r> {
+    pub(crate) workspace: Workspace,
+    db_pool: &'r SqlitePool,
+    cipher: Option<&'r DetailsCipher>,
+}
+
+impl<'r> WorkspaceDb<'r> {
+    pub(crate) fn new(
+        workspace: Workspace,
+        db_pool: &'r SqlitePool,
+        cipher: Option<&'r DetailsCipher>,
+    ) -> Self {
+        Self {
+            workspace,
+            db_pool,
+            cipher,
+        }
+    }
+
+    /// Present only when `details_encryption` is enabled.
+    pub(crate) fn cipher(&self) -> Option<&'r DetailsCipher> {
+        self.cipher
+    }
+
+    /// NOTE(alex): Reads go through a transaction too, dropping it without a commit rolls it back.
+    pub(crate) async fn begin(&self) -> Result<WorkspaceTx, AppError> {
+        Ok(WorkspaceTx {
+            workspace_id: self.workspace.id,
+            tx: self.db_pool.begin().await?,
+        })
+    }
+
+    pub(crate) async fn calendar_feed(&self) -> Result<CalendarFeed, AppError> {
+        let mut tx = self.begin().await?;
+        let (feed_token,): (String,) = tx.query_as(FIND_FEED_TOKEN).fetch_one().await?;
+
+        Ok(CalendarFeed::new(feed_token))
+    }
+
+    /// A new `feed_token`, the old feed URL stops working right away.
+    pub(crate) async fn rotate_feed_token(&self) -> Result<CalendarFeed, AppError> {
+        let mut tx = self.begin().await?;
+        tx.query(ROTATE_FEED_TOKEN).execute().await?;
+        let (feed_token,): (String,) = tx.query_as(FIND_FEED_TOKEN).fetch_one().await?;
+        tx.commit().await?;
+
+        Ok(CalendarFeed::new(feed_token))
+    }
+}
+
+/// A transaction of a [`WorkspaceDb`], the only thing that runs queries.
+pub(crate) struct WorkspaceTx {
+    workspace_id: i64,
+    tx: Transaction<'static, Sqlite>,
+}
+
+impl WorkspaceTx {
+    pub(crate) fn query<'t, 'q>(&'t mut self, sql: Scoped) -> ScopedQuery<'t, 'q> {
+        ScopedQuery {
+            query: sqlx::query(sql.0).bind(self.workspace_id),
+            tx: &mut self.tx,
+        }
+    }
+
+    pub(crate) fn query_as<'t, 'q, T>(&'t mut self, sql: Scoped) -> ScopedQueryAs<'t, 'q, T>
+    where
+        T: for<'row> FromRow<'row, SqliteRow>,
+    {
+        ScopedQueryAs {
+            query: sqlx::query_as(sql.0).bind(self.workspace_id),
+            tx: &mut self.tx,
+        }
+    }
+
+    pub(crate) async fn commit(self) -> Result<(), AppError> {
+        Ok(self.tx.commit().await?)
+    }
+
+    pub(crate) async fn rollback(self) -> Result<(), AppError> {
+        Ok(self.tx.rollback().await?)
+    }
+
+    /// Starts a savepoint, undone by [`WorkspaceTx::rollback_to_savepoint`], and ended by
+    /// [`WorkspaceTx::release_savepoint`] either way.
+    pub(crate) async fn savepoint(&mut self) -> Result<(), AppError> {
+        sqlx::query("savepoint workspace_tx")
+            .execute(&mut self.tx)
+            .await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn rollback_to_savepoint(&mut self) -> Result<(), AppError> {
+        sqlx::query("rollback to workspace_tx")
+            .execute(&mut self.tx)
+            .await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn release_savepoint(&mut self) -> Result<(), AppError> {
+        sqlx::query("release workspace_tx")
+            .execute(&mut self.tx)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// A [`Scoped`] query of a [`WorkspaceTx`], with the workspace id bound as `$1`.
+pub(crate) struct ScopedQuery<'t, 'q> {
+    tx: &'t mut Transaction<'static, Sqlite>,
+    query: Query<'q, Sqlite, SqliteArguments<'q>>,
+}
+
+impl<'t, 'q> ScopedQuery<'t, 'q> {
+    pub(crate) fn bind<T>(mut self, value: T) -> Self
+    where
+        T: 'q + Send + Encode<'q, Sqlite> + Type<Sqlite>,
+    {
+        self.query = self.query.bind(value);
+        self
+    }
+
+    pub(crate) async fn execute(self) -> Result<SqliteQueryResult, sqlx::Error> {
+        self.query.execute(&mut *self.tx).await
+    }
+}
+
+/// [`ScopedQuery`] of rows read as `T`.
+pub(crate) struct ScopedQueryAs<'t, 'q, T> {
+    tx: &'t mut Transaction<'static, Sqlite>,
+    query: QueryAs<'q, Sqlite, T, SqliteArguments<'q>>,
+}
+
+impl<'t, 'q, T> ScopedQueryAs<'t, 'q, T>
+where
+    T: Send + Unpin + for<'row> FromRow<'row, SqliteRow>,
+{
+    pub(crate) fn bind<V>(mut self, value: V) -> Self
+    where
+        V: 'q + Send + Encode<'q, Sqlite> + Type<Sqlite>,
+    {
+        self.query = self.query.bind(value);
+        self
+    }
+
+    pub(crate) async fn fetch_one(self) -> Result<T, sqlx::Error> {
+        self.query.fetch_one(&mut *self.tx).await
+    }
+
+    pub(crate) async fn fetch_optional(self) -> Result<Option<T>, sqlx::Error> {
+        self.query.fetch_optional(&mut *self.tx).await
+    }
+
+    pub(crate) async fn fetch_all(self) -> Result<Vec<T>, sqlx::Error> {
+        self.query.fetch_all(&mut *self.tx).await
+    }
+
+    /// One row at a time, for as long as the transaction is borrowed.
+    pub(crate) fn fetch(self) -> BoxStream<'t, Result<T, sqlx::Error>>
+    where
+        'q: 't,
+        T: 't,
+    {
+        self.query.fetch(&mut *self.tx)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WorkspaceDb<'r> {
+    type Error = AppError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let db_pool = match req.guard::<&State<SqlitePool>>().await {
+            Outcome::Success(db_pool) => db_pool.inner(),
+            _ => return Outcome::Failure((Status::InternalServerError, AppError::Internal)),
+        };
+
+        let cipher = req.rocket().state::<DetailsCipher>();
+
+        match Workspace::from_request(req, db_pool).await {
+            Ok(workspace) => Outcome::Success(WorkspaceDb::new(workspace, db_pool, cipher)),
+            Err(fail @ AppError::WorkspaceNotFound) => Outcome::Failure((Status::NotFound, fail)),
+            Err(fail @ AppError::InvalidToken) => Outcome::Failure((Status::Unauthorized, fail)),
+            Err(fail @ AppError::WorkspaceMismatch(_)) => {
+                Outcome::Failure((Status::Forbidden, fail))
+            }
+            Err(fail) => Outcome::Failure((Status::InternalServerError, fail)),
+        }
+    }
+}
+
+/// Requests that sent the API token in the `Authorization` header, for the routes that hand out
+/// other credentials, the cookie of a signed in browser isn't enough. `WorkspaceDb` still checks
+/// the token itself.
+pub(crate) struct BearerToken;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BearerToken {
+    type Error = AppError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match req.headers().get_one("Authorization") {
+            Some(header) if header.starts_with("Bearer ") => Outcome::Success(BearerToken),
+            _ => Outcome::Failure((Status::Unauthorized, AppError::InvalidToken)),
+        }
+    }
+}
+
+/// Loads the workspace config, and strips the `/w/<slug>` prefix from incoming requests so the
+/// routes can be mounted once at `/`.
+pub(crate) struct Workspaces;
+
+#[rocket::async_trait]
+impl Fairing for Workspaces {
+    fn info(&self) -> Info {
+        Info {
+            name: "Workspaces",
+            kind: Kind::Ignite | Kind::Request,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let config: WorkspaceConfig = rocket.figment().extract().unwrap_or_default();
+
+        Ok(rocket.manage(config))
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _data: &mut Data<'_>) {
+        let path = req.uri().path().as_str().to_string();
+        let rest = match path.strip_prefix(PATH_PREFIX) {
+            Some(rest) => rest,
+            None => return,
+        };
+
+        let (slug, rest) = rest.split_once('/').unwrap_or((rest, ""));
+        let query = req
+            .uri()
+            .query()
+            .map(|query| format!("?{}", query.as_str()))
+            .unwrap_or_default();
+
+        if let Ok(uri) = Origin::parse_owned(format!("/{}{}", rest, query)) {
+            let slug = slug.to_string();
+            req.local_cache(|| PrefixSlug(Some(slug)));
+            req.set_uri(uri);
+        }
+    }
+}